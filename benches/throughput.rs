@@ -190,8 +190,8 @@ pub fn connections(c: &mut Criterion) {
             tcp::run_client(&mut s, 1, Mode::ReadWrite).await
         })
     });
-    // TODO(https://github.com/istio/ztunnel/issues/15): when we have pooling, split this into "new hbone connection"
-    // and "new connection on existing HBONE connection"
+    // The HBONE connection established during setup is pooled, so this measures a new stream on an
+    // existing HBONE connection rather than a full handshake.
     c.bench_function("hbone", |b| {
         b.to_async(&rt).iter(|| async {
             let e = env.lock().await;
@@ -219,8 +219,7 @@ pub fn metrics(c: &mut Criterion) {
                 derived_source: None,
                 destination: None,
                 destination_service: None,
-                destination_service_name: None,
-                destination_service_namespace: None,
+                request_protocol: Default::default(),
                 connection_security_policy: Default::default(),
             })
         })
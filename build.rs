@@ -33,7 +33,11 @@ fn main() -> Result<(), anyhow::Error> {
     let config = {
         let mut c = prost_build::Config::new();
         c.disable_comments(Some("."));
-        c.bytes([".istio.workload.Workload", ".istio.security.Address"]);
+        c.bytes([
+            ".istio.workload.Workload",
+            ".istio.workload.NetworkAddress",
+            ".istio.security.Address",
+        ]);
         c
     };
     tonic_build::configure()
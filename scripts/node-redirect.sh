@@ -55,6 +55,8 @@ cat <<EOF | iptables-restore -w
 -A ztunnel-PREROUTING ! -s ${ZTUNNEL_IP}/32 -i ${ZTUNNEL_INTERFACE} -j MARK --set-xmark 0x210/0x210
 -A ztunnel-PREROUTING -m mark --mark 0x200/0x200 -j RETURN
 -A ztunnel-PREROUTING -i ${ZTUNNEL_INTERFACE} -j MARK --set-xmark 0x220/0x220
+-A ztunnel-PREROUTING -p udp -m set --match-set ztunnel-pods-ips src -j MARK --set-xmark 0x100/0x100
+-A ztunnel-PREROUTING -m mark --mark 0x100/0x100 -j RETURN
 -A ztunnel-PREROUTING -p udp -j MARK --set-xmark 0x220/0x220
 -A ztunnel-PREROUTING -m mark --mark 0x200/0x200 -j RETURN
 -A ztunnel-PREROUTING -p tcp -m set --match-set ztunnel-pods-ips src -j MARK --set-xmark 0x100/0x100
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 use std::{net::SocketAddr, time::Duration};
@@ -36,14 +36,18 @@ use tracing::error;
 use crate::config::Config;
 use crate::hyper_util::{empty_response, plaintext_response, Server};
 use crate::identity::SecretManager;
+use crate::proxy::{ConnectionFilter, ConnectionTracker};
+use crate::ratelimit::{RateLimitConfig, RateLimiter};
 use crate::tls::asn1_time_to_system_time;
 use crate::version::BuildInfo;
 use crate::workload::LocalConfig;
-use crate::workload::WorkloadInformation;
+use crate::workload::{WorkloadInformation, WorkloadMtls};
 use crate::{signal, telemetry};
 
 struct State {
     workload_info: WorkloadInformation,
+    rate_limiter: RateLimiter,
+    connections: ConnectionTracker,
     config: Config,
     shutdown_trigger: signal::ShutdownTrigger,
     cert_manager: Arc<SecretManager>,
@@ -57,6 +61,8 @@ pub struct Service {
 pub struct ConfigDump {
     #[serde(flatten)]
     workload_info: WorkloadInformation,
+    /// mtls_modes holds the mTLS modes in effect for each workload, keyed by uid.
+    mtls_modes: BTreeMap<String, WorkloadMtls>,
     static_config: LocalConfig,
     version: BuildInfo,
     config: Config,
@@ -84,6 +90,8 @@ impl Service {
     pub fn new(
         config: Config,
         workload_info: WorkloadInformation,
+        rate_limiter: RateLimiter,
+        connections: ConnectionTracker,
         shutdown_trigger: signal::ShutdownTrigger,
         drain_rx: Watch,
         cert_manager: Arc<SecretManager>,
@@ -96,6 +104,8 @@ impl Service {
             State {
                 config,
                 workload_info,
+                rate_limiter,
+                connections,
                 shutdown_trigger,
                 cert_manager,
             },
@@ -119,6 +129,7 @@ impl Service {
                 "/config_dump" => Ok(handle_config_dump(
                     ConfigDump {
                         workload_info: state.workload_info.clone(),
+                        mtls_modes: state.workload_info.mtls_modes(),
                         static_config: Default::default(),
                         version: BuildInfo::new(),
                         config: state.config.clone(),
@@ -128,6 +139,8 @@ impl Service {
                 )
                 .await),
                 "/logging" => Ok(handle_logging(req).await),
+                "/ratelimits" => Ok(handle_rate_limits(&state.rate_limiter, req).await),
+                "/connections" => Ok(handle_connections(&state.connections, req).await),
                 _ => Ok(empty_response(hyper::StatusCode::NOT_FOUND)),
             }
         })
@@ -247,6 +260,69 @@ async fn handle_config_dump(mut dump: ConfigDump, _req: Request<Body>) -> Respon
         .unwrap()
 }
 
+/// handle_rate_limits returns the current connection rate limits on GET, and replaces them with
+/// the JSON or YAML request body on POST.
+async fn handle_rate_limits(rate_limiter: &RateLimiter, req: Request<Body>) -> Response<Body> {
+    match *req.method() {
+        hyper::Method::GET => {}
+        hyper::Method::POST => {
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(b) => b,
+                Err(e) => {
+                    return plaintext_response(
+                        hyper::StatusCode::BAD_REQUEST,
+                        format!("failed to read body: {e}\n"),
+                    )
+                }
+            };
+            match serde_yaml::from_slice::<RateLimitConfig>(&body) {
+                Ok(config) => rate_limiter.set_config(config),
+                Err(e) => {
+                    return plaintext_response(
+                        hyper::StatusCode::BAD_REQUEST,
+                        format!("invalid rate limits: {e}\n"),
+                    )
+                }
+            }
+        }
+        _ => return empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED),
+    }
+    let vec = serde_json::to_vec(&rate_limiter.config()).unwrap();
+    Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(vec.into())
+        .unwrap()
+}
+
+/// handle_connections lists the connections currently being proxied. They may be filtered by the
+/// `namespace`, `workload` or `identity` of either end with query parameters.
+async fn handle_connections(connections: &ConnectionTracker, req: Request<Body>) -> Response<Body> {
+    if req.method() != hyper::Method::GET {
+        return empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let mut filter = ConnectionFilter::default();
+    for (k, v) in url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes()) {
+        match k.as_ref() {
+            "namespace" => filter.namespace = Some(v.into_owned()),
+            "workload" => filter.workload = Some(v.into_owned()),
+            "identity" => filter.identity = Some(v.into_owned()),
+            _ => {
+                return plaintext_response(
+                    hyper::StatusCode::BAD_REQUEST,
+                    format!("unknown filter {k}\n"),
+                )
+            }
+        }
+    }
+    let vec = serde_json::to_vec(&connections.dump(&filter)).unwrap();
+    Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(vec.into())
+        .unwrap()
+}
+
 //mirror envoy's behavior: https://www.envoyproxy.io/docs/envoy/latest/operations/admin#post--logging
 //NOTE: multiple query parameters is not supported, for example
 //curl -X POST http://127.0.0.1:15000/logging?"tap=debug&router=debug"
@@ -25,7 +25,7 @@ use tracing::{error, info, warn, Instrument};
 
 use crate::identity::SecretManager;
 use crate::metrics::Metrics;
-use crate::{admin, config, identity, proxy, readiness, signal, stats, workload};
+use crate::{admin, config, identity, proxy, ratelimit, readiness, signal, stats, workload};
 
 pub async fn build_with_cert(
     config: config::Config,
@@ -51,9 +51,14 @@ pub async fn build_with_cert(
     )
     .await?;
 
+    let rate_limiter = ratelimit::RateLimiter::new(&config);
+    let connections = proxy::ConnectionTracker::default();
+
     let admin_server = admin::Service::new(
         config.clone(),
         workload_manager.workloads(),
+        rate_limiter.clone(),
+        connections.clone(),
         shutdown.trigger(),
         drain_rx.clone(),
         cert_manager.clone(),
@@ -78,6 +83,8 @@ pub async fn build_with_cert(
         workload_manager.workloads(),
         cert_manager.clone(),
         metrics.clone(),
+        rate_limiter.clone(),
+        connections.clone(),
         drain_rx.clone(),
     )
     .await?;
@@ -86,6 +93,7 @@ pub async fn build_with_cert(
     // spawn all tasks that should run in the main thread
     admin_server.spawn();
     stats_server.spawn();
+    tokio::spawn(rate_limiter.run().in_current_span());
     tokio::spawn(
         async move {
             if let Err(e) = workload_manager.run().await {
@@ -126,6 +134,7 @@ pub async fn build_with_cert(
         admin_address,
         stats_address,
         proxy_addresses,
+        connections,
     })
 }
 
@@ -143,6 +152,7 @@ pub struct Bound {
     pub proxy_addresses: proxy::Addresses,
     pub readiness_address: SocketAddr,
     pub stats_address: SocketAddr,
+    pub connections: proxy::ConnectionTracker,
 
     pub shutdown: signal::Shutdown,
     config: config::Config,
@@ -160,8 +170,9 @@ impl Bound {
         match time::timeout(self.config.termination_grace_period, self.drain_tx.drain()).await {
             Ok(()) => info!("Shutdown completed gracefully"),
             Err(_) => warn!(
-                "Graceful shutdown did not complete in {:?}, terminating now",
-                self.config.termination_grace_period
+                "Graceful shutdown did not complete in {:?}, terminating now and force closing {} connections",
+                self.config.termination_grace_period,
+                self.connections.active(),
             ),
         }
         Ok(())
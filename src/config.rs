@@ -24,15 +24,22 @@ use anyhow::anyhow;
 use bytes::Bytes;
 use hyper::http::uri::InvalidUri;
 use hyper::Uri;
+use ipnet::IpNet;
 use tokio::time;
 
 use crate::identity;
+use crate::ratelimit::RateLimitConfig;
+use crate::socket::SocketOptions;
+use crate::workload::lb::LoadBalancerPolicy;
 
 const KUBERNETES_SERVICE_HOST: &str = "KUBERNETES_SERVICE_HOST";
 const NODE_NAME: &str = "NODE_NAME";
 const PROXY_MODE: &str = "PROXY_MODE";
 const INSTANCE_IP: &str = "INSTANCE_IP";
 const CLUSTER_ID: &str = "CLUSTER_ID";
+const NETWORK: &str = "NETWORK";
+const NETWORK_GATEWAYS: &str = "NETWORK_GATEWAYS";
+const NETWORK_GATEWAY_IDENTITY: &str = "NETWORK_GATEWAY_IDENTITY";
 const LOCAL_XDS_PATH: &str = "LOCAL_XDS_PATH";
 const XDS_ON_DEMAND: &str = "XDS_ON_DEMAND";
 const XDS_ADDRESS: &str = "XDS_ADDRESS";
@@ -42,6 +49,40 @@ const FAKE_CA: &str = "FAKE_CA";
 const ZTUNNEL_WORKER_THREADS: &str = "ZTUNNEL_WORKER_THREADS";
 const ENABLE_ORIG_SRC: &str = "ENABLE_ORIG_SRC";
 const PROXY_CONFIG: &str = "PROXY_CONFIG";
+const POOL_MAX_STREAMS_PER_CONNECTION: &str = "POOL_MAX_STREAMS_PER_CONNECTION";
+const POOL_UNUSED_RELEASE_TIMEOUT: &str = "POOL_UNUSED_RELEASE_TIMEOUT";
+const LOAD_BALANCING_POLICY: &str = "LOAD_BALANCING_POLICY";
+const SERVICE_LOAD_BALANCING_POLICIES: &str = "SERVICE_LOAD_BALANCING_POLICIES";
+const CONNECT_RETRY_BUDGET: &str = "CONNECT_RETRY_BUDGET";
+const OUTLIER_CONSECUTIVE_FAILURES: &str = "OUTLIER_CONSECUTIVE_FAILURES";
+const OUTLIER_BASE_EJECTION_TIME: &str = "OUTLIER_BASE_EJECTION_TIME";
+const OUTLIER_MAX_EJECTION_TIME: &str = "OUTLIER_MAX_EJECTION_TIME";
+const OUTLIER_MAX_EJECTION_PERCENT: &str = "OUTLIER_MAX_EJECTION_PERCENT";
+const CONNECTION_IDLE_TIMEOUT: &str = "CONNECTION_IDLE_TIMEOUT";
+const MAX_CONNECTION_DURATION: &str = "MAX_CONNECTION_DURATION";
+const SERVICE_CONNECTION_IDLE_TIMEOUTS: &str = "SERVICE_CONNECTION_IDLE_TIMEOUTS";
+const SERVICE_MAX_CONNECTION_DURATIONS: &str = "SERVICE_MAX_CONNECTION_DURATIONS";
+const UDP_IDLE_TIMEOUT: &str = "UDP_IDLE_TIMEOUT";
+const CIRCUIT_BREAKER_MAX_CONNECTIONS: &str = "CIRCUIT_BREAKER_MAX_CONNECTIONS";
+const CIRCUIT_BREAKER_MAX_PENDING_CONNECTS: &str = "CIRCUIT_BREAKER_MAX_PENDING_CONNECTS";
+const CIRCUIT_BREAKER_MAX_HBONE_STREAMS: &str = "CIRCUIT_BREAKER_MAX_HBONE_STREAMS";
+const CONNECTION_RATE_LIMITS: &str = "CONNECTION_RATE_LIMITS";
+const TCP_KEEPALIVE_TIME: &str = "TCP_KEEPALIVE_TIME";
+const TCP_KEEPALIVE_INTERVAL: &str = "TCP_KEEPALIVE_INTERVAL";
+const TCP_KEEPALIVE_RETRIES: &str = "TCP_KEEPALIVE_RETRIES";
+const TCP_USER_TIMEOUT: &str = "TCP_USER_TIMEOUT";
+const SOCKET_RECV_BUFFER_SIZE: &str = "SOCKET_RECV_BUFFER_SIZE";
+const SOCKET_SEND_BUFFER_SIZE: &str = "SOCKET_SEND_BUFFER_SIZE";
+const SOCKS5_DNS_FALLBACK: &str = "SOCKS5_DNS_FALLBACK";
+const SOCKS5_CREDENTIALS: &str = "SOCKS5_CREDENTIALS";
+const SOCKS5_ALLOW_UNAUTHENTICATED: &str = "SOCKS5_ALLOW_UNAUTHENTICATED";
+const HTTP_CONNECT_ADDR: &str = "HTTP_CONNECT_ADDR";
+const OUTBOUND_UDP_ADDR: &str = "OUTBOUND_UDP_ADDR";
+const INBOUND_H3_ADDR: &str = "INBOUND_H3_ADDR";
+const PROXY_PROTOCOL_TRUSTED_CIDRS: &str = "PROXY_PROTOCOL_TRUSTED_CIDRS";
+
+/// HBONE_PORT is the port HBONE is served on, by ztunnel and by workloads that natively speak HBONE.
+pub const HBONE_PORT: u16 = 15008;
 
 const DEFAULT_WORKER_THREADS: u16 = 2;
 const DEFAULT_ADMIN_PORT: u16 = 15000;
@@ -49,6 +90,19 @@ const DEFAULT_READINESS_PORT: u16 = 15021;
 const DEFAULT_STATS_PORT: u16 = 15020;
 const DEFAULT_DRAIN_DURATION: Duration = Duration::from_secs(5);
 const DEFAULT_CLUSTER_ID: &str = "Kubernetes";
+const DEFAULT_NETWORK_GATEWAY_IDENTITY: &str =
+    "spiffe://cluster.local/ns/istio-system/sa/istio-eastwestgateway";
+const DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION: u16 = 100;
+const DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_CONNECT_RETRY_BUDGET: u8 = 2;
+const DEFAULT_OUTLIER_CONSECUTIVE_FAILURES: u32 = 5;
+const DEFAULT_OUTLIER_BASE_EJECTION_TIME: Duration = Duration::from_secs(30);
+const DEFAULT_OUTLIER_MAX_EJECTION_TIME: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_OUTLIER_MAX_EJECTION_PERCENT: u8 = 10;
+const DEFAULT_UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_TCP_KEEPALIVE_TIME: Duration = Duration::from_secs(180);
+const DEFAULT_TCP_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(180);
+const DEFAULT_TCP_KEEPALIVE_RETRIES: u32 = 9;
 
 const ISTIO_META_PREFIX: &str = "ISTIO_META_";
 
@@ -96,13 +150,91 @@ pub struct Config {
     pub connection_window_size: u32,
     pub frame_size: u32,
 
+    /// The maximum number of concurrent streams multiplexed onto a single pooled HBONE connection.
+    pub pool_max_streams_per_conn: u16,
+    /// How long a pooled HBONE connection with no active streams is kept open before being released.
+    pub pool_unused_release_timeout: Duration,
+
+    /// The load balancing policy used to pick an endpoint for connections to a service VIP.
+    pub lb_policy: LoadBalancerPolicy,
+    /// Overrides of lb_policy for specific services, keyed by VIP.
+    pub service_lb_policies: HashMap<IpAddr, LoadBalancerPolicy>,
+    /// The number of times a failed connection to a service VIP is retried on another endpoint.
+    pub connect_retry_budget: u8,
+
+    /// The number of consecutive failed connections after which an endpoint is ejected from load
+    /// balancing. Zero disables outlier detection.
+    pub outlier_consecutive_failures: u32,
+    /// How long an endpoint is ejected for the first time. This doubles with each later ejection.
+    pub outlier_base_ejection_time: Duration,
+    /// The longest an endpoint can be ejected for.
+    pub outlier_max_ejection_time: Duration,
+    /// The maximum percentage of a service's endpoints that can be ejected at once, rounded down.
+    /// Zero means no endpoints are ejected.
+    pub outlier_max_ejection_percent: u8,
+
+    /// How long a proxied connection may go without sending or receiving any data before it is
+    /// closed. None, the default, disables the idle timeout.
+    pub connection_idle_timeout: Option<Duration>,
+    /// How long a proxied connection may stay open, regardless of activity. None disables the limit.
+    pub max_connection_duration: Option<Duration>,
+    /// Overrides of connection_idle_timeout for specific services, keyed by VIP. Zero disables the
+    /// idle timeout for the service.
+    pub service_connection_idle_timeouts: HashMap<IpAddr, Duration>,
+    /// Overrides of max_connection_duration for specific services, keyed by VIP. Zero disables the
+    /// limit for the service.
+    pub service_max_connection_durations: HashMap<IpAddr, Duration>,
+    /// How long a UDP association may go without sending or receiving any datagrams before it is
+    /// closed. UDP has no connection teardown, so this is always enforced.
+    pub udp_idle_timeout: Duration,
+
+    /// The maximum number of concurrent connections to a single destination workload or service VIP.
+    /// Connections using the node local fast path are not counted. Zero means unlimited.
+    pub circuit_breaker_max_connections: u32,
+    /// The maximum number of concurrent connects in progress to a single destination workload or
+    /// service VIP. Zero means unlimited.
+    pub circuit_breaker_max_pending_connects: u32,
+    /// The maximum number of concurrent HBONE streams to a single destination workload or service
+    /// VIP. Zero means unlimited.
+    pub circuit_breaker_max_hbone_streams: u32,
+
+    /// The initial limits on the rate each source workload may open connections. These can be
+    /// changed at runtime through the admin server.
+    pub connection_rate_limits: RateLimitConfig,
+
+    /// Options applied to all downstream and upstream proxy sockets.
+    pub socket_options: SocketOptions,
+
+    /// If true, SOCKS5 and HTTP CONNECT hostnames that are not services or workloads in the mesh
+    /// are resolved with system DNS.
+    pub socks5_dns_fallback: bool,
+    /// YAML list of SOCKS5 username/password credentials, and the source workload each may connect
+    /// as.
+    #[serde(skip_serializing)]
+    pub socks5_credentials: Option<ConfigSource>,
+    /// If true, SOCKS5 clients may connect without authenticating, as the workload at their address.
+    /// Defaults to true only if no credentials are configured.
+    pub socks5_allow_unauthenticated: bool,
+
     pub socks5_addr: SocketAddr,
+    /// The address of the HTTP CONNECT forward proxy listener. None disables the listener.
+    pub http_connect_addr: Option<SocketAddr>,
     pub admin_addr: SocketAddr,
     pub stats_addr: SocketAddr,
     pub readiness_addr: SocketAddr,
     pub inbound_addr: SocketAddr,
     pub inbound_plaintext_addr: SocketAddr,
     pub outbound_addr: SocketAddr,
+    /// The address of the listener for outbound UDP captured with TPROXY. None disables the
+    /// listener, as capturing UDP requires TPROXY rules and CAP_NET_ADMIN.
+    pub outbound_udp_addr: Option<SocketAddr>,
+    /// The UDP address to serve HBONE over HTTP/3 on, which peers learn of from the Alt-Svc header
+    /// of our HTTP/2 responses. None disables HTTP/3, which requires the quic feature.
+    pub inbound_h3_addr: Option<SocketAddr>,
+    /// Peers that connect to the outbound and inbound plaintext listeners from these ranges must
+    /// send a PROXY protocol header, whose addresses replace those of the connection. Empty
+    /// disables the PROXY protocol.
+    pub proxy_protocol_trusted_cidrs: Vec<IpNet>,
 
     /// The name of the node this ztunnel is running as.
     pub local_node: Option<String>,
@@ -112,6 +244,13 @@ pub struct Config {
     pub local_ip: Option<IpAddr>,
     /// The Cluster ID of the cluster that his ztunnel belongs to
     pub cluster_id: String,
+    /// The network this ztunnel belongs to. Empty is the default network.
+    pub network: String,
+    /// The HBONE address of the east-west gateway for each other network. Workloads on a network
+    /// without a gateway are unreachable.
+    pub network_gateways: HashMap<String, SocketAddr>,
+    /// The identity expected of network gateways.
+    pub network_gateway_identity: identity::Identity,
 
     /// CA address to use. If fake_ca is set, this will be None.
     /// Note: we do not implicitly use None when set to "" since using the fake_ca is not secure.
@@ -162,6 +301,42 @@ impl From<InvalidUri> for Error {
     }
 }
 
+/// NetworkGateways parses a comma separated list of NETWORK=ADDRESS pairs, such as
+/// `network2=10.10.0.1:15008,network3=10.20.0.1:15008`.
+struct NetworkGateways(HashMap<String, SocketAddr>);
+
+impl FromStr for NetworkGateways {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (network, addr) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("expected NETWORK=ADDRESS, got {entry}"))?;
+                Ok((network.trim().to_string(), addr.trim().parse()?))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(NetworkGateways)
+    }
+}
+
+/// Cidrs parses a comma separated list of CIDRs, such as `10.0.0.0/8,fd00::/8`.
+struct Cidrs(Vec<IpNet>);
+
+impl FromStr for Cidrs {
+    type Err = ipnet::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| entry.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Cidrs)
+    }
+}
+
 /// GoDuration wraps a Duration to implement golang Duration parsing semantics
 struct GoDuration(Duration);
 
@@ -173,6 +348,33 @@ impl FromStr for GoDuration {
     }
 }
 
+/// ServiceOverrides parses a comma separated list of VIP=VALUE pairs, such as
+/// `10.96.0.10=ROUND_ROBIN,10.96.0.11=CONSISTENT_HASH`.
+struct ServiceOverrides<T>(HashMap<IpAddr, T>);
+
+impl<T: FromStr> FromStr for ServiceOverrides<T> {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (vip, value) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("expected VIP=VALUE, got {entry}"))?;
+                Ok((
+                    vip.trim().parse()?,
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| anyhow!("invalid value {value} for {vip}"))?,
+                ))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(ServiceOverrides)
+    }
+}
+
 fn parse<T: FromStr>(env: &str) -> Result<Option<T>, Error> {
     match std::env::var(env) {
         Ok(val) => val
@@ -247,11 +449,98 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         RootCert::Static(Bytes::from(ca_root_cert_provider))
     };
 
+    let socks5_credentials = parse::<PathBuf>(SOCKS5_CREDENTIALS)?.map(ConfigSource::File);
+
     Ok(Config {
         window_size: 4 * 1024 * 1024,
         connection_window_size: 4 * 1024 * 1024,
         frame_size: 1024 * 1024,
 
+        pool_max_streams_per_conn: parse_default(
+            POOL_MAX_STREAMS_PER_CONNECTION,
+            DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION,
+        )?,
+        pool_unused_release_timeout: parse(POOL_UNUSED_RELEASE_TIMEOUT)?
+            .map(|gd: GoDuration| gd.0)
+            .unwrap_or(DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT),
+
+        lb_policy: parse_default(LOAD_BALANCING_POLICY, LoadBalancerPolicy::default())?,
+        service_lb_policies: parse(SERVICE_LOAD_BALANCING_POLICIES)?
+            .map(|so: ServiceOverrides<LoadBalancerPolicy>| so.0)
+            .unwrap_or_default(),
+        connect_retry_budget: parse_default(CONNECT_RETRY_BUDGET, DEFAULT_CONNECT_RETRY_BUDGET)?,
+
+        outlier_consecutive_failures: parse_default(
+            OUTLIER_CONSECUTIVE_FAILURES,
+            DEFAULT_OUTLIER_CONSECUTIVE_FAILURES,
+        )?,
+        outlier_base_ejection_time: parse(OUTLIER_BASE_EJECTION_TIME)?
+            .map(|gd: GoDuration| gd.0)
+            .unwrap_or(DEFAULT_OUTLIER_BASE_EJECTION_TIME),
+        outlier_max_ejection_time: parse(OUTLIER_MAX_EJECTION_TIME)?
+            .map(|gd: GoDuration| gd.0)
+            .unwrap_or(DEFAULT_OUTLIER_MAX_EJECTION_TIME),
+        outlier_max_ejection_percent: parse_default(
+            OUTLIER_MAX_EJECTION_PERCENT,
+            DEFAULT_OUTLIER_MAX_EJECTION_PERCENT,
+        )?
+        .min(100),
+
+        connection_idle_timeout: parse(CONNECTION_IDLE_TIMEOUT)?
+            .map(|gd: GoDuration| gd.0)
+            .filter(|d| !d.is_zero()),
+        max_connection_duration: parse(MAX_CONNECTION_DURATION)?
+            .map(|gd: GoDuration| gd.0)
+            .filter(|d| !d.is_zero()),
+        service_connection_idle_timeouts: parse(SERVICE_CONNECTION_IDLE_TIMEOUTS)?
+            .map(|so: ServiceOverrides<GoDuration>| so.0)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(vip, gd)| (vip, gd.0))
+            .collect(),
+        service_max_connection_durations: parse(SERVICE_MAX_CONNECTION_DURATIONS)?
+            .map(|so: ServiceOverrides<GoDuration>| so.0)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(vip, gd)| (vip, gd.0))
+            .collect(),
+        udp_idle_timeout: parse(UDP_IDLE_TIMEOUT)?
+            .map(|gd: GoDuration| gd.0)
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_UDP_IDLE_TIMEOUT),
+
+        circuit_breaker_max_connections: parse_default(CIRCUIT_BREAKER_MAX_CONNECTIONS, 0)?,
+        circuit_breaker_max_pending_connects: parse_default(
+            CIRCUIT_BREAKER_MAX_PENDING_CONNECTS,
+            0,
+        )?,
+        circuit_breaker_max_hbone_streams: parse_default(CIRCUIT_BREAKER_MAX_HBONE_STREAMS, 0)?,
+
+        connection_rate_limits: parse_default(CONNECTION_RATE_LIMITS, RateLimitConfig::default())?,
+
+        socket_options: SocketOptions {
+            keepalive_time: Some(
+                parse(TCP_KEEPALIVE_TIME)?
+                    .map(|gd: GoDuration| gd.0)
+                    .unwrap_or(DEFAULT_TCP_KEEPALIVE_TIME),
+            )
+            .filter(|d| !d.is_zero()),
+            keepalive_interval: Some(
+                parse(TCP_KEEPALIVE_INTERVAL)?
+                    .map(|gd: GoDuration| gd.0)
+                    .unwrap_or(DEFAULT_TCP_KEEPALIVE_INTERVAL),
+            ),
+            keepalive_retries: Some(parse_default(
+                TCP_KEEPALIVE_RETRIES,
+                DEFAULT_TCP_KEEPALIVE_RETRIES,
+            )?),
+            user_timeout: parse(TCP_USER_TIMEOUT)?
+                .map(|gd: GoDuration| gd.0)
+                .filter(|d| !d.is_zero()),
+            recv_buffer_size: parse(SOCKET_RECV_BUFFER_SIZE)?,
+            send_buffer_size: parse(SOCKET_SEND_BUFFER_SIZE)?,
+        },
+
         termination_grace_period: parse(TERMINATION_GRACE_PERIOD)?
             .map(|gd: GoDuration| gd.0)
             .or(pc.termination_drain_duration)
@@ -272,10 +561,22 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             DEFAULT_READINESS_PORT, // There is no config for this in ProxyConfig currently
         ),
 
+        socks5_dns_fallback: parse_default(SOCKS5_DNS_FALLBACK, false)?,
+        socks5_allow_unauthenticated: parse_default(
+            SOCKS5_ALLOW_UNAUTHENTICATED,
+            socks5_credentials.is_none(),
+        )?,
+        socks5_credentials,
         socks5_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 15080),
-        inbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15008),
+        http_connect_addr: parse(HTTP_CONNECT_ADDR)?,
+        inbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), HBONE_PORT),
         inbound_plaintext_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15006),
         outbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15001),
+        outbound_udp_addr: parse(OUTBOUND_UDP_ADDR)?,
+        inbound_h3_addr: parse(INBOUND_H3_ADDR)?,
+        proxy_protocol_trusted_cidrs: parse(PROXY_PROTOCOL_TRUSTED_CIDRS)?
+            .map(|c: Cidrs| c.0)
+            .unwrap_or_default(),
 
         local_node: parse(NODE_NAME)?,
         proxy_mode: match parse::<String>(PROXY_MODE)? {
@@ -288,6 +589,16 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         },
         local_ip: parse(INSTANCE_IP)?,
         cluster_id,
+        network: parse_default(NETWORK, String::new())?,
+        network_gateways: parse(NETWORK_GATEWAYS)?
+            .map(|ng: NetworkGateways| ng.0)
+            .unwrap_or_default(),
+        network_gateway_identity: parse_default(
+            NETWORK_GATEWAY_IDENTITY,
+            DEFAULT_NETWORK_GATEWAY_IDENTITY
+                .parse()
+                .expect("default gateway identity is valid"),
+        )?,
 
         xds_address,
         xds_root_cert,
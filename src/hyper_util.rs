@@ -53,6 +53,34 @@ pub fn tls_server<T: CertProvider + Clone + 'static>(
         })
 }
 
+/// tls_server_from_stream is like tls_server, but terminates TLS on connections from `incoming`
+/// rather than accepting them directly from a listener.
+pub fn tls_server_from_stream<T, S>(
+    acceptor: T,
+    incoming: S,
+) -> impl Stream<
+    Item = Result<tokio_boring::SslStream<TcpStream>, tls_listener::Error<io::Error, TlsError>>,
+>
+where
+    T: CertProvider + Clone + 'static,
+    S: Stream<Item = io::Result<TcpStream>>,
+{
+    let boring_acceptor = BoringTlsAcceptor { acceptor };
+    let incoming = tls_listener::hyper::wrap(hyper::server::accept::from_stream(incoming));
+
+    tls_listener::builder(boring_acceptor)
+        .listen(incoming)
+        .filter(|conn| {
+            if let Err(err) = conn {
+                warn!("TLS handshake error: {}", err);
+                false
+            } else {
+                debug!("TLS handshake succeeded");
+                true
+            }
+        })
+}
+
 pub fn empty_response(code: hyper::StatusCode) -> Response<Body> {
     Response::builder()
         .status(code)
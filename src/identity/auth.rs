@@ -31,10 +31,7 @@ impl AuthSource {
                 let t = std::fs::read(path)?;
 
                 if t.is_empty() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "token file exists, but was empty",
-                    ));
+                    return Err(io::Error::other("token file exists, but was empty"));
                 }
                 Ok(t)
             }
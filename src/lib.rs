@@ -19,6 +19,7 @@ pub mod config;
 pub mod identity;
 pub mod metrics;
 pub mod proxy;
+pub mod ratelimit;
 pub mod rbac;
 pub mod readiness;
 pub mod signal;
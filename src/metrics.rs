@@ -18,6 +18,7 @@ use prometheus_client::registry::Registry;
 use tracing::error;
 
 mod meta;
+pub mod pool;
 #[allow(non_camel_case_types)]
 pub mod traffic;
 pub mod xds;
@@ -28,6 +29,7 @@ pub struct Metrics {
     #[allow(dead_code)]
     meta: meta::Metrics,
     traffic: traffic::Metrics,
+    pool: pool::Metrics,
 }
 
 impl Metrics {
@@ -36,6 +38,7 @@ impl Metrics {
             xds: xds::Metrics::new(registry),
             meta: meta::Metrics::new(registry),
             traffic: traffic::Metrics::new(registry),
+            pool: pool::Metrics::new(registry),
         }
     }
 }
@@ -89,6 +92,18 @@ where
     event: Option<E>,
 }
 
+impl<E> MetricGuard<'_, E>
+where
+    Metrics: IncrementRecorder<E>,
+{
+    /// update modifies the event that will be recorded once the guard is dropped.
+    pub fn update(&mut self, f: impl FnOnce(&mut E)) {
+        if let Some(m) = self.event.as_mut() {
+            f(m)
+        }
+    }
+}
+
 impl<E> Drop for MetricGuard<'_, E>
 where
     Metrics: IncrementRecorder<E>,
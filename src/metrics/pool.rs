@@ -0,0 +1,59 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::registry::Registry;
+
+use crate::metrics::Recorder;
+
+pub(super) struct Metrics {
+    pub(super) checkouts: Family<PoolCheckout, Counter>,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct PoolCheckout {
+    pub result: PoolCheckoutResult,
+}
+
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum PoolCheckoutResult {
+    /// An existing HBONE connection was reused
+    Hit,
+    /// A new HBONE connection was established
+    Miss,
+}
+
+impl Metrics {
+    pub fn new(registry: &mut Registry) -> Self {
+        let checkouts = Family::default();
+        registry.register(
+            "hbone_pool_checkouts",
+            "The total number of HBONE connection pool checkouts",
+            checkouts.clone(),
+        );
+
+        Self { checkouts }
+    }
+}
+
+impl Recorder<PoolCheckoutResult, u64> for super::Metrics {
+    fn record(&self, result: &PoolCheckoutResult, count: u64) {
+        self.pool
+            .checkouts
+            .get_or_create(&PoolCheckout { result: *result })
+            .inc_by(count);
+    }
+}
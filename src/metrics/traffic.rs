@@ -22,16 +22,19 @@ use prometheus_client::registry::Registry;
 use crate::identity::Identity;
 use crate::metrics::traffic::Reporter::source;
 use crate::metrics::Recorder;
-use crate::workload::Workload;
+use crate::workload::{Service, Workload};
 
 pub(super) struct Metrics {
     pub(super) connection_opens: Family<CommonTrafficLabels, Counter>,
     pub(super) connection_close: Family<CommonTrafficLabels, Counter>,
     pub(super) received_bytes: Family<CommonTrafficLabels, Counter>,
     pub(super) sent_bytes: Family<CommonTrafficLabels, Counter>,
+    pub(super) connect_retries: Family<ConnectRetry, Counter>,
+    pub(super) plaintext_rejected: Family<CommonTrafficLabels, Counter>,
+    pub(super) authorization_revoked: Family<CommonTrafficLabels, Counter>,
 }
 
-#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue, serde::Serialize)]
 pub enum Reporter {
     #[default]
     source,
@@ -39,35 +42,70 @@ pub enum Reporter {
     destination,
 }
 
-#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue, serde::Serialize)]
 pub enum RequestProtocol {
     #[default]
     tcp,
     #[allow(dead_code)]
     http,
+    udp,
 }
 
 #[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum ResponseFlags {
     #[default]
     none,
+    /// The connection was closed after being idle for too long.
+    stream_idle_timeout,
+    /// The connection was closed after exceeding its maximum duration.
+    duration_timeout,
+    /// The connection was rejected because a circuit breaker for the destination was open.
+    upstream_overflow,
+    /// The connection was rejected because the source exceeded its connection rate limit.
+    rate_limited,
+    /// The connection was closed because an authorization policy change denied it.
+    unauthorized_rbac,
+    /// The connection to the upstream could not be established.
+    upstream_connection_failure,
 }
 
 impl EncodeLabelValue for ResponseFlags {
     fn encode(&self, writer: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
         match self {
             ResponseFlags::none => writer.write_str("-"),
+            ResponseFlags::stream_idle_timeout => writer.write_str("SI"),
+            ResponseFlags::duration_timeout => writer.write_str("DT"),
+            ResponseFlags::upstream_overflow => writer.write_str("UO"),
+            ResponseFlags::rate_limited => writer.write_str("RL"),
+            ResponseFlags::unauthorized_rbac => writer.write_str("RBAC"),
+            ResponseFlags::upstream_connection_failure => writer.write_str("UF"),
         }
     }
 }
 
-#[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+#[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue, serde::Serialize)]
 pub enum SecurityPolicy {
     #[default]
     unknown,
     mutual_tls,
 }
 
+/// ConnectRetry records an upstream connection attempt that failed and was retried on another endpoint.
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectRetry {
+    pub reason: ConnectRetryReason,
+}
+
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum ConnectRetryReason {
+    /// The TCP connection to the upstream could not be established
+    connect_error,
+    /// The TLS handshake with the upstream failed
+    tls_error,
+    /// The HBONE CONNECT to the upstream failed
+    hbone_error,
+}
+
 #[derive(Default, Hash, PartialEq, Eq, Clone, Debug)]
 // DefaultedUnknown is a wrapper around an Option that encodes as "unknown" when missing, rather than ""
 struct DefaultedUnknown<T>(Option<T>);
@@ -103,10 +141,27 @@ impl<T: EncodeLabelValue> EncodeLabelValue for DefaultedUnknown<T> {
     }
 }
 
-pub struct ConnectionClose<'a>(&'a ConnectionOpen);
+pub struct ConnectionClose<'a> {
+    open: &'a ConnectionOpen,
+    response_flags: ResponseFlags,
+}
+
+impl ConnectionClose<'_> {
+    /// set_response_flags records why the connection was closed.
+    pub fn set_response_flags(&mut self, response_flags: ResponseFlags) {
+        self.response_flags = response_flags;
+    }
+}
 
 pub struct BytesTransferred<'a>(&'a ConnectionOpen);
 
+/// PlaintextRejected records a plaintext connection refused because its destination requires mTLS.
+pub struct PlaintextRejected<'a>(pub &'a ConnectionOpen);
+
+/// AuthorizationRevoked records an established connection closed because an authorization policy
+/// change denied it.
+pub struct AuthorizationRevoked<'a>(pub &'a ConnectionOpen);
+
 #[derive(Clone, Debug, Default)]
 pub struct DerivedWorkload {
     pub workload_name: Option<String>,
@@ -123,15 +178,17 @@ pub struct ConnectionOpen {
     pub source: Option<Workload>,
     pub derived_source: Option<DerivedWorkload>,
     pub destination: Option<Workload>,
-    pub destination_service: Option<String>,
-    pub destination_service_namespace: Option<String>,
-    pub destination_service_name: Option<String>,
+    pub destination_service: Option<Service>,
+    pub request_protocol: RequestProtocol,
     pub connection_security_policy: SecurityPolicy,
 }
 
 impl<'a> From<&'a ConnectionOpen> for ConnectionClose<'a> {
     fn from(c: &'a ConnectionOpen) -> Self {
-        ConnectionClose(c)
+        ConnectionClose {
+            open: c,
+            response_flags: ResponseFlags::none,
+        }
     }
 }
 
@@ -147,9 +204,7 @@ impl CommonTrafficLabels {
     }
 
     fn with_source(mut self, w: Option<&Workload>) -> Self {
-        let Some(w) = w else {
-            return self
-        };
+        let Some(w) = w else { return self };
         self.source_workload = w.workload_name.clone().into();
         self.source_canonical_service = w.canonical_name.clone().into();
         self.source_canonical_revision = w.canonical_revision.clone().into();
@@ -162,9 +217,7 @@ impl CommonTrafficLabels {
     }
 
     fn with_derived_source(mut self, w: Option<&DerivedWorkload>) -> Self {
-        let Some(w) = w else {
-            return self
-        };
+        let Some(w) = w else { return self };
         self.source_workload = w.workload_name.clone().into();
         self.source_canonical_service = w.app.clone().into();
         self.source_canonical_revision = w.revision.clone().into();
@@ -177,9 +230,7 @@ impl CommonTrafficLabels {
     }
 
     fn with_destination(mut self, w: Option<&Workload>) -> Self {
-        let Some(w) = w else {
-            return self
-        };
+        let Some(w) = w else { return self };
         self.destination_workload = w.workload_name.clone().into();
         self.destination_canonical_service = w.canonical_name.clone().into();
         self.destination_canonical_revision = w.canonical_revision.clone().into();
@@ -190,6 +241,14 @@ impl CommonTrafficLabels {
         self.destination_cluster = w.cluster_id.to_string().into();
         self
     }
+
+    fn with_destination_service(mut self, s: Option<&Service>) -> Self {
+        let Some(s) = s else { return self };
+        self.destination_service = s.hostname.clone().into();
+        self.destination_service_namespace = s.namespace.clone().into();
+        self.destination_service_name = s.name.clone().into();
+        self
+    }
 }
 
 impl From<BytesTransferred<'_>> for CommonTrafficLabels {
@@ -202,7 +261,7 @@ impl From<&ConnectionOpen> for CommonTrafficLabels {
     fn from(c: &ConnectionOpen) -> Self {
         CommonTrafficLabels {
             reporter: c.reporter,
-            request_protocol: RequestProtocol::tcp,
+            request_protocol: c.request_protocol,
             response_flags: ResponseFlags::none,
             connection_security_policy: c.connection_security_policy,
             ..CommonTrafficLabels::new()
@@ -210,6 +269,7 @@ impl From<&ConnectionOpen> for CommonTrafficLabels {
                 .with_derived_source(c.derived_source.as_ref())
                 .with_source(c.source.as_ref())
                 .with_destination(c.destination.as_ref())
+                .with_destination_service(c.destination_service.as_ref())
         }
     }
 }
@@ -227,7 +287,6 @@ pub(super) struct CommonTrafficLabels {
     source_version: DefaultedUnknown<String>,
     source_cluster: DefaultedUnknown<String>,
 
-    // TODO: never set
     destination_service: DefaultedUnknown<String>,
     destination_service_namespace: DefaultedUnknown<String>,
     destination_service_name: DefaultedUnknown<String>,
@@ -274,11 +333,35 @@ impl Metrics {
             sent_bytes.clone(),
         );
 
+        let connect_retries = Family::default();
+        registry.register(
+            "tcp_connect_retries",
+            "The total number of failed upstream connections retried on another endpoint",
+            connect_retries.clone(),
+        );
+
+        let plaintext_rejected = Family::default();
+        registry.register(
+            "tcp_plaintext_connections_rejected",
+            "The total number of plaintext connections rejected because the destination requires mTLS",
+            plaintext_rejected.clone(),
+        );
+
+        let authorization_revoked = Family::default();
+        registry.register(
+            "tcp_connections_revoked",
+            "The total number of established connections closed because an authorization policy change denied them",
+            authorization_revoked.clone(),
+        );
+
         Self {
             connection_opens,
             connection_close,
             received_bytes,
             sent_bytes,
+            connect_retries,
+            plaintext_rejected,
+            authorization_revoked,
         }
     }
 }
@@ -292,11 +375,41 @@ impl Recorder<ConnectionOpen, u64> for super::Metrics {
     }
 }
 
+impl Recorder<ConnectRetryReason, u64> for super::Metrics {
+    fn record(&self, reason: &ConnectRetryReason, count: u64) {
+        self.traffic
+            .connect_retries
+            .get_or_create(&ConnectRetry { reason: *reason })
+            .inc_by(count);
+    }
+}
+
+impl Recorder<PlaintextRejected<'_>, u64> for super::Metrics {
+    fn record(&self, event: &PlaintextRejected<'_>, count: u64) {
+        self.traffic
+            .plaintext_rejected
+            .get_or_create(&CommonTrafficLabels::from(event.0))
+            .inc_by(count);
+    }
+}
+
+impl Recorder<AuthorizationRevoked<'_>, u64> for super::Metrics {
+    fn record(&self, event: &AuthorizationRevoked<'_>, count: u64) {
+        self.traffic
+            .authorization_revoked
+            .get_or_create(&CommonTrafficLabels::from(event.0))
+            .inc_by(count);
+    }
+}
+
 impl Recorder<ConnectionClose<'_>, u64> for super::Metrics {
     fn record(&self, reason: &ConnectionClose, count: u64) {
         self.traffic
             .connection_close
-            .get_or_create(&CommonTrafficLabels::from(reason.0))
+            .get_or_create(&CommonTrafficLabels {
+                response_flags: reason.response_flags,
+                ..CommonTrafficLabels::from(reason.open)
+            })
             .inc_by(count);
     }
 }
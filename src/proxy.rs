@@ -12,8 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::future::Future;
 use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, io};
@@ -22,31 +26,54 @@ use boring::error::ErrorStack;
 use drain::Watch;
 use hyper::{header, Body, Request};
 use rand::Rng;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::time::timeout;
-use tracing::{error, trace, warn, Instrument};
+use tracing::{error, info, trace, warn, Instrument};
 
 use inbound::Inbound;
 
 use crate::identity::SecretManager;
 use crate::metrics::{traffic, Metrics, Recorder};
+use crate::proxy::http_connect::HttpConnect;
 use crate::proxy::inbound_passthrough::InboundPassthrough;
 use crate::proxy::outbound::Outbound;
+use crate::proxy::outbound_udp::OutboundUdp;
 use crate::proxy::socks5::Socks5;
+use crate::ratelimit::{self, RateLimiter};
 use crate::workload::WorkloadInformation;
 use crate::{config, identity, socket, tls};
 
+mod circuit_breaker;
+mod connections;
+mod http_connect;
 mod inbound;
 mod inbound_passthrough;
 mod outbound;
+mod outbound_udp;
+mod pool;
+mod proxy_protocol;
+#[cfg(feature = "quic")]
+mod quic;
 mod socks5;
+mod udp;
 mod util;
 
+pub use connections::{ConnectionFilter, ConnectionTracker};
+use connections::{ConnectionInfo, TrackedConnection};
+
 pub struct Proxy {
     inbound: Inbound,
     inbound_passthrough: InboundPassthrough,
     outbound: Outbound,
+    outbound_udp: Option<OutboundUdp>,
     socks5: Socks5,
+    http_connect: Option<HttpConnect>,
+    pool: pool::Pool,
+    connections: ConnectionTracker,
+    workloads: WorkloadInformation,
+    metrics: Arc<Metrics>,
+    drain: Watch,
 }
 
 #[derive(Clone)]
@@ -56,6 +83,12 @@ pub(super) struct ProxyInputs {
     hbone_port: u16,
     workloads: WorkloadInformation,
     metrics: Arc<Metrics>,
+    pool: pool::Pool,
+    circuit_breakers: circuit_breaker::CircuitBreakers,
+    rate_limiter: RateLimiter,
+    connections: ConnectionTracker,
+    #[cfg(feature = "quic")]
+    h3: quic::Client,
 }
 
 impl Proxy {
@@ -64,37 +97,93 @@ impl Proxy {
         workloads: WorkloadInformation,
         cert_manager: Arc<SecretManager>,
         metrics: Arc<Metrics>,
+        rate_limiter: RateLimiter,
+        connections: ConnectionTracker,
         drain: Watch,
     ) -> Result<Proxy, Error> {
+        let pool = pool::Pool::new(&cfg, metrics.clone());
+        let circuit_breakers = circuit_breaker::CircuitBreakers::new(&cfg);
         let mut pi = ProxyInputs {
+            #[cfg(feature = "quic")]
+            h3: quic::Client::new(&cfg, cert_manager.clone()),
             cfg,
             workloads,
             cert_manager,
             metrics,
             hbone_port: 0,
+            pool: pool.clone(),
+            circuit_breakers,
+            rate_limiter,
+            connections: connections.clone(),
         };
         // We setup all the listeners first so we can capture any errors that should block startup
         let inbound = Inbound::new(pi.clone(), drain.clone()).await?;
         pi.hbone_port = inbound.address().port();
 
-        let inbound_passthrough = InboundPassthrough::new(pi.clone()).await?;
+        let inbound_passthrough = InboundPassthrough::new(pi.clone(), drain.clone()).await?;
         let outbound = Outbound::new(pi.clone(), drain.clone()).await?;
-        let socks5 = Socks5::new(pi.clone(), drain).await?;
+        let outbound_udp = match pi.cfg.outbound_udp_addr {
+            Some(addr) => Some(OutboundUdp::new(pi.clone(), addr, drain.clone()).await?),
+            None => None,
+        };
+        let socks5 = Socks5::new(pi.clone(), drain.clone()).await?;
+        let http_connect = match pi.cfg.http_connect_addr {
+            Some(addr) => Some(HttpConnect::new(pi.clone(), addr, drain.clone()).await?),
+            None => None,
+        };
         Ok(Proxy {
             inbound,
             inbound_passthrough,
             outbound,
+            outbound_udp,
             socks5,
+            http_connect,
+            pool,
+            connections,
+            workloads: pi.workloads,
+            metrics: pi.metrics,
+            drain,
         })
     }
 
     pub async fn run(self) {
-        let tasks = vec![
+        let connections = self.connections;
+        let drain = self.drain;
+        // Subscribed before accepting connections, so no change to their policies is missed
+        let policy_changes = self.workloads.policy_changes();
+        let mut tasks = vec![
             tokio::spawn(self.inbound_passthrough.run().in_current_span()),
             tokio::spawn(self.inbound.run().in_current_span()),
             tokio::spawn(self.outbound.run().in_current_span()),
             tokio::spawn(self.socks5.run().in_current_span()),
+            tokio::spawn(self.pool.run(drain.clone()).in_current_span()),
+            tokio::spawn(
+                connections
+                    .clone()
+                    .enforce_policies(self.workloads, self.metrics, policy_changes, drain.clone())
+                    .in_current_span(),
+            ),
+            // The listeners stop accepting once drained, but the drain is not complete until the
+            // connections they accepted have been relayed.
+            tokio::spawn(
+                async move {
+                    let release = drain.signaled().await;
+                    info!(
+                        connections = connections.active(),
+                        "waiting for connections to complete"
+                    );
+                    connections.idle().await;
+                    drop(release);
+                }
+                .in_current_span(),
+            ),
         ];
+        if let Some(outbound_udp) = self.outbound_udp {
+            tasks.push(tokio::spawn(outbound_udp.run().in_current_span()));
+        }
+        if let Some(http_connect) = self.http_connect {
+            tasks.push(tokio::spawn(http_connect.run().in_current_span()));
+        }
 
         futures::future::join_all(tasks).await;
     }
@@ -102,8 +191,11 @@ impl Proxy {
     pub fn addresses(&self) -> Addresses {
         Addresses {
             outbound: self.outbound.address(),
+            outbound_udp: self.outbound_udp.as_ref().map(|o| o.address()),
             inbound: self.inbound.address(),
+            inbound_plaintext: self.inbound_passthrough.address(),
             socks5: self.socks5.address(),
+            http_connect: self.http_connect.as_ref().map(|h| h.address()),
         }
     }
 }
@@ -111,8 +203,11 @@ impl Proxy {
 #[derive(Copy, Clone)]
 pub struct Addresses {
     pub outbound: SocketAddr,
+    pub outbound_udp: Option<SocketAddr>,
     pub inbound: SocketAddr,
+    pub inbound_plaintext: SocketAddr,
     pub socks5: SocketAddr,
+    pub http_connect: Option<SocketAddr>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -120,12 +215,19 @@ pub enum Error {
     #[error("failed to bind to address {0}: {1}")]
     Bind(SocketAddr, io::Error),
 
+    #[error("invalid socks5 credentials: {0}")]
+    Socks5Credentials(anyhow::Error),
+
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 
     #[error("tls handshake failed: {0:?}")]
     TlsHandshake(#[from] tokio_boring::HandshakeError<TcpStream>),
 
+    // The handshake error holds the tunnel, which is not Sync, so only the message is kept
+    #[error("tls handshake through network gateway failed: {0}")]
+    GatewayTlsHandshake(String),
+
     #[error("http handshake failed: {0}")]
     HttpHandshake(#[source] hyper::Error),
 
@@ -135,6 +237,22 @@ pub enum Error {
     #[error("http status: {0}")]
     HttpStatus(hyper::StatusCode),
 
+    #[cfg(feature = "quic")]
+    #[error("quic connect failed: {0}")]
+    QuicConnect(#[from] quinn::ConnectError),
+
+    #[cfg(feature = "quic")]
+    #[error("quic connection failed: {0}")]
+    Quic(#[from] quinn::ConnectionError),
+
+    #[cfg(feature = "quic")]
+    #[error("http/3 connection failed: {0}")]
+    Http3Connection(#[from] h3::error::ConnectionError),
+
+    #[cfg(feature = "quic")]
+    #[error("http/3 failed: {0}")]
+    Http3(#[from] h3::error::StreamError),
+
     #[error("tls error: {0}")]
     Tls(#[from] tls::Error),
 
@@ -153,23 +271,160 @@ pub enum Error {
     #[error("unknown destination: {0}")]
     UnknownDestination(IpAddr),
 
+    #[error("connection rejected by authorization policy: {0}")]
+    RbacRejected(crate::rbac::Connection),
+
+    #[error("connection denied by change to authorization policy {0}")]
+    AuthorizationRevoked(String),
+
+    #[error("cannot relay udp to {0}, which is only reachable through a proxy")]
+    UdpUnreachable(SocketAddr),
+
+    #[error("invalid PROXY protocol header: {0}")]
+    ProxyProtocol(String),
+
     #[error("attempted recursive call to ourselves")]
     SelfCall,
 
     #[error("no gateway address: {0}")]
     NoGatewayAddress(Box<crate::workload::Workload>),
+
+    #[error("no gateway configured for network {0:?}")]
+    NoNetworkGateway(String),
+
+    #[error("workload {0} is on another network, but does not support HBONE")]
+    NetworkUnreachable(String),
+
+    #[error("connection idle for longer than {0:?}")]
+    IdleTimeout(Duration),
+
+    #[error("connection open for longer than {0:?}")]
+    MaxConnectionDuration(Duration),
+
+    #[error("{0}")]
+    CircuitBreakerOpen(#[from] circuit_breaker::Rejected),
+
+    #[error("{0}")]
+    RateLimited(#[from] ratelimit::Rejected),
+}
+
+impl Error {
+    /// response_flags returns the flags to report when a connection is closed due to this error.
+    pub fn response_flags(&self) -> traffic::ResponseFlags {
+        match self {
+            Error::IdleTimeout(_) => traffic::ResponseFlags::stream_idle_timeout,
+            Error::MaxConnectionDuration(_) => traffic::ResponseFlags::duration_timeout,
+            Error::CircuitBreakerOpen(_) => traffic::ResponseFlags::upstream_overflow,
+            Error::RateLimited(_) => traffic::ResponseFlags::rate_limited,
+            Error::AuthorizationRevoked(_) => traffic::ResponseFlags::unauthorized_rbac,
+            _ => traffic::ResponseFlags::none,
+        }
+    }
+
+    /// is_deliberate_close returns whether this error is the proxy closing a connection on purpose,
+    /// because it exceeded a limit or is no longer authorized, rather than something going wrong.
+    pub fn is_deliberate_close(&self) -> bool {
+        matches!(
+            self,
+            Error::IdleTimeout(_)
+                | Error::MaxConnectionDuration(_)
+                | Error::AuthorizationRevoked(_)
+        )
+    }
 }
 
+/// ConnectionLimits bounds how long a proxied connection is kept open.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Close the connection once no data has been sent or received for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Close the connection once it has been open for this long, regardless of activity.
+    pub max_duration: Option<Duration>,
+}
+
+impl ConnectionLimits {
+    /// new returns the limits for connections to the service with the VIP `service`. If the service
+    /// is not known, or has no overrides, the global limits apply.
+    pub fn new(cfg: &config::Config, service: Option<IpAddr>) -> ConnectionLimits {
+        let lookup =
+            |overrides: &HashMap<IpAddr, Duration>, default: Option<Duration>| match service
+                .and_then(|vip| overrides.get(&vip))
+            {
+                Some(d) => Some(*d).filter(|d| !d.is_zero()),
+                None => default,
+            };
+        ConnectionLimits {
+            idle_timeout: lookup(
+                &cfg.service_connection_idle_timeouts,
+                cfg.connection_idle_timeout,
+            ),
+            max_duration: lookup(
+                &cfg.service_max_connection_durations,
+                cfg.max_connection_duration,
+            ),
+        }
+    }
+
+    /// enforce drives `copy` to completion, unless the limits are exceeded first. `fd` is a TCP
+    /// socket carrying the connection in both directions, which is used to detect when it is idle.
+    /// Idle detection relies on TCP_INFO, so is only supported on Linux.
+    async fn enforce<T>(
+        self,
+        fd: RawFd,
+        copy: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let start = tokio::time::Instant::now();
+        tokio::pin!(copy);
+        loop {
+            let mut deadline = self.max_duration.map(|d| start + d);
+            if let Some(idle_timeout) = self.idle_timeout {
+                let idle = socket::idle_time(fd).unwrap_or_default();
+                if idle >= idle_timeout {
+                    return Err(Error::IdleTimeout(idle_timeout));
+                }
+                // Check again once we could have been idle for long enough
+                let check = tokio::time::Instant::now() + (idle_timeout - idle);
+                deadline = Some(deadline.map_or(check, |d| d.min(check)));
+            }
+            let deadline = match deadline {
+                Some(deadline) => deadline,
+                None => return copy.await,
+            };
+            tokio::select! {
+                res = &mut copy => return res,
+                _ = tokio::time::sleep_until(deadline) => {}
+            }
+            if let Some(max_duration) = self.max_duration {
+                if start.elapsed() >= max_duration {
+                    return Err(Error::MaxConnectionDuration(max_duration));
+                }
+            }
+        }
+    }
+}
+
+/// Tunnel is the stream of an established HBONE tunnel, whichever version of HTTP carries it.
+pub(super) trait Tunnel: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Tunnel for T {}
+
+/// PendingTunnel resolves to the tunnel of an accepted HBONE request, once it has been responded to.
+pub(super) type PendingTunnel = Pin<Box<dyn Future<Output = io::Result<Box<dyn Tunnel>>> + Send>>;
+
 // TLS record size max is 16k. But we also have a H2 frame header, so leave a bit of room for that.
 const HBONE_BUFFER_SIZE: usize = 16_384 - 64;
 
 pub async fn copy_hbone(
-    upgraded: &mut hyper::upgrade::Upgraded,
+    upgraded: &mut (impl AsyncRead + AsyncWrite + Unpin),
     stream: &mut TcpStream,
+    limits: ConnectionLimits,
     metrics: impl AsRef<Metrics>,
     transferred_bytes: traffic::BytesTransferred<'_>,
+    tracked: &TrackedConnection,
 ) -> Result<(), Error> {
     use tokio::io::AsyncWriteExt;
+    let fd = stream.as_raw_fd();
+    let relaying = tracked.relaying(fd);
     let (mut ri, mut wi) = tokio::io::split(upgraded);
     let (mut ro, mut wo) = stream.split();
 
@@ -193,13 +448,23 @@ pub async fn copy_hbone(
         wi.shutdown().await
     };
 
-    tokio::try_join!(client_to_server, server_to_client)?;
+    let copy = limits.enforce(fd, async {
+        tokio::try_join!(client_to_server, server_to_client).map_err(Error::Io)
+    });
+    let res = tracked.revocable(copy).await;
+    // A copy that is cut short does not report what it copied, so fall back to the kernel's count
+    let (sent, received) = match res {
+        Ok(_) => (sent, received),
+        Err(_) => relaying.transferred(),
+    };
 
     trace!(sent, recv = received, "copy hbone complete");
+    drop(relaying);
+    tracked.transferred((sent, received));
     metrics
         .as_ref()
         .record(&transferred_bytes, (sent, received));
-    Ok(())
+    res.map(|_| ())
 }
 
 /// Represents a traceparent, as defined by https://www.w3.org/TR/trace-context/
@@ -311,34 +576,35 @@ pub fn get_original_src_from_fwded(req: &Request<Body>) -> Option<IpAddr> {
         })
 }
 
-pub fn get_original_src_from_stream(stream: &TcpStream) -> Option<IpAddr> {
-    stream
-        .peer_addr()
-        .map_or(None, |sa| Some(socket::to_canonical(sa).ip()))
-}
-
 const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub async fn freebind_connect(local: Option<IpAddr>, addr: SocketAddr) -> io::Result<TcpStream> {
-    async fn connect(local: Option<IpAddr>, addr: SocketAddr) -> io::Result<TcpStream> {
+pub async fn freebind_connect(
+    local: Option<IpAddr>,
+    addr: SocketAddr,
+    socket_options: &socket::SocketOptions,
+) -> io::Result<TcpStream> {
+    async fn connect(
+        local: Option<IpAddr>,
+        addr: SocketAddr,
+        socket_options: &socket::SocketOptions,
+    ) -> io::Result<TcpStream> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        // Options such as buffer sizes must be set before connecting to take full effect
+        socket_options.apply(&socket)?;
         match local {
             None => {
                 trace!(dest=%addr, "no local address, connect directly");
-                Ok(TcpStream::connect(addr).await?)
             }
             // TODO: Need figure out how to handle case of loadbalancing to itself.
             //       We use ztunnel addr instead, otherwise app side will be confused.
             Some(src) if src == socket::to_canonical(addr).ip() => {
                 trace!(%src, dest=%addr, "dest and source are the same, connect directly");
-                Ok(TcpStream::connect(addr).await?)
             }
             Some(src) => {
-                let socket = if src.is_ipv4() {
-                    TcpSocket::new_v4()?
-                } else {
-                    TcpSocket::new_v6()?
-                };
-
                 let local_addr = SocketAddr::new(src, 0);
                 match socket::set_freebind_and_transparent(&socket) {
                     Err(err) => warn!("failed to set freebind: {:?}", err),
@@ -349,12 +615,12 @@ pub async fn freebind_connect(local: Option<IpAddr>, addr: SocketAddr) -> io::Re
                     }
                 };
                 trace!(%src, dest=%addr, "connect with source IP");
-                Ok(socket.connect(addr).await?)
             }
         }
+        socket.connect(addr).await
     }
     // Wrap the entire connect function in a timeout
-    timeout(CONNECTION_TIMEOUT, connect(local, addr))
+    timeout(CONNECTION_TIMEOUT, connect(local, addr, socket_options))
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?
 }
@@ -362,17 +628,25 @@ pub async fn freebind_connect(local: Option<IpAddr>, addr: SocketAddr) -> io::Re
 pub async fn relay(
     downstream: &mut tokio::net::TcpStream,
     upstream: &mut tokio::net::TcpStream,
+    limits: ConnectionLimits,
     metrics: impl AsRef<Metrics>,
     transferred_bytes: traffic::BytesTransferred<'_>,
+    tracked: &TrackedConnection,
 ) -> Result<(u64, u64), Error> {
-    match socket::relay(downstream, upstream).await {
-        Ok(transferred) => {
-            trace!(sent = transferred.0, recv = transferred.1, "relay complete");
-            metrics.as_ref().record(&transferred_bytes, transferred);
-            Ok(transferred)
-        }
-        Err(e) => Err(Error::Io(e)),
-    }
+    let fd = downstream.as_raw_fd();
+    let relaying = tracked.relaying(fd);
+    let relay = async { socket::relay(downstream, upstream).await.map_err(Error::Io) };
+    let res = tracked.revocable(limits.enforce(fd, relay)).await;
+    // A relay that is cut short does not report what it copied, so fall back to the kernel's count
+    let transferred = match res {
+        Ok(transferred) => transferred,
+        Err(_) => relaying.transferred(),
+    };
+    trace!(sent = transferred.0, recv = transferred.1, "relay complete");
+    drop(relaying);
+    tracked.transferred(transferred);
+    metrics.as_ref().record(&transferred_bytes, transferred);
+    res
 }
 
 #[cfg(test)]
@@ -384,6 +658,37 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn connection_limits() {
+        let vip: IpAddr = "10.0.0.1".parse().unwrap();
+        let cfg = config::Config {
+            connection_idle_timeout: Some(Duration::from_secs(60)),
+            max_connection_duration: None,
+            service_connection_idle_timeouts: HashMap::from([(vip, Duration::ZERO)]),
+            service_max_connection_durations: HashMap::from([(vip, Duration::from_secs(5))]),
+            ..crate::test_helpers::test_config()
+        };
+        assert_eq!(
+            ConnectionLimits::new(&cfg, None),
+            ConnectionLimits {
+                idle_timeout: Some(Duration::from_secs(60)),
+                max_duration: None,
+            }
+        );
+        assert_eq!(
+            ConnectionLimits::new(&cfg, Some("10.0.0.2".parse().unwrap())),
+            ConnectionLimits::new(&cfg, None)
+        );
+        // A zero override disables the limit for the service
+        assert_eq!(
+            ConnectionLimits::new(&cfg, Some(vip)),
+            ConnectionLimits {
+                idle_timeout: None,
+                max_duration: Some(Duration::from_secs(5)),
+            }
+        );
+    }
+
     #[test_case(r#""#, None; "empty")]
     #[test_case(r#"proto=https"#, None; "no for")]
     #[test_case(r#"abc"#, None; "malformed")]
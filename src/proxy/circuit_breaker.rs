@@ -0,0 +1,282 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::config;
+
+/// CircuitBreakers limit the load placed on any single destination workload or service. Connections
+/// that would exceed a limit are rejected immediately, rather than queued.
+#[derive(Clone)]
+pub struct CircuitBreakers {
+    thresholds: Thresholds,
+    counts: Arc<Mutex<HashMap<Key, Counts>>>,
+}
+
+/// Thresholds for each destination. Zero means unlimited.
+#[derive(Clone, Copy, Debug)]
+struct Thresholds {
+    max_connections: u32,
+    max_pending_connects: u32,
+    max_hbone_streams: u32,
+}
+
+/// Key identifies a destination which has its own circuit breaker.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Key {
+    /// Workload is keyed by the workload uid.
+    Workload(String),
+    Service(IpAddr),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Workload(uid) => write!(f, "workload {uid}"),
+            Key::Service(ip) => write!(f, "service {ip}"),
+        }
+    }
+}
+
+/// Limit is the limit of a circuit breaker that was exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Connections,
+    PendingConnects,
+    HboneStreams,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Connections => write!(f, "max connections"),
+            Limit::PendingConnects => write!(f, "max pending connects"),
+            Limit::HboneStreams => write!(f, "max HBONE streams"),
+        }
+    }
+}
+
+/// Rejected is returned when a connection is not admitted because a circuit breaker is open.
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+#[error("circuit breaker open for {key}: {limit} exceeded")]
+pub struct Rejected {
+    pub key: Key,
+    pub limit: Limit,
+}
+
+#[derive(Default, Debug)]
+struct Counts {
+    /// Connections that are established, or being established.
+    connections: u32,
+    pending_connects: u32,
+    hbone_streams: u32,
+}
+
+impl Counts {
+    fn is_empty(&self) -> bool {
+        self.connections == 0 && self.pending_connects == 0 && self.hbone_streams == 0
+    }
+}
+
+/// Admission holds the capacity reserved for a single connection, which is released once dropped.
+pub struct Admission {
+    breakers: CircuitBreakers,
+    keys: Vec<Key>,
+    hbone: bool,
+    pending: bool,
+}
+
+impl Admission {
+    /// established records that the connection to the destination has been established, so it no
+    /// longer counts as pending.
+    pub fn established(&mut self) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+        self.breakers.release(&self.keys, |c| {
+            c.pending_connects -= 1;
+        });
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let (hbone, pending) = (self.hbone, self.pending);
+        self.breakers.release(&self.keys, |c| {
+            c.connections -= 1;
+            if pending {
+                c.pending_connects -= 1;
+            }
+            if hbone {
+                c.hbone_streams -= 1;
+            }
+        });
+    }
+}
+
+impl CircuitBreakers {
+    pub fn new(cfg: &config::Config) -> CircuitBreakers {
+        CircuitBreakers {
+            thresholds: Thresholds {
+                max_connections: cfg.circuit_breaker_max_connections,
+                max_pending_connects: cfg.circuit_breaker_max_pending_connects,
+                max_hbone_streams: cfg.circuit_breaker_max_hbone_streams,
+            },
+            counts: Default::default(),
+        }
+    }
+
+    /// admit reserves capacity for a new connection, which will be pending until it is marked as
+    /// established, to each of `keys`. If any of them has reached a limit, the connection is
+    /// rejected and nothing is reserved.
+    pub fn admit(&self, keys: Vec<Key>, hbone: bool) -> Result<Admission, Rejected> {
+        let t = self.thresholds;
+        let exceeded = |count: u32, max: u32| max != 0 && count >= max;
+        let mut counts = self.counts.lock().unwrap();
+        for key in &keys {
+            let Some(c) = counts.get(key) else {
+                continue;
+            };
+            let limit = if exceeded(c.connections, t.max_connections) {
+                Limit::Connections
+            } else if exceeded(c.pending_connects, t.max_pending_connects) {
+                Limit::PendingConnects
+            } else if hbone && exceeded(c.hbone_streams, t.max_hbone_streams) {
+                Limit::HboneStreams
+            } else {
+                continue;
+            };
+            return Err(Rejected {
+                key: key.clone(),
+                limit,
+            });
+        }
+        for key in &keys {
+            let c = counts.entry(key.clone()).or_default();
+            c.connections += 1;
+            c.pending_connects += 1;
+            if hbone {
+                c.hbone_streams += 1;
+            }
+        }
+        Ok(Admission {
+            breakers: self.clone(),
+            keys,
+            hbone,
+            pending: true,
+        })
+    }
+
+    fn release(&self, keys: &[Key], f: impl Fn(&mut Counts)) {
+        let mut counts = self.counts.lock().unwrap();
+        for key in keys {
+            if let Some(c) = counts.get_mut(key) {
+                f(c);
+                if c.is_empty() {
+                    counts.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breakers(
+        max_connections: u32,
+        max_pending_connects: u32,
+        max_hbone_streams: u32,
+    ) -> CircuitBreakers {
+        CircuitBreakers {
+            thresholds: Thresholds {
+                max_connections,
+                max_pending_connects,
+                max_hbone_streams,
+            },
+            counts: Default::default(),
+        }
+    }
+
+    fn key(i: u8) -> Key {
+        Key::Workload(format!("10.0.0.{i}"))
+    }
+
+    fn assert_rejected(res: Result<Admission, Rejected>, want: Limit) {
+        match res {
+            Err(rejected) => assert_eq!(rejected.limit, want),
+            Ok(_) => panic!("expected {want} to be exceeded"),
+        }
+    }
+
+    #[test]
+    fn max_connections() {
+        let cb = breakers(2, 0, 0);
+        let mut first = cb.admit(vec![key(1)], false).unwrap();
+        first.established();
+        let _second = cb.admit(vec![key(1)], false).unwrap();
+        assert_rejected(cb.admit(vec![key(1)], false), Limit::Connections);
+        // Other destinations are unaffected
+        let _other = cb.admit(vec![key(2)], false).unwrap();
+        drop(first);
+        let _third = cb.admit(vec![key(1)], false).unwrap();
+    }
+
+    #[test]
+    fn max_pending_connects() {
+        let cb = breakers(0, 1, 0);
+        let mut first = cb.admit(vec![key(1)], false).unwrap();
+        assert_rejected(cb.admit(vec![key(1)], false), Limit::PendingConnects);
+        first.established();
+        let _second = cb.admit(vec![key(1)], false).unwrap();
+    }
+
+    #[test]
+    fn max_hbone_streams() {
+        let cb = breakers(3, 0, 1);
+        let _first = cb.admit(vec![key(1)], true).unwrap();
+        assert_rejected(cb.admit(vec![key(1)], true), Limit::HboneStreams);
+        // Plain TCP connections to the same destination do not use a stream
+        let _tcp = cb.admit(vec![key(1)], false).unwrap();
+        let _tcp2 = cb.admit(vec![key(1)], false).unwrap();
+        assert_rejected(cb.admit(vec![key(1)], false), Limit::Connections);
+    }
+
+    #[test]
+    fn all_keys_must_admit() {
+        let svc = Key::Service(IpAddr::from([10, 96, 0, 1]));
+        let cb = breakers(1, 0, 0);
+        let _first = cb.admit(vec![key(1), svc.clone()], false).unwrap();
+        // The service is full, even though the workload is not
+        assert_rejected(cb.admit(vec![key(2), svc], false), Limit::Connections);
+        // A rejected connection reserves nothing
+        assert!(!cb.counts.lock().unwrap().contains_key(&key(2)));
+    }
+
+    #[test]
+    fn released_on_drop() {
+        let cb = breakers(1, 1, 1);
+        let mut conn = cb.admit(vec![key(1)], true).unwrap();
+        conn.established();
+        drop(conn);
+        assert!(cb.counts.lock().unwrap().is_empty());
+        drop(cb.admit(vec![key(1)], true).unwrap());
+        assert!(cb.counts.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,686 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use drain::Watch;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::metrics::traffic::{self, Reporter, RequestProtocol, SecurityPolicy};
+use crate::metrics::{IncrementRecorder, Metrics};
+use crate::proxy::outbound::RequestType;
+use crate::proxy::Error;
+use crate::rbac::{self, Authorization};
+use crate::socket;
+use crate::workload::{Protocol, Workload, WorkloadInformation};
+
+/// ConnectionTracker keeps a table of the connections the proxy is relaying, so that a drain can
+/// wait for them to complete, and they can be inspected while they run.
+#[derive(Clone, Default)]
+pub struct ConnectionTracker {
+    state: Arc<TrackerState>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    next_id: AtomicU64,
+    connections: Mutex<BTreeMap<u64, Arc<Entry>>>,
+    idle: Notify,
+}
+
+struct Entry {
+    start: SystemTime,
+    // Set once the connection is known well enough to report metrics for it.
+    info: Mutex<Option<ConnectionInfo>>,
+    sent: AtomicU64,
+    received: AtomicU64,
+    // The socket being relayed, and the bytes it had transferred before the relay started.
+    socket: Mutex<Option<(RawFd, (u64, u64))>>,
+    // The authorization check the connection passed, if it was subject to one.
+    authorized: Mutex<Option<rbac::Connection>>,
+    // The policy which denied the connection since it was established, if any.
+    revoked: Mutex<Option<String>>,
+    revoke: Notify,
+}
+
+/// ConnectionInfo describes a connection being relayed.
+#[derive(Clone)]
+pub(super) struct ConnectionInfo {
+    pub(super) open: traffic::ConnectionOpen,
+    /// The kind of outbound request, which is not known for inbound connections.
+    pub(super) request_type: Option<RequestType>,
+    /// The protocol the destination is reached with.
+    pub(super) protocol: Protocol,
+    /// The address the connection is sent to, for outbound connections.
+    pub(super) gateway: Option<SocketAddr>,
+}
+
+/// TrackedConnection represents a connection being relayed. It is removed from the table once
+/// dropped, so it should be held for as long as the relay runs.
+pub struct TrackedConnection {
+    id: u64,
+    entry: Arc<Entry>,
+    state: Arc<TrackerState>,
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        let mut connections = self.state.connections.lock().unwrap();
+        connections.remove(&self.id);
+        if connections.is_empty() {
+            self.state.idle.notify_waiters();
+        }
+    }
+}
+
+impl TrackedConnection {
+    /// describe records what the connection is, once it is known.
+    pub(super) fn describe(&self, info: ConnectionInfo) {
+        *self.entry.info.lock().unwrap() = Some(info);
+    }
+
+    /// relaying reports the bytes transferred over the TCP socket `fd` as the connection's own,
+    /// until the returned guard is dropped. `fd` must remain open until then.
+    pub fn relaying(&self, fd: RawFd) -> Relaying<'_> {
+        if let Ok(initial) = socket::bytes_transferred(fd) {
+            *self.entry.socket.lock().unwrap() = Some((fd, initial));
+        }
+        Relaying { entry: &self.entry }
+    }
+
+    /// transferred records the bytes sent from and received by the downstream so far.
+    pub fn transferred(&self, (sent, received): (u64, u64)) {
+        self.entry.sent.store(sent, Ordering::SeqCst);
+        self.entry.received.store(received, Ordering::SeqCst);
+    }
+
+    /// authorized records that `conn` was allowed by authorization policy, so that it is checked
+    /// again whenever policies change.
+    pub(super) fn authorized(&self, conn: rbac::Connection) {
+        *self.entry.authorized.lock().unwrap() = Some(conn);
+    }
+
+    /// revocation returns the policy which has denied the connection since it was authorized.
+    pub(super) fn revocation(&self) -> Option<String> {
+        self.entry.revoked.lock().unwrap().clone()
+    }
+
+    /// revoked completes once the connection is denied by a policy change, with the policy.
+    pub(super) async fn revoked(&self) -> String {
+        loop {
+            // Registered before checking, so a revocation in between is not missed
+            let revoke = self.entry.revoke.notified();
+            if let Some(policy) = self.revocation() {
+                return policy;
+            }
+            revoke.await;
+        }
+    }
+
+    /// revocable drives `relay` to completion, unless the connection is denied by a policy
+    /// change first.
+    pub(super) async fn revocable<T>(
+        &self,
+        relay: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        tokio::select! {
+            res = relay => res,
+            policy = self.revoked() => Err(Error::AuthorizationRevoked(policy)),
+        }
+    }
+}
+
+/// Relaying is returned by TrackedConnection::relaying.
+pub struct Relaying<'a> {
+    entry: &'a Entry,
+}
+
+impl Relaying<'_> {
+    /// transferred returns the bytes sent from and received by the downstream since relaying
+    /// started, as counted by the kernel. This is how much an interrupted relay copied.
+    pub fn transferred(&self) -> (u64, u64) {
+        self.entry.transferred()
+    }
+}
+
+impl Drop for Relaying<'_> {
+    fn drop(&mut self) {
+        *self.entry.socket.lock().unwrap() = None;
+    }
+}
+
+impl Entry {
+    fn transferred(&self) -> (u64, u64) {
+        // The lock is held while the socket is read, so it cannot be closed in the meantime
+        let socket = self.socket.lock().unwrap();
+        if let Some((fd, (initial_received, initial_sent))) = *socket {
+            // The socket is read from to send to the upstream, and written to with what it received
+            if let Ok((received, sent)) = socket::bytes_transferred(fd) {
+                return (
+                    received.saturating_sub(initial_received),
+                    sent.saturating_sub(initial_sent),
+                );
+            }
+        }
+        (
+            self.sent.load(Ordering::SeqCst),
+            self.received.load(Ordering::SeqCst),
+        )
+    }
+}
+
+impl ConnectionTracker {
+    pub fn track(&self) -> TrackedConnection {
+        let id = self.state.next_id.fetch_add(1, Ordering::SeqCst);
+        let entry = Arc::new(Entry {
+            start: SystemTime::now(),
+            info: Default::default(),
+            sent: Default::default(),
+            received: Default::default(),
+            socket: Default::default(),
+            authorized: Default::default(),
+            revoked: Default::default(),
+            revoke: Default::default(),
+        });
+        self.state
+            .connections
+            .lock()
+            .unwrap()
+            .insert(id, entry.clone());
+        TrackedConnection {
+            id,
+            entry,
+            state: self.state.clone(),
+        }
+    }
+
+    /// active returns the number of connections currently being relayed.
+    pub fn active(&self) -> usize {
+        self.state.connections.lock().unwrap().len()
+    }
+
+    /// idle completes once no connections are being relayed.
+    pub async fn idle(&self) {
+        loop {
+            // Registered before checking, so a connection closing in between is not missed
+            let idle = self.state.idle.notified();
+            if self.active() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// enforce_policies checks the authorized connections again as each authorization policy
+    /// change is received on `changes`, and closes those which are now denied. It stops once the
+    /// proxy has drained.
+    pub(super) async fn enforce_policies(
+        self,
+        workloads: WorkloadInformation,
+        metrics: Arc<Metrics>,
+        mut changes: broadcast::Receiver<Authorization>,
+        drain: Watch,
+    ) {
+        let tracker = self.clone();
+        let drained = async move {
+            drop(drain.signaled().await);
+            tracker.idle().await;
+        };
+        tokio::pin!(drained);
+        loop {
+            let changed = tokio::select! {
+                changed = changes.recv() => changed,
+                _ = &mut drained => return,
+            };
+            match changed {
+                Ok(policy) => self.reauthorize(&workloads, &metrics, Some(&policy)),
+                Err(RecvError::Lagged(missed)) => {
+                    // Without knowing which policies changed, every connection must be checked
+                    warn!(missed, "authorization policy changes were missed");
+                    self.reauthorize(&workloads, &metrics, None);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    /// reauthorize closes the connections denied by authorization policy since `changed` was
+    /// inserted or removed, or by any policy if the change is not known.
+    fn reauthorize(
+        &self,
+        workloads: &WorkloadInformation,
+        metrics: &Metrics,
+        changed: Option<&Authorization>,
+    ) {
+        let policy = changed.map_or_else(|| "unknown".to_string(), Authorization::to_key);
+        let connections: Vec<_> = self
+            .state
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        for entry in connections {
+            let Some(conn) = entry.authorized.lock().unwrap().clone() else {
+                continue;
+            };
+            if entry.revoked.lock().unwrap().is_some() || workloads.reassert_rbac(&conn, changed) {
+                continue;
+            }
+            info!(%conn, %policy, "closing connection denied by authorization policy change");
+            if let Some(info) = entry.info.lock().unwrap().as_ref() {
+                metrics.increment(&traffic::AuthorizationRevoked(&info.open));
+            }
+            *entry.revoked.lock().unwrap() = Some(policy.clone());
+            entry.revoke.notify_waiters();
+        }
+    }
+
+    /// dump returns the connections matching `filter`, in the order they were accepted.
+    /// Connections which are still being established are left out.
+    pub fn dump(&self, filter: &ConnectionFilter) -> Vec<ConnectionDump> {
+        let connections: Vec<_> = self
+            .state
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect();
+        connections
+            .into_iter()
+            .filter_map(|(id, entry)| {
+                let info = entry.info.lock().unwrap().clone()?;
+                filter
+                    .matches(&info)
+                    .then(|| ConnectionDump::new(id, &entry, info))
+            })
+            .collect()
+    }
+}
+
+/// ConnectionFilter selects connections by either of their ends. Unset fields match everything.
+#[derive(Default, Debug)]
+pub struct ConnectionFilter {
+    pub namespace: Option<String>,
+    /// workload matches the name of a workload, or of the workload that manages it.
+    pub workload: Option<String>,
+    pub identity: Option<String>,
+}
+
+impl ConnectionFilter {
+    fn matches(&self, info: &ConnectionInfo) -> bool {
+        let ends = [
+            PeerDump::source(&info.open),
+            PeerDump::from(info.open.destination.as_ref()),
+        ];
+        let matches = |want: &Option<String>, f: fn(&PeerDump) -> [Option<&String>; 2]| {
+            let Some(want) = want else { return true };
+            ends.iter()
+                .any(|end| f(end).into_iter().flatten().any(|v| v == want))
+        };
+        matches(&self.namespace, |e| [e.namespace.as_ref(), None])
+            && matches(&self.workload, |e| {
+                [e.name.as_ref(), e.workload_name.as_ref()]
+            })
+            && matches(&self.identity, |e| [e.identity.as_ref(), None])
+    }
+}
+
+#[derive(serde::Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerDump {
+    name: Option<String>,
+    namespace: Option<String>,
+    workload_name: Option<String>,
+    identity: Option<String>,
+}
+
+impl PeerDump {
+    // The source workload is preferred over what was derived from the connection, as for metrics.
+    fn source(open: &traffic::ConnectionOpen) -> PeerDump {
+        match (&open.source, &open.derived_source) {
+            (Some(w), _) => PeerDump::from(Some(w)),
+            (None, Some(d)) => PeerDump {
+                name: None,
+                namespace: d.namespace.clone(),
+                workload_name: d.workload_name.clone(),
+                identity: d.identity.as_ref().map(|i| i.to_string()),
+            },
+            (None, None) => PeerDump::default(),
+        }
+    }
+}
+
+impl From<Option<&Workload>> for PeerDump {
+    fn from(w: Option<&Workload>) -> Self {
+        let Some(w) = w else {
+            return PeerDump::default();
+        };
+        PeerDump {
+            name: Some(w.name.clone()),
+            namespace: Some(w.namespace.clone()),
+            workload_name: Some(w.workload_name.clone()),
+            identity: Some(w.identity().to_string()),
+        }
+    }
+}
+
+/// ConnectionDump is how a connection is shown on the connections admin endpoint.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionDump {
+    id: u64,
+    reporter: Reporter,
+    source: PeerDump,
+    destination: PeerDump,
+    destination_service: Option<String>,
+    request_type: Option<RequestType>,
+    protocol: Protocol,
+    request_protocol: RequestProtocol,
+    security_policy: SecurityPolicy,
+    gateway: Option<SocketAddr>,
+    start_time: String,
+    sent_bytes: u64,
+    received_bytes: u64,
+}
+
+impl ConnectionDump {
+    fn new(id: u64, entry: &Entry, info: ConnectionInfo) -> ConnectionDump {
+        use chrono::prelude::{DateTime, Utc};
+        let start: DateTime<Utc> = entry.start.into();
+        let (sent_bytes, received_bytes) = entry.transferred();
+        ConnectionDump {
+            id,
+            reporter: info.open.reporter,
+            source: PeerDump::source(&info.open),
+            destination: PeerDump::from(info.open.destination.as_ref()),
+            destination_service: info.open.destination_service.map(|s| s.hostname),
+            request_type: info.request_type,
+            protocol: info.protocol,
+            request_protocol: info.open.request_protocol,
+            security_policy: info.open.connection_security_policy,
+            gateway: info.gateway,
+            start_time: start.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            sent_bytes,
+            received_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use test_case::test_case;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::identity::Identity;
+
+    #[tokio::test]
+    async fn idle() {
+        let tracker = ConnectionTracker::default();
+        timeout(Duration::from_secs(1), tracker.idle())
+            .await
+            .expect("idle without connections");
+
+        let first = tracker.track();
+        let second = tracker.track();
+        assert_eq!(tracker.active(), 2);
+
+        let waiter = tracker.clone();
+        let idle = tokio::spawn(async move { waiter.idle().await });
+        drop(first);
+        assert_eq!(tracker.active(), 1);
+        tokio::task::yield_now().await;
+        assert!(!idle.is_finished());
+
+        drop(second);
+        assert_eq!(tracker.active(), 0);
+        timeout(Duration::from_secs(1), idle)
+            .await
+            .expect("idle once connections close")
+            .unwrap();
+    }
+
+    fn workload(name: &str, namespace: &str) -> Workload {
+        Workload {
+            name: name.to_string(),
+            namespace: namespace.to_string(),
+            workload_name: format!("{name}-deployment"),
+            service_account: name.to_string(),
+            trust_domain: "cluster.local".to_string(),
+            ..crate::test_helpers::test_default_workload()
+        }
+    }
+
+    fn info() -> ConnectionInfo {
+        ConnectionInfo {
+            open: traffic::ConnectionOpen {
+                reporter: Reporter::source,
+                source: Some(workload("client", "ns-a")),
+                derived_source: None,
+                destination: Some(workload("server", "ns-b")),
+                destination_service: None,
+                request_protocol: RequestProtocol::tcp,
+                connection_security_policy: SecurityPolicy::mutual_tls,
+            },
+            request_type: Some(RequestType::Direct),
+            protocol: Protocol::HBONE,
+            gateway: Some("127.0.0.2:15008".parse().unwrap()),
+        }
+    }
+
+    #[test_case(ConnectionFilter::default(), true; "unfiltered")]
+    #[test_case(ConnectionFilter{namespace: Some("ns-a".into()), ..Default::default()}, true; "source namespace")]
+    #[test_case(ConnectionFilter{namespace: Some("ns-b".into()), ..Default::default()}, true; "destination namespace")]
+    #[test_case(ConnectionFilter{namespace: Some("ns-c".into()), ..Default::default()}, false; "other namespace")]
+    #[test_case(ConnectionFilter{workload: Some("client".into()), ..Default::default()}, true; "workload name")]
+    #[test_case(ConnectionFilter{workload: Some("server-deployment".into()), ..Default::default()}, true; "managing workload name")]
+    #[test_case(ConnectionFilter{workload: Some("other".into()), ..Default::default()}, false; "other workload")]
+    #[test_case(ConnectionFilter{identity: Some("spiffe://cluster.local/ns/ns-b/sa/server".into()), ..Default::default()}, true; "identity")]
+    #[test_case(ConnectionFilter{identity: Some("spiffe://cluster.local/ns/ns-b/sa/client".into()), ..Default::default()}, false; "other identity")]
+    #[test_case(ConnectionFilter{namespace: Some("ns-a".into()), workload: Some("server".into()), ..Default::default()}, true; "either end")]
+    fn filter(filter: ConnectionFilter, matches: bool) {
+        assert_eq!(filter.matches(&info()), matches);
+    }
+
+    #[test]
+    fn derived_source() {
+        let mut info = info();
+        info.open.source = None;
+        info.open.derived_source = Some(traffic::DerivedWorkload {
+            namespace: Some("ns-a".into()),
+            identity: Some(Identity::default()),
+            ..Default::default()
+        });
+        assert_eq!(
+            PeerDump::source(&info.open),
+            PeerDump {
+                namespace: Some("ns-a".into()),
+                identity: Some(Identity::default().to_string()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn dump() {
+        let tracker = ConnectionTracker::default();
+        let pending = tracker.track();
+        let tracked = tracker.track();
+        tracked.describe(info());
+        tracked.transferred((10, 20));
+        assert_eq!(tracker.active(), 2);
+
+        // Connections still being established are not shown
+        let dump = tracker.dump(&ConnectionFilter::default());
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].id, 1);
+        assert_eq!(dump[0].gateway, Some("127.0.0.2:15008".parse().unwrap()));
+        assert_eq!((dump[0].sent_bytes, dump[0].received_bytes), (10, 20));
+
+        let filter = ConnectionFilter {
+            namespace: Some("ns-c".into()),
+            ..Default::default()
+        };
+        assert!(tracker.dump(&filter).is_empty());
+
+        drop(pending);
+        drop(tracked);
+        assert!(tracker.dump(&ConnectionFilter::default()).is_empty());
+    }
+
+    // Live bytes are read from TCP_INFO, which is only supported on Linux
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn relaying() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        // Bytes transferred before the relay starts are not counted
+        server.write_all(b"handshake").await.unwrap();
+        client.read_exact(&mut [0u8; 9]).await.unwrap();
+
+        let tracker = ConnectionTracker::default();
+        let tracked = tracker.track();
+        tracked.describe(info());
+        let relaying = tracked.relaying(std::os::unix::io::AsRawFd::as_raw_fd(&server));
+        client.write_all(b"hello").await.unwrap();
+        server.read_exact(&mut [0u8; 5]).await.unwrap();
+        server.write_all(b"world!").await.unwrap();
+        client.read_exact(&mut [0u8; 6]).await.unwrap();
+        // What was sent is counted once it is acknowledged, which may be delayed
+        let transferred = || {
+            let dump = &tracker.dump(&ConnectionFilter::default())[0];
+            (dump.sent_bytes, dump.received_bytes)
+        };
+        timeout(Duration::from_secs(1), async {
+            while transferred() != (5, 6) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("live bytes are reported");
+
+        // Once the relay completes, its totals are reported instead
+        drop(relaying);
+        tracked.transferred((1, 2));
+        assert_eq!(transferred(), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn enforce_policies() {
+        use crate::workload::WorkloadStore;
+        use crate::xds::istio::security::{
+            Action as XdsAction, Authorization as XdsAuthorization, Group, Match, Rules,
+            Scope as XdsScope,
+        };
+        use crate::xds::istio::workload::Workload as XdsWorkload;
+        use crate::xds::{Handler, XdsResource, XdsUpdate};
+
+        let store = WorkloadStore::test_store(vec![XdsWorkload {
+            addresses: vec![bytes::Bytes::copy_from_slice(&[127, 0, 0, 1])],
+            namespace: "ns".to_string(),
+            ..Default::default()
+        }])
+        .unwrap();
+        let store = Arc::new(Mutex::new(store));
+        let workloads = WorkloadInformation {
+            info: store.clone(),
+            demand: None,
+        };
+        let mut registry = prometheus_client::registry::Registry::default();
+        let metrics = Arc::new(Metrics::from(&mut registry));
+        let (_drain_tx, drain_rx) = drain::channel();
+
+        let tracker = ConnectionTracker::default();
+        let conn = |port: u16| rbac::Connection {
+            src_identity: None,
+            src_ip: "127.0.0.2".parse().unwrap(),
+            dst: SocketAddr::from(([127, 0, 0, 1], port)),
+        };
+        let denied = tracker.track();
+        denied.describe(info());
+        denied.authorized(conn(80));
+        let allowed = tracker.track();
+        allowed.authorized(conn(8080));
+        // Connections which were not subject to authorization are left alone
+        let unchecked = tracker.track();
+
+        let changes = workloads.policy_changes();
+        tokio::spawn(
+            tracker
+                .clone()
+                .enforce_policies(workloads, metrics, changes, drain_rx),
+        );
+        let deny = XdsAuthorization {
+            name: "deny-80".to_string(),
+            namespace: "ns".to_string(),
+            scope: XdsScope::Namespace as i32,
+            action: XdsAction::Deny as i32,
+            groups: vec![Group {
+                rules: vec![Rules {
+                    matches: vec![Match {
+                        destination_ports: vec![80],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+        };
+        let updated = Handler::<XdsAuthorization>::handle(
+            &store,
+            vec![XdsUpdate::Update(XdsResource {
+                name: "ns/deny-80".to_string(),
+                resource: deny,
+            })],
+        );
+        assert!(updated.is_ok());
+
+        let relay = std::future::pending::<Result<(), Error>>();
+        let res = timeout(Duration::from_secs(1), denied.revocable(relay))
+            .await
+            .expect("denied connection is closed");
+        assert!(
+            matches!(&res, Err(Error::AuthorizationRevoked(policy)) if policy == "ns/deny-80"),
+            "{res:?}"
+        );
+        assert_eq!(allowed.revocation(), None);
+        assert_eq!(unchecked.revocation(), None);
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, &registry).unwrap();
+        let revoked = encoded
+            .lines()
+            .filter(|l| l.starts_with("istio_tcp_connections_revoked_total{"))
+            .collect::<Vec<_>>();
+        assert!(
+            revoked.len() == 1 && revoked[0].ends_with(" 1"),
+            "{encoded}"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::net::SocketAddr;
+
+use drain::Watch;
+use hyper::http::uri::Authority;
+use hyper::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+use crate::proxy::outbound::{Handshake, OutboundConnection};
+use crate::proxy::{util, Error, ProxyInputs, TraceParent};
+use crate::socket;
+
+// The largest request head we accept. CONNECT requests carry few headers, so this is generous.
+const MAX_REQUEST_HEAD_SIZE: usize = 8192;
+
+/// HttpConnect is an explicit forward proxy for clients that support HTTP/1.1 CONNECT, such as
+/// those configured with HTTPS_PROXY. Like SOCKS5, only destinations in the mesh may be reached.
+pub(super) struct HttpConnect {
+    pi: ProxyInputs,
+    listener: TcpListener,
+    drain: Watch,
+}
+
+impl HttpConnect {
+    pub(super) async fn new(
+        pi: ProxyInputs,
+        addr: SocketAddr,
+        drain: Watch,
+    ) -> Result<HttpConnect, Error> {
+        let listener: TcpListener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| Error::Bind(addr, e))?;
+        pi.cfg.socket_options.apply(&listener)?;
+
+        info!(
+            address=%listener.local_addr().unwrap(),
+            component="http_connect",
+            "listener established",
+        );
+
+        Ok(HttpConnect {
+            pi,
+            listener,
+            drain,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    pub async fn run(self) {
+        let accept = async move {
+            loop {
+                // Asynchronously wait for an inbound socket.
+                let socket = self.listener.accept().await;
+                match socket {
+                    Ok((stream, remote)) => {
+                        info!("accepted outbound connection from {}", remote);
+                        let oc = OutboundConnection {
+                            pi: self.pi.clone(),
+                            id: TraceParent::new(),
+                        };
+                        tokio::spawn(async move {
+                            if let Err(err) = handle(oc, stream).await {
+                                warn!("http connect proxy failed: {}", err);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("Failed TCP handshake {}", e);
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                info!("http connect drained");
+            }
+        }
+    }
+}
+
+// handle serves a CONNECT request, then relays the connection to the requested destination. The
+// response is only sent once the outcome of connecting to the destination is known.
+async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(), Error> {
+    let remote_addr = socket::to_canonical(stream.peer_addr()?);
+    let request = match read_request_head(&mut stream).await? {
+        Some(head) => parse_request(&head),
+        None => Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+    };
+    let (host, port) = match request {
+        Ok(target) => target,
+        Err(status) => {
+            respond(&mut stream, status).await?;
+            return Err(Error::HttpStatus(status));
+        }
+    };
+    let Some(ip) = util::resolve(&oc.pi, &host).await else {
+        respond(&mut stream, StatusCode::NOT_FOUND).await?;
+        return Err(Error::HttpStatus(StatusCode::NOT_FOUND));
+    };
+    let target = SocketAddr::new(ip, port);
+
+    info!("accepted connect from {remote_addr} to {target}");
+    oc.proxy_to(
+        stream,
+        remote_addr.ip(),
+        target,
+        true,
+        Handshake::HttpConnect,
+    )
+    .await
+}
+
+// read_request_head reads the request line and headers, or returns None if they are too large.
+// They are read a byte at a time, so none of the data the client sends after the request is
+// consumed.
+async fn read_request_head(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut head = Vec::with_capacity(512);
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD_SIZE {
+            return Ok(None);
+        }
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+    Ok(Some(head))
+}
+
+// parse_request returns the host and port of a CONNECT request, or the status to reject it with.
+// Headers are ignored.
+fn parse_request(head: &[u8]) -> Result<(String, u16), StatusCode> {
+    let head = std::str::from_utf8(head).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+    }
+    if method != "CONNECT" {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let authority: Authority = target.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let port = authority.port_u16().ok_or(StatusCode::BAD_REQUEST)?;
+    // IPv6 addresses are enclosed in brackets
+    let host = authority.host();
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    Ok((host.to_string(), port))
+}
+
+/// respond sends the response to a CONNECT request. Any status other than 200 closes the
+/// connection.
+pub(super) async fn respond(stream: &mut TcpStream, status: StatusCode) -> Result<(), Error> {
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    );
+    if status != StatusCode::OK {
+        response.push_str("connection: close\r\ncontent-length: 0\r\n");
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// status returns the status to reject a CONNECT request with, when connecting to the destination
+/// failed with `e`.
+pub(super) fn status(e: &Error) -> StatusCode {
+    match e {
+        Error::UnknownDestination(_) => StatusCode::NOT_FOUND,
+        // Authorization policies reject connections from other nodes with 401
+        Error::HttpStatus(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        | Error::RbacRejected(_)
+        | Error::UnknownSource(_) => StatusCode::FORBIDDEN,
+        Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        Error::CircuitBreakerOpen(_) => StatusCode::SERVICE_UNAVAILABLE,
+        Error::Io(e) if e.kind() == io::ErrorKind::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("CONNECT echo.default.svc.cluster.local:80 HTTP/1.1\r\nhost: echo\r\n\r\n", Ok(("echo.default.svc.cluster.local", 80)); "hostname")]
+    #[test_case("CONNECT 10.0.0.1:443 HTTP/1.1\r\n\r\n", Ok(("10.0.0.1", 443)); "ipv4")]
+    #[test_case("CONNECT [::1]:443 HTTP/1.0\r\n\r\n", Ok(("::1", 443)); "ipv6")]
+    #[test_case("CONNECT 10.0.0.1 HTTP/1.1\r\n\r\n", Err(StatusCode::BAD_REQUEST); "no port")]
+    #[test_case("CONNECT 10.0.0.1:443\r\n\r\n", Err(StatusCode::BAD_REQUEST); "no version")]
+    #[test_case("GET http://10.0.0.1/ HTTP/1.1\r\n\r\n", Err(StatusCode::METHOD_NOT_ALLOWED); "not connect")]
+    #[test_case("CONNECT 10.0.0.1:443 HTTP/2\r\n\r\n", Err(StatusCode::HTTP_VERSION_NOT_SUPPORTED); "http2")]
+    fn parse_request(head: &str, expected: Result<(&str, u16), StatusCode>) {
+        let expected = expected.map(|(host, port)| (host.to_string(), port));
+        assert_eq!(super::parse_request(head.as_bytes()), expected);
+    }
+}
@@ -14,32 +14,142 @@
 
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::io;
 use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use drain::Watch;
+use hyper::header::{HeaderValue, ALT_SVC};
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::oneshot;
+use tokio_stream::StreamExt;
 use tracing::{debug, error, info, instrument, trace, trace_span, warn, Instrument};
 
 use crate::baggage::parse_baggage_header;
-use crate::config::Config;
+use crate::config::{self, Config};
 use crate::identity::SecretManager;
 use crate::metrics::traffic::{ConnectionOpen, Reporter};
 use crate::metrics::{traffic, Metrics, Recorder};
 use crate::proxy::inbound::InboundConnect::{DirectPath, Hbone};
-use crate::proxy::{ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER};
+use crate::proxy::{
+    proxy_protocol, udp, ConnectionInfo, ConnectionLimits, ConnectionTracker, PendingTunnel,
+    ProxyInputs, TraceParent, TrackedConnection, Tunnel, BAGGAGE_HEADER, TRACEPARENT_HEADER,
+};
+use crate::ratelimit::RateLimiter;
 use crate::rbac::Connection;
-use crate::socket::to_canonical;
+use crate::socket::{to_canonical, SocketOptions};
 use crate::tls::TlsError;
-use crate::workload::{Workload, WorkloadInformation};
+use crate::workload::{Protocol, Workload, WorkloadInformation};
 use crate::{proxy, rbac};
 
 use super::Error;
 
+/// NativeHboneForwarder forwards inbound connections to workloads that natively speak HBONE, as is.
+/// These workloads terminate HBONE, including mTLS, themselves.
+#[derive(Clone)]
+struct NativeHboneForwarder {
+    workloads: WorkloadInformation,
+    metrics: Arc<Metrics>,
+    limits: ConnectionLimits,
+    socket_options: SocketOptions,
+    enable_original_source: bool,
+    connections: ConnectionTracker,
+}
+
+impl NativeHboneForwarder {
+    /// divert forwards `stream` if it is addressed to a native HBONE workload, and otherwise returns
+    /// it for us to terminate. Only workloads already known are diverted, so accepting connections
+    /// never waits on the workload store.
+    fn divert(&self, stream: TcpStream) -> Option<TcpStream> {
+        let dst = crate::socket::orig_dst_addr_or_default(&stream);
+        let Some(wl) = self.workloads.find_native_hbone_workload(&dst.ip()) else {
+            return Some(stream);
+        };
+        let source = match stream.peer_addr() {
+            Ok(remote) => to_canonical(remote),
+            Err(e) => {
+                warn!(destination=%dst, "dropping native HBONE connection: {e}");
+                return None;
+            }
+        };
+        let forwarder = self.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = forwarder.forward(stream, source, dst, wl).await {
+                    warn!(%source, destination=%dst, "native HBONE forwarding failed: {e}");
+                }
+            }
+            .in_current_span(),
+        );
+        None
+    }
+
+    async fn forward(
+        &self,
+        mut stream: TcpStream,
+        source: SocketAddr,
+        dst: SocketAddr,
+        upstream: Workload,
+    ) -> Result<(), Error> {
+        // The workload serves HBONE on the standard port, regardless of the port we received it on
+        let target = SocketAddr::from((dst.ip(), config::HBONE_PORT));
+        info!(%source, destination=%target, "forwarding to native HBONE workload");
+        let tracked = self.connections.track();
+        let connection_metrics = traffic::ConnectionOpen {
+            reporter: Reporter::destination,
+            source: self.workloads.fetch_workload(&source.ip()).await,
+            derived_source: None,
+            destination: Some(upstream),
+            request_protocol: traffic::RequestProtocol::tcp,
+            connection_security_policy: traffic::SecurityPolicy::mutual_tls,
+            // The target port is only known inside the tunnel, so the service cannot be found
+            destination_service: None,
+        };
+        tracked.describe(ConnectionInfo {
+            open: connection_metrics.clone(),
+            request_type: None,
+            protocol: Protocol::HBONE,
+            gateway: None,
+        });
+        let mut _connection_close = self
+            .metrics
+            .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
+        let orig_src = self.enable_original_source.then_some(source.ip());
+        let mut outbound =
+            match super::freebind_connect(orig_src, target, &self.socket_options).await {
+                Ok(outbound) => outbound,
+                Err(e) => {
+                    _connection_close.update(|c| {
+                        c.set_response_flags(traffic::ResponseFlags::upstream_connection_failure)
+                    });
+                    return Err(e.into());
+                }
+            };
+        let transferred_bytes = traffic::BytesTransferred::from(&connection_metrics);
+        if let Err(e) = proxy::relay(
+            &mut stream,
+            &mut outbound,
+            self.limits,
+            &self.metrics,
+            transferred_bytes,
+            &tracked,
+        )
+        .await
+        {
+            _connection_close.update(|c| c.set_response_flags(e.response_flags()));
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
 pub(super) struct Inbound {
     cfg: Config,
     listener: TcpListener,
@@ -47,6 +157,12 @@ pub(super) struct Inbound {
     workloads: WorkloadInformation,
     drain: Watch,
     metrics: Arc<Metrics>,
+    rate_limiter: RateLimiter,
+    connections: ConnectionTracker,
+    #[cfg(feature = "quic")]
+    h3: Option<super::quic::InboundH3>,
+    // Advertises HTTP/3 in our HTTP/2 responses, if it is served.
+    alt_svc: Option<HeaderValue>,
 }
 
 impl Inbound {
@@ -54,6 +170,7 @@ impl Inbound {
         let listener: TcpListener = TcpListener::bind(pi.cfg.inbound_addr)
             .await
             .map_err(|e| Error::Bind(pi.cfg.inbound_addr, e))?;
+        pi.cfg.socket_options.apply(&listener)?;
         let transparent = super::maybe_set_transparent(&pi, &listener)?;
         // Override with our explicitly configured setting
         pi.cfg.enable_original_source = Some(transparent);
@@ -63,13 +180,34 @@ impl Inbound {
             transparent,
             "listener established",
         );
+        #[cfg(feature = "quic")]
+        let h3 = match pi.cfg.inbound_h3_addr {
+            Some(addr) => Some(super::quic::InboundH3::new(pi.clone(), addr, drain.clone()).await?),
+            None => None,
+        };
+        #[cfg(feature = "quic")]
+        let alt_svc = h3
+            .as_ref()
+            .map(|h3| super::quic::alt_svc(h3.address().port()));
+        #[cfg(not(feature = "quic"))]
+        let alt_svc = {
+            if let Some(addr) = pi.cfg.inbound_h3_addr {
+                warn!(address=%addr, "not serving HTTP/3, which requires the quic feature");
+            }
+            None
+        };
         Ok(Inbound {
             cfg: pi.cfg,
             workloads: pi.workloads,
             listener,
             cert_manager: pi.cert_manager,
             metrics: pi.metrics,
+            rate_limiter: pi.rate_limiter,
+            connections: pi.connections,
             drain,
+            #[cfg(feature = "quic")]
+            h3,
+            alt_svc,
         })
     }
 
@@ -78,6 +216,10 @@ impl Inbound {
     }
 
     pub(super) async fn run(self) {
+        #[cfg(feature = "quic")]
+        if let Some(h3) = self.h3 {
+            tokio::spawn(h3.run().in_current_span());
+        }
         let (tx, rx) = oneshot::channel();
         let service = make_service_fn(|socket: &tokio_boring::SslStream<TcpStream>| {
             let dst = crate::socket::orig_dst_addr_or_default(socket.get_ref());
@@ -92,16 +234,38 @@ impl Inbound {
             let workloads = self.workloads.clone();
             debug!(%conn, "accepted connection");
             let enable_original_source = self.cfg.enable_original_source;
+            // The destination service is not known for inbound connections, so global limits apply
+            let limits = ConnectionLimits::new(&self.cfg, None);
+            let socket_options = self.cfg.socket_options;
+            let udp_idle_timeout = self.cfg.udp_idle_timeout;
             let metrics = self.metrics.clone();
+            let rate_limiter = self.rate_limiter.clone();
+            let connections = self.connections.clone();
+            let alt_svc = self.alt_svc.clone();
             async move {
-                Ok::<_, hyper::Error>(service_fn(move |req| {
-                    Self::serve_connect(
+                Ok::<_, hyper::Error>(service_fn(move |mut req| {
+                    let tunnel = Self::upgrade(&mut req);
+                    let response = Self::serve_connect(
                         workloads.clone(),
                         conn.clone(),
                         enable_original_source.unwrap_or_default(),
+                        limits,
+                        socket_options,
+                        udp_idle_timeout,
                         req,
+                        tunnel,
                         metrics.clone(),
-                    )
+                        rate_limiter.clone(),
+                        connections.clone(),
+                    );
+                    let alt_svc = alt_svc.clone();
+                    async move {
+                        let mut response = response.await?;
+                        if let Some(alt_svc) = alt_svc {
+                            response.headers_mut().insert(ALT_SVC, alt_svc);
+                        }
+                        Ok::<_, hyper::Error>(response)
+                    }
                 }))
             }
         });
@@ -110,7 +274,24 @@ impl Inbound {
             workloads: self.workloads.clone(),
             cert_manager: self.cert_manager.clone(),
         };
-        let tls_stream = crate::hyper_util::tls_server(acceptor, self.listener);
+        let forwarder = NativeHboneForwarder {
+            workloads: self.workloads.clone(),
+            metrics: self.metrics.clone(),
+            limits: ConnectionLimits::new(&self.cfg, None),
+            socket_options: self.cfg.socket_options,
+            enable_original_source: self.cfg.enable_original_source.unwrap_or_default(),
+            connections: self.connections.clone(),
+        };
+        // Connections are accepted as usual, except those to native HBONE workloads, which are
+        // forwarded before TLS is terminated.
+        let mut incoming = AddrIncoming::from_listener(self.listener).expect("server bind");
+        incoming.set_nodelay(true);
+        let accepted = futures::stream::poll_fn(move |cx| Pin::new(&mut incoming).poll_accept(cx))
+            .filter_map(move |conn| match conn {
+                Ok(stream) => forwarder.divert(stream.into_inner()).map(Ok),
+                Err(e) => Some(Err(e)),
+            });
+        let tls_stream = crate::hyper_util::tls_server_from_stream(acceptor, accepted);
         let incoming = hyper::server::accept::from_stream(tls_stream);
 
         let server = Server::builder(incoming)
@@ -118,7 +299,11 @@ impl Inbound {
             .http2_initial_stream_window_size(self.cfg.window_size)
             .http2_initial_connection_window_size(self.cfg.connection_window_size)
             .http2_max_frame_size(self.cfg.frame_size)
+            // Allows extended CONNECT, used to tunnel UDP
+            .http2_enable_connect_protocol()
             .serve(service)
+            // Once drained, hyper sends a GOAWAY on every connection, so peers stop opening streams
+            // on them, and closes each once its streams complete.
             .with_graceful_shutdown(async {
                 // Wait until the drain is signaled
                 let shutdown = self.drain.signaled().await;
@@ -141,17 +326,23 @@ impl Inbound {
         }
     }
 
-    /// handle_inbound serves an inbound connection with a target address `addr`.
+    /// handle_inbound serves an inbound connection with a target address `addr`. If set,
+    /// `proxy_header` is sent to the workload before any data from the downstream.
+    #[allow(clippy::too_many_arguments)]
     pub(super) async fn handle_inbound(
         request_type: InboundConnect,
         orig_src: Option<IpAddr>,
         addr: SocketAddr,
+        proxy_header: Option<Vec<u8>>,
         metrics: Arc<Metrics>,
         connection_metrics: ConnectionOpen,
         extra_connection_metrics: Option<ConnectionOpen>,
+        limits: ConnectionLimits,
+        socket_options: SocketOptions,
+        tracked: TrackedConnection,
     ) -> Result<(), std::io::Error> {
         let start = Instant::now();
-        let stream = super::freebind_connect(orig_src, addr).await;
+        let stream = super::freebind_connect(orig_src, addr, &socket_options).await;
         match stream {
             Err(err) => {
                 warn!(dur=?start.elapsed(), "connection to {} failed: {}", addr, err);
@@ -161,24 +352,29 @@ impl Inbound {
                 let mut stream = stream;
                 stream.set_nodelay(true)?;
                 trace!(dur=?start.elapsed(), "connected to: {addr}");
+                if let Some(header) = proxy_header {
+                    stream.write_all(&header).await?;
+                }
                 tokio::task::spawn(
                     (async move {
-                        let _connection_close = metrics
+                        let mut _connection_close = metrics
                             .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
 
-                        let _extra_conn_close = extra_connection_metrics
+                        let mut _extra_conn_close = extra_connection_metrics
                             .as_ref()
                             .map(|co| metrics.increment_defer::<_, traffic::ConnectionClose>(co));
 
                         let transferred_bytes =
                             traffic::BytesTransferred::from(&connection_metrics);
-                        match request_type {
+                        let res = match request_type {
                             DirectPath(mut incoming) => {
                                 match proxy::relay(
                                     &mut incoming,
                                     &mut stream,
+                                    limits,
                                     &metrics,
                                     transferred_bytes,
+                                    &tracked,
                                 )
                                 .await
                                 {
@@ -189,31 +385,54 @@ impl Inbound {
                                                 transferred,
                                             );
                                         }
+                                        Ok(())
+                                    }
+                                    Err(e) if e.is_deliberate_close() => {
+                                        info!(dur=?start.elapsed(), "internal server copy: {}", e);
+                                        Err(e)
                                     }
                                     Err(e) => {
-                                        error!(dur=?start.elapsed(), "internal server copy: {}", e)
+                                        error!(dur=?start.elapsed(), "internal server copy: {}", e);
+                                        Err(e)
                                     }
                                 }
                             }
-                            Hbone(req) => match hyper::upgrade::on(req).await {
+                            Hbone(tunnel) => match tunnel.await {
                                 Ok(mut upgraded) => {
-                                    if let Err(e) = super::copy_hbone(
+                                    let res = super::copy_hbone(
                                         &mut upgraded,
                                         &mut stream,
+                                        limits,
                                         &metrics,
                                         transferred_bytes,
+                                        &tracked,
                                     )
                                     .instrument(trace_span!("hbone server"))
-                                    .await
-                                    {
-                                        error!(dur=?start.elapsed(), "hbone server copy: {}", e);
+                                    .await;
+                                    match &res {
+                                        Err(e) if e.is_deliberate_close() => {
+                                            info!(dur=?start.elapsed(), "hbone server copy: {}", e);
+                                        }
+                                        Err(e) => {
+                                            error!(dur=?start.elapsed(), "hbone server copy: {}", e);
+                                        }
+                                        Ok(_) => {}
                                     }
+                                    res
                                 }
                                 Err(e) => {
                                     // Not sure if this can even happen
                                     error!(dur=?start.elapsed(), "No upgrade {e}");
+                                    Ok(())
                                 }
                             },
+                        };
+                        if let Err(e) = res {
+                            let flags = e.response_flags();
+                            _connection_close.update(|c| c.set_response_flags(flags));
+                            if let Some(cc) = _extra_conn_close.as_mut() {
+                                cc.update(|c| c.set_response_flags(flags));
+                            }
                         }
                     })
                     .in_current_span(),
@@ -226,6 +445,17 @@ impl Inbound {
         }
     }
 
+    // upgrade returns the tunnel of an HTTP/2 request, which is available once it is responded to.
+    fn upgrade(req: &mut Request<Body>) -> PendingTunnel {
+        let upgrade = hyper::upgrade::on(req);
+        Box::pin(async move {
+            match upgrade.await {
+                Ok(upgraded) => Ok(Box::new(upgraded) as Box<dyn Tunnel>),
+                Err(e) => Err(io::Error::other(e)),
+            }
+        })
+    }
+
     fn extract_traceparent(req: &Request<Body>) -> TraceParent {
         req.headers()
             .get(TRACEPARENT_HEADER)
@@ -239,27 +469,43 @@ impl Inbound {
         peer_ip=%conn.src_ip,
         peer_id=%OptionDisplay(&conn.src_identity)
     ))]
-    async fn serve_connect(
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn serve_connect(
         workloads: WorkloadInformation,
         conn: rbac::Connection,
         enable_original_source: bool,
+        limits: ConnectionLimits,
+        socket_options: SocketOptions,
+        udp_idle_timeout: Duration,
         req: Request<Body>,
+        tunnel: PendingTunnel,
         metrics: Arc<Metrics>,
+        rate_limiter: RateLimiter,
+        connections: ConnectionTracker,
     ) -> Result<Response<Body>, hyper::Error> {
         match req.method() {
             &Method::CONNECT => {
                 let uri = req.uri();
                 info!("got {} request to {}", req.method(), uri);
-                let addr: Result<SocketAddr, _> = uri.to_string().as_str().parse();
-                if addr.is_err() {
-                    info!("Sending 400, {:?}", addr.err());
+                let udp = req
+                    .extensions()
+                    .get::<hyper::ext::Protocol>()
+                    .map(|p| p.as_str() == udp::CONNECT_UDP_PROTOCOL);
+                let addr = match udp {
+                    // A UDP tunnel carries its target in the path
+                    Some(true) => udp::parse_connect_udp_path(uri.path()),
+                    // We do not support any other extended CONNECT protocols
+                    Some(false) => None,
+                    None => uri.to_string().as_str().parse().ok(),
+                };
+                let Some(addr) = addr else {
+                    info!("Sending 400, invalid target {uri}");
                     return Ok(Response::builder()
                         .status(hyper::StatusCode::BAD_REQUEST)
                         .body(Body::empty())
                         .unwrap());
-                }
-
-                let addr: SocketAddr = addr.unwrap();
+                };
+                let udp = udp.unwrap_or_default();
                 if addr.ip() != conn.dst.ip() {
                     info!("Sending 400, ip mismatch {addr} != {}", conn.dst);
                     return Ok(Response::builder()
@@ -278,6 +524,8 @@ impl Inbound {
                 };
                 let (has_waypoint, from_waypoint) =
                     Self::check_waypoint(&workloads, &upstream, &conn).await;
+                // Connections from our waypoint were authorized by the waypoint instead
+                let authorized = (!from_waypoint).then(|| conn.clone());
 
                 if from_waypoint {
                     debug!("request from waypoint, skipping policy");
@@ -309,6 +557,35 @@ impl Inbound {
                     parse_baggage_header(req.headers().get_all(BAGGAGE_HEADER)).unwrap_or_default();
                 // Find source info. We can lookup by XDS or from connection attributes
                 let source = workloads.fetch_workload(&source_ip).await;
+                // Connections from our waypoint are limited by the waypoint's own ztunnel instead
+                let rate_limited = match (&conn.src_identity, from_waypoint) {
+                    (Some(identity), false) => rate_limiter.check(identity, source.as_ref()),
+                    _ => Ok(()),
+                };
+                if let Err(e) = &rate_limited {
+                    info!(%conn, "{e}");
+                }
+                let proxy_header = if upstream.wants_proxy_protocol(addr.port()) {
+                    // Unless the source node preserves it, the connection comes from the node
+                    // rather than the source workload. The Forwarded header names the workload, and
+                    // is trusted if that workload has the identity the peer presented.
+                    let orig_src = match super::get_original_src_from_fwded(&req) {
+                        Some(ip) if ip != source_ip => {
+                            let fwded_identity =
+                                workloads.fetch_workload(&ip).await.map(|w| w.identity());
+                            Some(ip).filter(|_| fwded_identity == conn.src_identity)
+                        }
+                        _ => None,
+                    };
+                    // HBONE does not carry the source port
+                    Some(proxy_protocol::header(
+                        SocketAddr::new(orig_src.unwrap_or(source_ip), 0),
+                        addr,
+                        conn.src_identity.as_ref(),
+                    ))
+                } else {
+                    None
+                };
                 let derived_source = traffic::DerivedWorkload {
                     identity: conn.src_identity,
                     cluster_id: baggage.cluster_id,
@@ -321,19 +598,59 @@ impl Inbound {
                     reporter: Reporter::destination,
                     source,
                     derived_source: Some(derived_source),
+                    destination_service: workloads.find_destination_service(&upstream, addr.port()),
                     destination: Some(upstream),
+                    request_protocol: if udp {
+                        traffic::RequestProtocol::udp
+                    } else {
+                        traffic::RequestProtocol::tcp
+                    },
                     connection_security_policy: traffic::SecurityPolicy::mutual_tls,
-                    destination_service: None,
-                    destination_service_namespace: None,
-                    destination_service_name: None,
                 };
+                let tracked = connections.track();
+                tracked.describe(ConnectionInfo {
+                    open: connection_metrics.clone(),
+                    request_type: None,
+                    protocol: Protocol::HBONE,
+                    gateway: None,
+                });
+                if let Some(conn) = authorized {
+                    tracked.authorized(conn);
+                }
+                if let Err(e) = rate_limited {
+                    let e = Error::from(e);
+                    metrics
+                        .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics)
+                        .update(|c| c.set_response_flags(e.response_flags()));
+                    return Ok(Response::builder()
+                        .status(StatusCode::TOO_MANY_REQUESTS)
+                        .body(Body::empty())
+                        .unwrap());
+                }
+                if udp {
+                    return Ok(Self::serve_udp(
+                        tunnel,
+                        enable_original_source.then_some(source_ip),
+                        addr,
+                        udp_idle_timeout,
+                        metrics,
+                        connection_metrics,
+                        tracked,
+                    )
+                    .in_current_span()
+                    .await);
+                }
                 let status_code = match Self::handle_inbound(
-                    Hbone(req),
+                    Hbone(tunnel),
                     enable_original_source.then_some(source_ip),
                     addr,
+                    proxy_header,
                     metrics,
                     connection_metrics,
                     None,
+                    limits,
+                    socket_options,
+                    tracked,
                 )
                 .in_current_span()
                 .await
@@ -358,6 +675,54 @@ impl Inbound {
         }
     }
 
+    /// serve_udp serves a request for a UDP tunnel to `addr`, relaying its datagrams to the
+    /// workload once the tunnel is established.
+    async fn serve_udp(
+        tunnel: PendingTunnel,
+        orig_src: Option<IpAddr>,
+        addr: SocketAddr,
+        idle_timeout: Duration,
+        metrics: Arc<Metrics>,
+        connection_metrics: ConnectionOpen,
+        tracked: TrackedConnection,
+    ) -> Response<Body> {
+        let socket = match udp::connect(orig_src, addr).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("udp connection to {addr} failed: {e}");
+                return Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        };
+        tokio::task::spawn(
+            async move {
+                match tunnel.await {
+                    Ok(tunnel) => {
+                        udp::serve_tunnel(
+                            tunnel,
+                            socket,
+                            idle_timeout,
+                            metrics,
+                            connection_metrics,
+                            tracked,
+                        )
+                        .instrument(trace_span!("udp server"))
+                        .await
+                    }
+                    Err(e) => error!("No upgrade {e}"),
+                }
+            }
+            .in_current_span(),
+        );
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(udp::CAPSULE_PROTOCOL_HEADER, "?1")
+            .body(Body::empty())
+            .unwrap()
+    }
+
     async fn check_waypoint(
         workloads: &WorkloadInformation,
         upstream: &Workload,
@@ -389,8 +754,9 @@ pub(super) enum InboundConnect {
     /// Rather than doing a full HBONE connection over the localhost network, we just pass the outbound
     /// context directly to the inbound handling in memory.
     DirectPath(TcpStream),
-    /// Hbone is a standard HBONE request coming from the network.
-    Hbone(Request<Body>),
+    /// Hbone is a standard HBONE request coming from the network, whose tunnel is available once
+    /// the request is responded to.
+    Hbone(PendingTunnel),
 }
 
 #[derive(Clone)]
@@ -14,28 +14,36 @@
 
 use std::net::SocketAddr;
 
+use drain::Watch;
 use tokio::net::{TcpListener, TcpStream};
 use tracing::{error, info, trace, warn, Instrument};
 
 use crate::config::ProxyMode;
 use crate::metrics::traffic;
 use crate::metrics::traffic::Reporter;
-use crate::proxy::outbound::OutboundConnection;
-use crate::proxy::{util, ProxyInputs};
-use crate::proxy::{Error, TraceParent};
-use crate::rbac;
+use crate::metrics::IncrementRecorder;
+use crate::proxy::outbound::{Handshake, OutboundConnection};
+use crate::proxy::{proxy_protocol, util, ProxyInputs};
+use crate::proxy::{ConnectionInfo, ConnectionLimits, Error, TraceParent};
+use crate::rbac::{self, MtlsMode};
+use crate::workload::Protocol;
 use crate::{proxy, socket};
 
 pub(super) struct InboundPassthrough {
     listener: TcpListener,
     pi: ProxyInputs,
+    drain: Watch,
 }
 
 impl InboundPassthrough {
-    pub(super) async fn new(mut pi: ProxyInputs) -> Result<InboundPassthrough, Error> {
+    pub(super) async fn new(
+        mut pi: ProxyInputs,
+        drain: Watch,
+    ) -> Result<InboundPassthrough, Error> {
         let listener: TcpListener = TcpListener::bind(pi.cfg.inbound_plaintext_addr)
             .await
             .map_err(|e| Error::Bind(pi.cfg.inbound_plaintext_addr, e))?;
+        pi.cfg.socket_options.apply(&listener)?;
         let transparent = super::maybe_set_transparent(&pi, &listener)?;
         // Override with our explicitly configured setting
         pi.cfg.enable_original_source = Some(transparent);
@@ -46,52 +54,87 @@ impl InboundPassthrough {
             transparent,
             "listener established",
         );
-        Ok(InboundPassthrough { listener, pi })
+        Ok(InboundPassthrough {
+            listener,
+            pi,
+            drain,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
     }
 
     pub(super) async fn run(self) {
-        loop {
-            // Asynchronously wait for an inbound socket.
-            let socket = self.listener.accept().await;
-            let pi = self.pi.clone();
-            match socket {
-                Ok((stream, remote)) => {
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::proxy_inbound_plaintext(
-                            pi, // pi cloned above; OK to move
-                            socket::to_canonical(remote),
-                            stream,
-                        )
-                        .await
-                        {
-                            warn!(source=%socket::to_canonical(remote), component="inbound plaintext", "proxying failed: {}", e)
+        let accept = async move {
+            loop {
+                // Asynchronously wait for an inbound socket.
+                let socket = self.listener.accept().await;
+                let pi = self.pi.clone();
+                match socket {
+                    Ok((stream, remote)) => {
+                        tokio::spawn(async move {
+                            match Self::proxy_inbound_plaintext(
+                                pi, // pi cloned above; OK to move
+                                stream,
+                            )
+                            .await
+                            {
+                                Err(e) if e.is_deliberate_close() => {
+                                    info!(source=%socket::to_canonical(remote), component="inbound plaintext", "proxying closed: {}", e)
+                                }
+                                Err(e) => {
+                                    warn!(source=%socket::to_canonical(remote), component="inbound plaintext", "proxying failed: {}", e)
+                                }
+                                Ok(()) => {}
+                            }
+                        }.in_current_span());
+                    }
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
                         }
-                    }.in_current_span());
-                }
-                Err(e) => {
-                    if util::is_runtime_shutdown(&e) {
-                        return;
+                        error!("Failed TCP handshake {}", e);
                     }
-                    error!("Failed TCP handshake {}", e);
                 }
             }
+        };
+
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                info!("inbound plaintext drained");
+            }
         }
     }
 
-    async fn proxy_inbound_plaintext(
-        pi: ProxyInputs,
-        source: SocketAddr,
-        mut inbound: TcpStream,
-    ) -> Result<(), Error> {
-        let orig = socket::orig_dst_addr_or_default(&inbound);
+    async fn proxy_inbound_plaintext(pi: ProxyInputs, mut inbound: TcpStream) -> Result<(), Error> {
+        let (source, orig) = proxy_protocol::accept(&pi.cfg, &mut inbound).await?;
         // Check if it is a recursive call when proxy mode is Node.
         if pi.cfg.proxy_mode == ProxyMode::Shared && Some(orig.ip()) == pi.cfg.local_ip {
             return Err(Error::SelfCall);
         }
         info!(%source, destination=%orig, component="inbound plaintext", "accepted connection");
         let Some(upstream) = pi.workloads.fetch_workload(&orig.ip()).await else {
-            return Err(Error::UnknownDestination(orig.ip()))
+            return Err(Error::UnknownDestination(orig.ip()));
         };
+        if pi.workloads.mtls_mode(&upstream, orig.port()) == MtlsMode::Strict {
+            info!(%source, destination=%orig, component="inbound plaintext", "rejected plaintext connection: destination requires mTLS");
+            let connection_metrics = traffic::ConnectionOpen {
+                reporter: Reporter::destination,
+                source: pi.workloads.fetch_workload(&source.ip()).await,
+                derived_source: None,
+                destination_service: pi
+                    .workloads
+                    .find_destination_service(&upstream, orig.port()),
+                destination: Some(upstream),
+                request_protocol: traffic::RequestProtocol::tcp,
+                connection_security_policy: traffic::SecurityPolicy::unknown,
+            };
+            pi.metrics
+                .increment(&traffic::PlaintextRejected(&connection_metrics));
+            return Ok(());
+        }
         if !upstream.waypoint_addresses.is_empty() {
             // This is an inbound request not over HBONE, but we have a waypoint.
             // The request needs to go through the waypoint for policy enforcement.
@@ -105,7 +148,9 @@ impl InboundPassthrough {
             // Spoofing the source IP only works when the destination or the source are on our node.
             // In this case, the source and the destination might both be remote, so we need to disable it.
             oc.pi.cfg.enable_original_source = Some(false);
-            return oc.proxy_to(inbound, source.ip(), orig, false).await;
+            return oc
+                .proxy_to(inbound, source.ip(), orig, false, Handshake::None)
+                .await;
         }
 
         // We enforce RBAC only for non-hairpin cases. This is because we may not be able to properly
@@ -121,42 +166,60 @@ impl InboundPassthrough {
             info!(%conn, "RBAC rejected");
             return Ok(());
         }
-        let source_ip = super::get_original_src_from_stream(&inbound);
+        let source_ip = source.ip();
         let orig_src = pi
             .cfg
             .enable_original_source
             .unwrap_or_default()
-            .then_some(source_ip)
-            .flatten();
+            .then_some(source_ip);
         trace!(%source, destination=%orig, component="inbound plaintext", "connect to {orig:?} from {orig_src:?}");
-        let mut outbound = super::freebind_connect(orig_src, orig).await?;
+        let mut outbound = super::freebind_connect(orig_src, orig, &pi.cfg.socket_options).await?;
         trace!(%source, destination=%orig, component="inbound plaintext", "connected");
 
         // Find source info. We can lookup by XDS or from connection attributes
-        let source_workload = if let Some(source_ip) = source_ip {
-            pi.workloads.fetch_workload(&source_ip).await
-        } else {
-            None
-        };
+        let source_workload = pi.workloads.fetch_workload(&source_ip).await;
         let derived_source = traffic::DerivedWorkload {
-            identity: conn.src_identity,
+            identity: conn.src_identity.clone(),
             ..Default::default()
         };
         let connection_metrics = traffic::ConnectionOpen {
             reporter: Reporter::destination,
             source: source_workload,
             derived_source: Some(derived_source),
+            destination_service: pi
+                .workloads
+                .find_destination_service(&upstream, orig.port()),
             destination: Some(upstream),
+            request_protocol: traffic::RequestProtocol::tcp,
             connection_security_policy: traffic::SecurityPolicy::unknown,
-            destination_service: None,
-            destination_service_namespace: None,
-            destination_service_name: None,
         };
-        let _connection_close = pi
+        let tracked = pi.connections.track();
+        tracked.describe(ConnectionInfo {
+            open: connection_metrics.clone(),
+            request_type: None,
+            protocol: Protocol::TCP,
+            gateway: None,
+        });
+        tracked.authorized(conn);
+        let mut _connection_close = pi
             .metrics
             .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
         let transferred_bytes = traffic::BytesTransferred::from(&connection_metrics);
-        proxy::relay(&mut outbound, &mut inbound, &pi.metrics, transferred_bytes).await?;
+        // The destination service is not known for inbound connections, so global limits apply
+        let limits = ConnectionLimits::new(&pi.cfg, None);
+        if let Err(e) = proxy::relay(
+            &mut outbound,
+            &mut inbound,
+            limits,
+            &pi.metrics,
+            transferred_bytes,
+            &tracked,
+        )
+        .await
+        {
+            _connection_close.update(|c| c.set_response_flags(e.response_flags()));
+            return Err(e);
+        }
         info!(%source, destination=%orig, component="inbound plaintext", "connection complete");
         Ok(())
     }
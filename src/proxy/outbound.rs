@@ -17,19 +17,26 @@ use std::time::Instant;
 
 use boring::ssl::ConnectConfiguration;
 use drain::Watch;
+use hyper::client::conn::SendRequest;
 use hyper::header::FORWARDED;
 use hyper::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
 use tracing::{debug, error, info, info_span, trace, trace_span, warn, Instrument};
 
 use crate::config::ProxyMode;
 use crate::identity::Identity;
 use crate::metrics::traffic;
-use crate::metrics::traffic::Reporter;
+use crate::metrics::traffic::{ConnectRetryReason, Reporter};
+use crate::metrics::IncrementRecorder;
 use crate::proxy::inbound::{Inbound, InboundConnect};
-use crate::proxy::{util, Error, ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER};
-use crate::workload::{Protocol, Workload};
-use crate::{proxy, rbac, socket};
+use crate::proxy::{
+    circuit_breaker, http_connect, pool, proxy_protocol, udp, util, ConnectionInfo,
+    ConnectionLimits, Error, ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER,
+};
+use crate::rbac::MtlsMode;
+use crate::workload::{Protocol, Workload, WorkloadInformation};
+use crate::{proxy, rbac};
 
 pub struct Outbound {
     pi: ProxyInputs,
@@ -42,6 +49,7 @@ impl Outbound {
         let listener: TcpListener = TcpListener::bind(pi.cfg.outbound_addr)
             .await
             .map_err(|e| Error::Bind(pi.cfg.outbound_addr, e))?;
+        pi.cfg.socket_options.apply(&listener)?;
         let transparent = super::maybe_set_transparent(&pi, &listener)?;
         // Override with our explicitly configured setting
         pi.cfg.enable_original_source = Some(transparent);
@@ -81,6 +89,7 @@ impl Outbound {
                                 let res = oc.proxy(stream).await;
                                 match res {
                                     Ok(_) => info!(dur=?start_outbound_instant.elapsed(), "complete"),
+                                    Err(e) if e.is_deliberate_close() => info!(dur=?start_outbound_instant.elapsed(), err=%e, "closed"),
                                     Err(e) => warn!(dur=?start_outbound_instant.elapsed(), err=%e, "failed")
                                 };
                             })
@@ -97,9 +106,8 @@ impl Outbound {
             }
         }.in_current_span();
 
-        // Stop accepting once we drain.
-        // Note: we are *not* waiting for all connections to be closed. In the future, we may consider
-        // this, but will need some timeout period, as we have no back-pressure mechanism on connections.
+        // Stop accepting once we drain. Connections already accepted are tracked, and the drain
+        // waits for them to complete, up to the termination grace period.
         tokio::select! {
             res = accept => { res }
             _ = self.drain.signaled() => {
@@ -115,10 +123,10 @@ pub(super) struct OutboundConnection {
 }
 
 impl OutboundConnection {
-    async fn proxy(&mut self, stream: TcpStream) -> Result<(), Error> {
-        let peer = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
-        let orig_dst_addr = socket::orig_dst_addr_or_default(&stream);
-        self.proxy_to(stream, peer.ip(), orig_dst_addr, false).await
+    async fn proxy(&mut self, mut stream: TcpStream) -> Result<(), Error> {
+        let (peer, orig_dst_addr) = proxy_protocol::accept(&self.pi.cfg, &mut stream).await?;
+        self.proxy_to(stream, peer.ip(), orig_dst_addr, false, Handshake::None)
+            .await
     }
 
     pub async fn proxy_to(
@@ -127,98 +135,290 @@ impl OutboundConnection {
         remote_addr: IpAddr,
         orig_dst_addr: SocketAddr,
         block_passthrough: bool,
+        handshake: Handshake,
     ) -> Result<(), Error> {
         if self.pi.cfg.proxy_mode == ProxyMode::Shared
             && Some(orig_dst_addr.ip()) == self.pi.cfg.local_ip
         {
-            return Err(Error::SelfCall);
-        }
-        let req = self.build_request(remote_addr, orig_dst_addr).await?;
-        debug!(
-            "request from {} to {} via {} type {:#?} dir {:#?}",
-            req.source.name, orig_dst_addr, req.gateway, req.request_type, req.direction
-        );
-        if block_passthrough && req.destination_workload.is_none() {
-            // This is mostly used by socks5. For typical outbound calls, we need to allow calls to arbitrary
-            // domains. But for socks5
-            return Err(Error::UnknownDestination(req.destination.ip()));
+            return Err(handshake.reject(&mut stream, Error::SelfCall).await);
         }
-        let can_fastpath = self.pi.cfg.proxy_mode == ProxyMode::Shared
-            && req.protocol == Protocol::HBONE
-            && !req
-                .destination_workload
-                .as_ref()
-                .map(|w| w.native_hbone)
-                .unwrap_or(false);
-        let connection_metrics = traffic::ConnectionOpen {
-            reporter: Reporter::source,
-            derived_source: None,
-            source: Some(req.source.clone()),
-            destination: req.destination_workload.clone(),
-            connection_security_policy: if req.protocol == Protocol::HBONE {
-                traffic::SecurityPolicy::mutual_tls
-            } else {
-                traffic::SecurityPolicy::unknown
-            },
-            destination_service: None,
-            destination_service_namespace: None,
-            destination_service_name: None,
-        };
-
-        if req.request_type == RequestType::DirectLocal && can_fastpath {
-            // For same node, we just access it directly rather than making a full network connection.
-            // Pass our `stream` over to the inbound handler, which will process as usual
-            // We *could* apply this to all traffic, rather than just for destinations that are "captured"
-            // However, we would then get inconsistent behavior where only node-local pods have RBAC enforced.
-            info!("proxying to {} using node local fast path", req.destination);
-            let origin_src = if self.pi.cfg.enable_original_source.unwrap_or_default() {
-                super::get_original_src_from_stream(&stream)
-            } else {
-                None
-            };
-            let conn = rbac::Connection {
-                src_identity: Some(req.source.identity()),
-                src_ip: remote_addr,
-                dst: req.destination,
+        let tracked = self.pi.connections.track();
+        // Endpoints we failed to connect to, which should not be picked again
+        let mut excluded = Vec::new();
+        // The error of the last attempt, and the connection it was reported against, if retrying
+        let mut last_err: Option<(Error, traffic::ConnectionOpen)> = None;
+        loop {
+            let req = match self
+                .build_request(remote_addr, orig_dst_addr, &excluded)
+                .await
+            {
+                Ok(req) => req,
+                Err(e) => return Err(handshake.reject(&mut stream, e).await),
             };
-            if !self.pi.workloads.assert_rbac(&conn).await {
-                info!(%conn, "RBAC rejected");
-                return Err(Error::HttpStatus(StatusCode::UNAUTHORIZED));
+            if req.destination_workload.is_none() {
+                if let Some((e, connection_metrics)) = last_err {
+                    // We have run out of endpoints to retry
+                    let _connection_close = self
+                        .pi
+                        .metrics
+                        .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
+                    return Err(handshake.reject(&mut stream, e).await);
+                }
+            }
+            debug!(
+                "request from {} to {} via {} type {:#?} dir {:#?}",
+                req.source.name, orig_dst_addr, req.gateway, req.request_type, req.direction
+            );
+            if block_passthrough && req.destination_workload.is_none() {
+                // This is mostly used by socks5. For typical outbound calls, we need to allow calls to arbitrary
+                // domains. But for socks5
+                let e = Error::UnknownDestination(req.destination.ip());
+                return Err(handshake.reject(&mut stream, e).await);
             }
-            // same as above but inverted, this is the "inbound" metric
-            let inbound_connection_metrics = traffic::ConnectionOpen {
-                reporter: Reporter::destination,
+            let limits = ConnectionLimits::new(&self.pi.cfg, Some(orig_dst_addr.ip()));
+            // _active will be counted for load balancing until the connection is closed
+            let _active = req
+                .destination_workload
+                .as_ref()
+                .map(|w| self.pi.workloads.track_connection(w.uid.clone()));
+            let can_fastpath = self.pi.cfg.proxy_mode == ProxyMode::Shared
+                && req.protocol == Protocol::HBONE
+                && !req
+                    .destination_workload
+                    .as_ref()
+                    .map(|w| w.native_hbone)
+                    .unwrap_or(false);
+            let destination_service = self.pi.workloads.find_service(&orig_dst_addr);
+            let connection_metrics = traffic::ConnectionOpen {
+                reporter: Reporter::source,
                 derived_source: None,
                 source: Some(req.source.clone()),
                 destination: req.destination_workload.clone(),
+                request_protocol: traffic::RequestProtocol::tcp,
                 connection_security_policy: if req.protocol == Protocol::HBONE {
                     traffic::SecurityPolicy::mutual_tls
                 } else {
                     traffic::SecurityPolicy::unknown
                 },
-                destination_service: None,
-                destination_service_namespace: None,
-                destination_service_name: None,
+                destination_service: destination_service.clone(),
             };
-            return Inbound::handle_inbound(
-                InboundConnect::DirectPath(stream),
-                origin_src,
-                req.destination,
-                self.pi.metrics.to_owned(), // self is a borrow so this clone is to return an owned
-                connection_metrics,
-                Some(inbound_connection_metrics),
-            )
-            .await
-            .map_err(Error::Io);
-        }
+            tracked.describe(ConnectionInfo {
+                open: connection_metrics.clone(),
+                request_type: Some(req.request_type),
+                protocol: req.protocol,
+                gateway: Some(req.gateway),
+            });
+            // Retries of the same connection do not take another token
+            if excluded.is_empty() {
+                if let Err(rejected) = self
+                    .pi
+                    .rate_limiter
+                    .check(&req.source.identity(), Some(&req.source))
+                {
+                    let e = Error::from(rejected);
+                    info!("{e}");
+                    self.pi
+                        .metrics
+                        .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics)
+                        .update(|c| c.set_response_flags(e.response_flags()));
+                    return Err(handshake.reject(&mut stream, e).await);
+                }
+            }
+
+            if req.request_type == RequestType::DirectLocal && can_fastpath {
+                // For same node, we just access it directly rather than making a full network connection.
+                // Pass our `stream` over to the inbound handler, which will process as usual
+                // We *could* apply this to all traffic, rather than just for destinations that are "captured"
+                // However, we would then get inconsistent behavior where only node-local pods have RBAC enforced.
+                info!("proxying to {} using node local fast path", req.destination);
+                let origin_src = self
+                    .pi
+                    .cfg
+                    .enable_original_source
+                    .unwrap_or_default()
+                    .then_some(remote_addr);
+                let conn = rbac::Connection {
+                    src_identity: Some(req.source.identity()),
+                    src_ip: remote_addr,
+                    dst: req.destination,
+                };
+                if !self.pi.workloads.assert_rbac(&conn).await {
+                    info!(%conn, "RBAC rejected");
+                    let e = Error::HttpStatus(StatusCode::UNAUTHORIZED);
+                    return Err(handshake.reject(&mut stream, e).await);
+                }
+                tracked.authorized(conn);
+                let proxy_header = req
+                    .destination_workload
+                    .as_ref()
+                    .filter(|w| w.wants_proxy_protocol(req.destination.port()))
+                    .map(|_| {
+                        proxy_protocol::header(
+                            SocketAddr::new(remote_addr, 0),
+                            req.destination,
+                            Some(&req.source.identity()),
+                        )
+                    });
+                // The inbound handler connects to the workload and relays in one step, so we cannot
+                // report a failure to connect; the downstream is closed instead.
+                handshake.established(&mut stream).await?;
+                // same as above but inverted, this is the "inbound" metric
+                let inbound_connection_metrics = traffic::ConnectionOpen {
+                    reporter: Reporter::destination,
+                    derived_source: None,
+                    source: Some(req.source.clone()),
+                    destination: req.destination_workload.clone(),
+                    request_protocol: traffic::RequestProtocol::tcp,
+                    connection_security_policy: if req.protocol == Protocol::HBONE {
+                        traffic::SecurityPolicy::mutual_tls
+                    } else {
+                        traffic::SecurityPolicy::unknown
+                    },
+                    destination_service,
+                };
+                return Inbound::handle_inbound(
+                    InboundConnect::DirectPath(stream),
+                    origin_src,
+                    req.destination,
+                    proxy_header,
+                    self.pi.metrics.to_owned(), // self is a borrow so this clone is to return an owned
+                    connection_metrics,
+                    Some(inbound_connection_metrics),
+                    limits,
+                    self.pi.cfg.socket_options,
+                    tracked,
+                )
+                .await
+                .map_err(Error::Io);
+            }
 
-        let transferred_bytes = traffic::BytesTransferred::from(&connection_metrics);
+            let transferred_bytes = traffic::BytesTransferred::from(&connection_metrics);
 
-        // _connection_close will record once dropped
-        let _connection_close = self
-            .pi
-            .metrics
-            .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
+            // _admission holds our capacity against the destination's circuit breakers until the
+            // connection is closed
+            let mut _admission = match self.pi.circuit_breakers.admit(
+                circuit_breaker_keys(&req, &self.pi.workloads, orig_dst_addr),
+                req.protocol == Protocol::HBONE,
+            ) {
+                Ok(admission) => admission,
+                Err(rejected) => {
+                    let e = Error::from(rejected);
+                    self.pi
+                        .metrics
+                        .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics)
+                        .update(|c| c.set_response_flags(e.response_flags()));
+                    return Err(handshake.reject(&mut stream, e).await);
+                }
+            };
+            let result = self.connect_upstream(&req, remote_addr).await;
+            // Only direct connections reflect the health of the endpoint itself, rather than a waypoint
+            if let (RequestType::Direct, Some(wl)) = (&req.request_type, &req.destination_workload)
+            {
+                let outcome = match &result {
+                    Ok(_) => Some(true),
+                    Err(Error::HttpStatus(_)) => Some(false),
+                    Err(e) => retry_reason(e).map(|_| false),
+                };
+                if let Some(success) = outcome {
+                    self.pi.workloads.record_outcome(wl.uid.clone(), success);
+                }
+            }
+            let upstream = match result {
+                Ok(upstream) => {
+                    _admission.established();
+                    upstream
+                }
+                Err(e) => {
+                    // Nothing has been sent yet, so we can safely try another endpoint of the VIP
+                    let retry = retry_reason(&e).filter(|_| {
+                        excluded.len() < self.pi.cfg.connect_retry_budget as usize
+                            && self.pi.workloads.is_vip(&orig_dst_addr)
+                    });
+                    match (retry, &req.destination_workload) {
+                        (Some(reason), Some(wl)) => {
+                            warn!(
+                                endpoint=%req.destination, ?reason, err=%e,
+                                "failed to connect to upstream, retrying another endpoint",
+                            );
+                            self.pi.metrics.increment(&reason);
+                            excluded.push(wl.uid.clone());
+                            last_err = Some((e, connection_metrics));
+                            continue;
+                        }
+                        _ => {
+                            let _connection_close = self
+                                .pi
+                                .metrics
+                                .increment_defer::<_, traffic::ConnectionClose>(
+                                    &connection_metrics,
+                                );
+                            return Err(handshake.reject(&mut stream, e).await);
+                        }
+                    }
+                }
+            };
+            // _connection_close will record once dropped. The connection is only reported once
+            // this attempt is final; attempts that were retried are only counted as retries.
+            let mut _connection_close = self
+                .pi
+                .metrics
+                .increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
+            handshake.established(&mut stream).await?;
+            let res = match upstream {
+                UpstreamConnection::Hbone(mut upgraded, _pooled) => {
+                    // _pooled holds our slot on the pooled connection until the tunnel is closed
+                    super::copy_hbone(
+                        &mut upgraded,
+                        &mut stream,
+                        limits,
+                        &self.pi.metrics,
+                        transferred_bytes,
+                        &tracked,
+                    )
+                    .instrument(trace_span!("hbone client"))
+                    .await
+                }
+                #[cfg(feature = "quic")]
+                UpstreamConnection::Hbone3(mut tunnel, _pooled) => {
+                    super::copy_hbone(
+                        &mut tunnel,
+                        &mut stream,
+                        limits,
+                        &self.pi.metrics,
+                        transferred_bytes,
+                        &tracked,
+                    )
+                    .instrument(trace_span!("hbone client"))
+                    .await
+                }
+                UpstreamConnection::Tcp(mut outbound) => {
+                    // Proxying data between downstrean and upstream
+                    proxy::relay(
+                        &mut stream,
+                        &mut outbound,
+                        limits,
+                        &self.pi.metrics,
+                        transferred_bytes,
+                        &tracked,
+                    )
+                    .await
+                    .map(|_| ())
+                }
+            };
+            if let Err(e) = &res {
+                _connection_close.update(|c| c.set_response_flags(e.response_flags()));
+            }
+            return res;
+        }
+    }
+
+    /// connect_upstream establishes the connection to the upstream for `req`, without sending any
+    /// data from the downstream.
+    async fn connect_upstream(
+        &self,
+        req: &Request,
+        remote_addr: IpAddr,
+    ) -> Result<UpstreamConnection, Error> {
         match req.protocol {
             Protocol::HBONE => {
                 info!(
@@ -226,72 +426,27 @@ impl OutboundConnection {
                     req.destination, req.gateway, req.request_type
                 );
 
-                // Using the raw connection API, instead of client, is a bit annoying, but the only reasonable
-                // way to work around https://github.com/hyperium/hyper/issues/2863
-                // Eventually we will need to implement our own smarter pooling, TLS handshaking, etc anyways.
-                let mut builder = hyper::client::conn::Builder::new();
-                let builder = builder
-                    .http2_only(true)
-                    .http2_initial_stream_window_size(self.pi.cfg.window_size)
-                    .http2_max_frame_size(self.pi.cfg.frame_size)
-                    .http2_initial_connection_window_size(self.pi.cfg.connection_window_size);
-
-                let mut f = http_types::proxies::Forwarded::new();
-                f.add_for(remote_addr.to_string());
-
-                let request = hyper::Request::builder()
-                    .uri(&req.destination.to_string())
-                    .method(hyper::Method::CONNECT)
-                    .version(hyper::Version::HTTP_2)
-                    .header(
-                        BAGGAGE_HEADER,
-                        baggage(&req, self.pi.cfg.cluster_id.clone()),
-                    )
-                    .header(FORWARDED, f.value().unwrap())
-                    .header(TRACEPARENT_HEADER, self.id.header())
-                    .body(hyper::Body::empty())
-                    .unwrap();
-                let local = self
-                    .pi
-                    .cfg
-                    .enable_original_source
-                    .unwrap_or_default()
-                    .then_some(remote_addr);
-                let id = &req.source.identity();
-                let cert = self.pi.cert_manager.fetch_certificate(id).await?;
-                let connector = cert
-                    .connector(req.expected_identity.as_ref())?
-                    .configure()
-                    .expect("configure");
-                let tcp_stream = super::freebind_connect(local, req.gateway).await?;
-                tcp_stream.set_nodelay(true)?;
-                let tls_stream = connect_tls(connector, tcp_stream).await?;
-                let (mut request_sender, connection) = builder
-                    .handshake(tls_stream)
-                    .await
-                    .map_err(Error::HttpHandshake)?;
-                // spawn a task to poll the connection and drive the HTTP state
-                tokio::spawn(async move {
-                    if let Err(e) = connection.await {
-                        error!("Error in HBONE connection handshake: {:?}", e);
+                let target = if req.request_type == RequestType::ToNetworkGateway {
+                    // The gateway forwards the tunnel to the destination's node, and we tunnel a
+                    // second HBONE connection to the destination through it
+                    SocketAddr::from((req.destination.ip(), self.pi.hbone_port))
+                } else {
+                    req.destination
+                };
+                let request = self.hbone_request(req, remote_addr, target);
+                #[cfg(feature = "quic")]
+                if req.request_type != RequestType::ToNetworkGateway {
+                    if let Some((tunnel, pooled)) = self.send_h3(req, &request).await? {
+                        return Ok(UpstreamConnection::Hbone3(tunnel, pooled));
                     }
-                });
-
-                let response = request_sender.send_request(request).await?;
-
-                let code = response.status();
-                if code != 200 {
-                    return Err(Error::HttpStatus(code));
                 }
-                let mut upgraded = hyper::upgrade::on(response).await?;
-                super::copy_hbone(
-                    &mut upgraded,
-                    &mut stream,
-                    &self.pi.metrics,
-                    transferred_bytes,
-                )
-                .instrument(trace_span!("hbone client"))
-                .await
+                let (mut upgraded, pooled) = self.send_hbone(req, remote_addr, request).await?;
+                if req.request_type == RequestType::ToNetworkGateway {
+                    upgraded = self
+                        .connect_through_gateway(upgraded, req, remote_addr)
+                        .await?;
+                }
+                Ok(UpstreamConnection::Hbone(upgraded, pooled))
             }
             Protocol::TCP => {
                 info!(
@@ -299,29 +454,226 @@ impl OutboundConnection {
                     req.destination, req.gateway, req.request_type
                 );
                 // Create a TCP connection to upstream
-                let local = if self.pi.cfg.enable_original_source.unwrap_or_default() {
-                    super::get_original_src_from_stream(&stream)
-                } else {
-                    None
-                };
-                let mut outbound = super::freebind_connect(local, req.gateway).await?;
-                // Proxying data between downstrean and upstream
-                proxy::relay(
-                    &mut stream,
-                    &mut outbound,
-                    &self.pi.metrics,
-                    transferred_bytes,
-                )
-                .await
-                .map(|_| ())
+                let local = self
+                    .pi
+                    .cfg
+                    .enable_original_source
+                    .unwrap_or_default()
+                    .then_some(remote_addr);
+                let outbound =
+                    super::freebind_connect(local, req.gateway, &self.pi.cfg.socket_options)
+                        .await?;
+                Ok(UpstreamConnection::Tcp(outbound))
             }
         }
     }
 
-    async fn build_request(
+    /// connect_hbone_udp opens an HBONE tunnel carrying UDP to the destination of `req`. The tunnel
+    /// is an extended CONNECT using the connect-udp protocol from RFC 9298, with each datagram
+    /// encapsulated in a capsule; see udp::Capsules.
+    pub(super) async fn connect_hbone_udp(
+        &self,
+        req: &Request,
+        remote_addr: IpAddr,
+    ) -> Result<(hyper::upgrade::Upgraded, pool::PooledStream), Error> {
+        info!(
+            "proxy udp to {} using HBONE via {} type {:#?}",
+            req.destination, req.gateway, req.request_type
+        );
+        let mut request = self.hbone_request(req, remote_addr, req.destination);
+        // The target is carried in the path, rather than the authority of a plain CONNECT
+        *request.uri_mut() = udp::connect_udp_uri(req.destination)
+            .parse()
+            .expect("valid uri");
+        request
+            .extensions_mut()
+            .insert(hyper::ext::Protocol::from_static(udp::CONNECT_UDP_PROTOCOL));
+        request.headers_mut().insert(
+            udp::CAPSULE_PROTOCOL_HEADER,
+            hyper::header::HeaderValue::from_static("?1"),
+        );
+        self.send_hbone(req, remote_addr, request).await
+    }
+
+    /// send_hbone sends `request` over a pooled HBONE connection to the gateway of `req`, returning
+    /// the tunnel once the upstream accepts it.
+    async fn send_hbone(
+        &self,
+        req: &Request,
+        remote_addr: IpAddr,
+        request: hyper::Request<hyper::Body>,
+    ) -> Result<(hyper::upgrade::Upgraded, pool::PooledStream), Error> {
+        let local = self
+            .pi
+            .cfg
+            .enable_original_source
+            .unwrap_or_default()
+            .then_some(remote_addr);
+        let key = pool::Key {
+            src_id: req.source.identity(),
+            src: local,
+            dst: req.gateway,
+            dst_id: req.expected_identity.clone(),
+        };
+        let (response, pooled) = self
+            .pi
+            .pool
+            .send_request(key, request, || self.connect_hbone(local, req))
+            .await?;
+        #[cfg(feature = "quic")]
+        self.pi.h3.learn(req.gateway, response.headers());
+
+        let code = response.status();
+        if code != 200 {
+            return Err(Error::HttpStatus(code));
+        }
+        Ok((hyper::upgrade::on(response).await?, pooled))
+    }
+
+    /// send_h3 sends `request` over HTTP/3, if the gateway of `req` advertised it. If the request
+    /// could not be sent this way, None is returned to fall back to HTTP/2.
+    #[cfg(feature = "quic")]
+    async fn send_h3(
+        &self,
+        req: &Request,
+        request: &hyper::Request<hyper::Body>,
+    ) -> Result<Option<(tokio::io::DuplexStream, super::quic::PooledConnection)>, Error> {
+        // QUIC connections are not bound to the source workload's address
+        if self.pi.cfg.enable_original_source.unwrap_or_default() {
+            return Ok(None);
+        }
+        let Some(addr) = self.pi.h3.alternative(req.gateway) else {
+            return Ok(None);
+        };
+        let key = pool::Key {
+            src_id: req.source.identity(),
+            src: None,
+            dst: req.gateway,
+            dst_id: req.expected_identity.clone(),
+        };
+        match self.pi.h3.send_request(key, addr, request).await {
+            Ok(tunnel) => Ok(Some(tunnel)),
+            // The gateway may have already acted on the request, so it must not be retried
+            Err(super::quic::SendError::Sent(e)) => Err(e),
+            Err(super::quic::SendError::NotSent(e)) => {
+                warn!(gateway=%req.gateway, "HTTP/3 failed, falling back to HTTP/2: {e}");
+                self.pi.h3.mark_broken(req.gateway);
+                Ok(None)
+            }
+        }
+    }
+
+    /// hbone_request builds the CONNECT request to `target` for `req`.
+    fn hbone_request(
+        &self,
+        req: &Request,
+        remote_addr: IpAddr,
+        target: SocketAddr,
+    ) -> hyper::Request<hyper::Body> {
+        let mut f = http_types::proxies::Forwarded::new();
+        f.add_for(remote_addr.to_string());
+
+        hyper::Request::builder()
+            .uri(&target.to_string())
+            .method(hyper::Method::CONNECT)
+            .version(hyper::Version::HTTP_2)
+            .header(BAGGAGE_HEADER, baggage(req, self.pi.cfg.cluster_id.clone()))
+            .header(FORWARDED, f.value().unwrap())
+            .header(TRACEPARENT_HEADER, self.id.header())
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    fn hbone_client_builder(&self) -> hyper::client::conn::Builder {
+        // Using the raw connection API, instead of client, is a bit annoying, but the only reasonable
+        // way to work around https://github.com/hyperium/hyper/issues/2863
+        let mut builder = hyper::client::conn::Builder::new();
+        builder
+            .http2_only(true)
+            .http2_initial_stream_window_size(self.pi.cfg.window_size)
+            .http2_max_frame_size(self.pi.cfg.frame_size)
+            .http2_initial_connection_window_size(self.pi.cfg.connection_window_size);
+        builder
+    }
+
+    /// connect_through_gateway establishes an HBONE connection to the destination of `req` inside
+    /// `tunnel`, which has been opened through the destination network's gateway. The gateway only
+    /// sees the outer tunnel, so mTLS is end to end with the destination.
+    async fn connect_through_gateway(
+        &self,
+        tunnel: hyper::upgrade::Upgraded,
+        req: &Request,
+        remote_addr: IpAddr,
+    ) -> Result<hyper::upgrade::Upgraded, Error> {
+        let cert = self
+            .pi
+            .cert_manager
+            .fetch_certificate(&req.source.identity())
+            .await?;
+        let dst_identity = req.destination_workload.as_ref().map(Workload::identity);
+        let connector = cert
+            .connector(dst_identity.as_ref())?
+            .configure()
+            .expect("configure");
+        let tls_stream = connect_tls(connector, tunnel)
+            .await
+            .map_err(|e| Error::GatewayTlsHandshake(e.to_string()))?;
+        let (mut request_sender, connection) = self
+            .hbone_client_builder()
+            .handshake(tls_stream)
+            .await
+            .map_err(Error::HttpHandshake)?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Error in HBONE connection through network gateway: {:?}", e);
+            }
+        });
+        let response = request_sender
+            .send_request(self.hbone_request(req, remote_addr, req.destination))
+            .await?;
+        let code = response.status();
+        if code != 200 {
+            return Err(Error::HttpStatus(code));
+        }
+        Ok(hyper::upgrade::on(response).await?)
+    }
+
+    /// connect_hbone establishes a new HBONE connection to the request's gateway, returning a handle
+    /// that can be used to send CONNECT requests over it.
+    async fn connect_hbone(
+        &self,
+        local: Option<IpAddr>,
+        req: &Request,
+    ) -> Result<SendRequest<hyper::Body>, Error> {
+        let id = &req.source.identity();
+        let cert = self.pi.cert_manager.fetch_certificate(id).await?;
+        let connector = cert
+            .connector(req.expected_identity.as_ref())?
+            .configure()
+            .expect("configure");
+        let tcp_stream =
+            super::freebind_connect(local, req.gateway, &self.pi.cfg.socket_options).await?;
+        tcp_stream.set_nodelay(true)?;
+        let tls_stream = connect_tls(connector, tcp_stream).await?;
+        let (request_sender, connection) = self
+            .hbone_client_builder()
+            .handshake(tls_stream)
+            .await
+            .map_err(Error::HttpHandshake)?;
+        // spawn a task to poll the connection and drive the HTTP state
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Error in HBONE connection handshake: {:?}", e);
+            }
+        });
+        Ok(request_sender)
+    }
+
+    pub(super) async fn build_request(
         &self,
         downstream: IpAddr,
         target: SocketAddr,
+        excluded: &[String],
     ) -> Result<Request, Error> {
         let source_workload = match self.pi.workloads.fetch_workload(&downstream).await {
             Some(wl) => wl,
@@ -329,11 +681,32 @@ impl OutboundConnection {
         };
 
         // TODO: we want a single lock for source and upstream probably...?
-        let us = self
-            .pi
-            .workloads
-            .find_upstream(target, self.pi.hbone_port)
-            .await;
+        let mut skipped = excluded.to_vec();
+        let mut unreachable = None;
+        let (us, network_gateway) = loop {
+            let Some(us) = self
+                .pi
+                .workloads
+                .find_upstream(target, downstream, self.pi.hbone_port, &skipped)
+                .await
+            else {
+                // Every endpoint we could pick is on a network we cannot reach
+                if let Some(e) = unreachable {
+                    return Err(e);
+                }
+                break (None, None);
+            };
+            match self.network_gateway(&us.workload) {
+                Ok(gateway) => break (Some(us), gateway),
+                // A service may have endpoints on multiple networks, so try another
+                Err(e) if self.pi.workloads.is_vip(&target) => {
+                    debug!(endpoint=%us.workload.uid, "skipping unreachable endpoint: {e}");
+                    skipped.push(us.workload.uid.clone());
+                    unreachable = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        };
         if us.is_none() {
             // For case no upstream found, passthrough it
             return Ok(Request {
@@ -349,9 +722,25 @@ impl OutboundConnection {
         }
 
         let us = us.unwrap();
+        // For case upstream server is on another network, which we reach through its gateway
+        if let Some(gateway) = network_gateway {
+            return Ok(Request {
+                protocol: Protocol::HBONE,
+                source: source_workload,
+                destination: SocketAddr::from((us.selected_workload_ip, us.port)),
+                destination_workload: Some(us.workload),
+                expected_identity: Some(self.pi.cfg.network_gateway_identity.clone()),
+                gateway,
+                direction: Direction::Outbound,
+                request_type: RequestType::ToNetworkGateway,
+            });
+        }
         // For case upstream server has enabled waypoint
         if !us.workload.waypoint_addresses.is_empty() {
-            let waypoint_address = us.workload.choose_waypoint_address().unwrap();
+            let waypoint_address = us
+                .workload
+                .choose_waypoint_address(us.selected_workload_ip)
+                .unwrap();
             // Even in this case, we are picking a single upstream pod and deciding if it has a remote proxy.
             // Typically this is all or nothing, but if not we should probably send to remote proxy if *any* upstream has one.
             let waypoint_workload = match self.pi.workloads.fetch_workload(&waypoint_address).await
@@ -367,7 +756,7 @@ impl OutboundConnection {
                 destination: target,
                 destination_workload: Some(us.workload),
                 expected_identity: Some(waypoint_workload.identity()),
-                gateway: SocketAddr::from((waypoint_address, 15008)),
+                gateway: SocketAddr::from((waypoint_address, self.pi.hbone_port)),
                 // Let the client remote know we are on the inbound path.
                 direction: Direction::Inbound,
                 request_type: RequestType::ToServerWaypoint,
@@ -376,10 +765,15 @@ impl OutboundConnection {
         if us.workload.gateway_address.is_none() {
             return Err(Error::NoGatewayAddress(Box::new(us.workload.clone())));
         }
+        // Workloads that disable mTLS are sent plaintext, even if they support HBONE
+        let protocol = match self.pi.workloads.mtls_mode(&us.workload, us.port) {
+            MtlsMode::Disable => Protocol::TCP,
+            _ => us.workload.protocol,
+        };
         // For case source client and upstream server are on the same node
         if !us.workload.node.is_empty()
             && self.pi.cfg.local_node.as_ref() == Some(&us.workload.node) // looks weird but in Rust borrows can be compared and will behave the same as owned (https://doc.rust-lang.org/std/primitive.reference.html)
-            && us.workload.protocol == Protocol::HBONE
+            && protocol == Protocol::HBONE
         {
             trace!(
                 workload_node = us.workload.node,
@@ -390,7 +784,7 @@ impl OutboundConnection {
             return Ok(Request {
                 protocol: Protocol::HBONE,
                 source: source_workload,
-                destination: SocketAddr::from((us.workload.workload_ip, us.port)),
+                destination: SocketAddr::from((us.selected_workload_ip, us.port)),
                 destination_workload: Some(us.workload.clone()),
                 expected_identity: Some(us.workload.identity()),
                 gateway: SocketAddr::from((
@@ -398,7 +792,7 @@ impl OutboundConnection {
                         .gateway_address
                         .expect("gateway address confirmed")
                         .ip(),
-                    15008,
+                    self.pi.hbone_port,
                 )),
                 direction: Direction::Outbound,
                 // Sending to a node on the same node (ourselves).
@@ -407,20 +801,79 @@ impl OutboundConnection {
             });
         }
         // For case no waypoint for both side and direct to remote node proxy
+        let destination = SocketAddr::from((us.selected_workload_ip, us.port));
         Ok(Request {
-            protocol: us.workload.protocol,
+            protocol,
             source: source_workload,
-            destination: SocketAddr::from((us.workload.workload_ip, us.port)),
+            destination,
             destination_workload: Some(us.workload.clone()),
             expected_identity: Some(us.workload.identity()),
-            gateway: us
-                .workload
-                .gateway_address
-                .expect("gateway address confirmed"),
+            gateway: match protocol {
+                // The gateway of an HBONE workload is the HBONE port, which plaintext bypasses
+                Protocol::TCP => destination,
+                Protocol::HBONE => us
+                    .workload
+                    .gateway_address
+                    .expect("gateway address confirmed"),
+            },
             direction: Direction::Outbound,
             request_type: RequestType::Direct,
         })
     }
+
+    /// network_gateway returns the gateway to reach `wl` through, if it is on another network.
+    /// Workloads on another network must support HBONE, as we tunnel to them through the gateway.
+    #[allow(clippy::result_large_err)]
+    fn network_gateway(&self, wl: &Workload) -> Result<Option<SocketAddr>, Error> {
+        if wl.network == self.pi.cfg.network {
+            return Ok(None);
+        }
+        if wl.protocol != Protocol::HBONE {
+            return Err(Error::NetworkUnreachable(wl.uid.clone()));
+        }
+        match self.pi.cfg.network_gateways.get(&wl.network) {
+            Some(gateway) => Ok(Some(*gateway)),
+            None => Err(Error::NoNetworkGateway(wl.network.clone())),
+        }
+    }
+}
+
+/// circuit_breaker_keys returns the circuit breakers that apply to `req`: the destination workload,
+/// and the service if the connection was addressed to a VIP.
+fn circuit_breaker_keys(
+    req: &Request,
+    workloads: &WorkloadInformation,
+    orig_dst_addr: SocketAddr,
+) -> Vec<circuit_breaker::Key> {
+    let mut keys = Vec::with_capacity(2);
+    if let Some(wl) = &req.destination_workload {
+        keys.push(circuit_breaker::Key::Workload(wl.uid.clone()));
+    }
+    if workloads.is_vip(&orig_dst_addr) {
+        keys.push(circuit_breaker::Key::Service(orig_dst_addr.ip()));
+    }
+    keys
+}
+
+/// UpstreamConnection is an established connection to an upstream, ready to relay traffic.
+enum UpstreamConnection {
+    Hbone(hyper::upgrade::Upgraded, pool::PooledStream),
+    #[cfg(feature = "quic")]
+    Hbone3(tokio::io::DuplexStream, super::quic::PooledConnection),
+    Tcp(TcpStream),
+}
+
+/// retry_reason returns why a failed upstream connection can be retried on another endpoint, if it
+/// can. Errors such as a non-200 response are an explicit answer from the upstream, so are not retried.
+fn retry_reason(e: &Error) -> Option<ConnectRetryReason> {
+    match e {
+        Error::Io(_) => Some(ConnectRetryReason::connect_error),
+        Error::TlsHandshake(_) | Error::GatewayTlsHandshake(_) => {
+            Some(ConnectRetryReason::tls_error)
+        }
+        Error::HttpHandshake(_) | Error::Http(_) => Some(ConnectRetryReason::hbone_error),
+        _ => None,
+    }
 }
 
 fn baggage(r: &Request, cluster: String) -> String {
@@ -434,19 +887,49 @@ fn baggage(r: &Request, cluster: String) -> String {
 }
 
 #[derive(Debug)]
-struct Request {
-    protocol: Protocol,
+pub(super) struct Request {
+    pub(super) protocol: Protocol,
     direction: Direction,
-    source: Workload,
-    destination: SocketAddr,
+    pub(super) source: Workload,
+    pub(super) destination: SocketAddr,
     // The intended destination workload. This is always the original intended target, even in the case
     // of other proxies along the path.
-    destination_workload: Option<Workload>,
+    pub(super) destination_workload: Option<Workload>,
     // The identity we will assert for the next hop; this may not be the same as destination_workload
     // in the case of proxies along the path.
     expected_identity: Option<Identity>,
-    gateway: SocketAddr,
-    request_type: RequestType,
+    pub(super) gateway: SocketAddr,
+    pub(super) request_type: RequestType,
+}
+
+/// Handshake is how the downstream learns the outcome of connecting to the upstream, for protocols
+/// where the client waits for it before sending any data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Handshake {
+    /// Nothing is sent; the downstream is relayed as soon as the upstream is connected.
+    None,
+    /// A response to an HTTP/1.1 CONNECT request is sent.
+    HttpConnect,
+}
+
+impl Handshake {
+    /// established reports that the upstream is connected, so the client may start sending data.
+    async fn established(self, stream: &mut TcpStream) -> Result<(), Error> {
+        match self {
+            Handshake::None => Ok(()),
+            Handshake::HttpConnect => http_connect::respond(stream, StatusCode::OK).await,
+        }
+    }
+
+    /// reject reports that the upstream could not be connected, and returns the error `e`.
+    async fn reject(self, stream: &mut TcpStream, e: Error) -> Error {
+        if self == Handshake::HttpConnect {
+            if let Err(err) = http_connect::respond(stream, http_connect::status(&e)).await {
+                debug!("failed to send connect response: {err}");
+            }
+        }
+        e
+    }
 }
 
 #[derive(Debug)]
@@ -455,8 +938,8 @@ enum Direction {
     Outbound,
 }
 
-#[derive(PartialEq, Debug)]
-enum RequestType {
+#[derive(PartialEq, Debug, Clone, Copy, serde::Serialize)]
+pub(super) enum RequestType {
     /// ToServerWaypoint refers to requests targeting a server waypoint proxy
     ToServerWaypoint,
     /// Direct requests are made directly to a intended backend pod
@@ -465,12 +948,15 @@ enum RequestType {
     DirectLocal,
     /// Passthrough refers to requests with an unknown target
     Passthrough,
+    /// ToNetworkGateway requests are made to a backend pod on another network, tunneled through
+    /// the east-west gateway of that network
+    ToNetworkGateway,
 }
 
-pub async fn connect_tls(
+pub async fn connect_tls<S: AsyncRead + AsyncWrite + Unpin>(
     mut connector: ConnectConfiguration,
-    stream: TcpStream,
-) -> Result<tokio_boring::SslStream<TcpStream>, tokio_boring::HandshakeError<TcpStream>> {
+    stream: S,
+) -> Result<tokio_boring::SslStream<S>, tokio_boring::HandshakeError<S>> {
     connector.set_verify_hostname(false);
     connector.set_use_server_name_indication(false);
     tokio_boring::connect(connector, "", stream).await
@@ -478,19 +964,51 @@ pub async fn connect_tls(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
     use bytes::Bytes;
 
     use crate::config::Config;
-    use crate::workload::WorkloadInformation;
+    use crate::ratelimit::RateLimiter;
+    use crate::xds::istio::workload::Port as XdsPort;
+    use crate::xds::istio::workload::PortList as XdsPortList;
     use crate::xds::istio::workload::Protocol as XdsProtocol;
     use crate::xds::istio::workload::Workload as XdsWorkload;
     use crate::{identity, workload};
 
     use super::*;
 
+    fn test_outbound(cfg: Config, workloads: Vec<XdsWorkload>) -> OutboundConnection {
+        let wl = workload::WorkloadStore::test_store(workloads).unwrap();
+
+        let wi = WorkloadInformation {
+            info: Arc::new(Mutex::new(wl)),
+            demand: None,
+        };
+        let metrics: Arc<crate::metrics::Metrics> = Arc::new(Default::default());
+        OutboundConnection {
+            pi: ProxyInputs {
+                cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
+                workloads: wi,
+                hbone_port: 15008,
+                pool: pool::Pool::new(&cfg, metrics.clone()),
+                circuit_breakers: circuit_breaker::CircuitBreakers::new(&cfg),
+                rate_limiter: RateLimiter::new(&cfg),
+                connections: Default::default(),
+                #[cfg(feature = "quic")]
+                h3: crate::proxy::quic::Client::new(
+                    &cfg,
+                    identity::mock::new_secret_manager(Duration::from_secs(10)),
+                ),
+                cfg,
+                metrics,
+            },
+            id: TraceParent::new(),
+        }
+    }
+
     async fn run_build_request(
         from: &str,
         to: &str,
@@ -504,36 +1022,21 @@ mod tests {
         let source = XdsWorkload {
             name: "source-workload".to_string(),
             namespace: "ns".to_string(),
-            address: Bytes::copy_from_slice(&[127, 0, 0, 1]),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
             node: "local-node".to_string(),
             ..Default::default()
         };
         let waypoint = XdsWorkload {
             name: "waypoint-workload".to_string(),
             namespace: "ns".to_string(),
-            address: Bytes::copy_from_slice(&[127, 0, 0, 10]),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 10])],
             node: "local-node".to_string(),
             ..Default::default()
         };
-        let wl = workload::WorkloadStore::test_store(vec![source, waypoint, xds]).unwrap();
-
-        let wi = WorkloadInformation {
-            info: Arc::new(Mutex::new(wl)),
-            demand: None,
-        };
-        let outbound = OutboundConnection {
-            pi: ProxyInputs {
-                cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
-                workloads: wi,
-                hbone_port: 15008,
-                cfg,
-                metrics: Arc::new(Default::default()),
-            },
-            id: TraceParent::new(),
-        };
+        let outbound = test_outbound(cfg, vec![source, waypoint, xds]);
 
         let req = outbound
-            .build_request(from.parse().unwrap(), to.parse().unwrap())
+            .build_request(from.parse().unwrap(), to.parse().unwrap(), &[])
             .await
             .ok();
         if let Some(r) = req {
@@ -557,7 +1060,7 @@ mod tests {
             "127.0.0.1",
             "1.2.3.4:80",
             XdsWorkload {
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 ..Default::default()
             },
             Some(ExpectedRequest {
@@ -578,7 +1081,7 @@ mod tests {
             XdsWorkload {
                 name: "test-tcp".to_string(),
                 namespace: "ns".to_string(),
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 protocol: XdsProtocol::Direct as i32,
                 node: "remote-node".to_string(),
                 ..Default::default()
@@ -601,7 +1104,7 @@ mod tests {
             XdsWorkload {
                 name: "test-tcp".to_string(),
                 namespace: "ns".to_string(),
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 protocol: XdsProtocol::Http as i32,
                 node: "remote-node".to_string(),
                 ..Default::default()
@@ -624,7 +1127,7 @@ mod tests {
             XdsWorkload {
                 name: "test-tcp".to_string(),
                 namespace: "ns".to_string(),
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 protocol: XdsProtocol::Direct as i32,
                 node: "local-node".to_string(),
                 ..Default::default()
@@ -647,7 +1150,7 @@ mod tests {
             XdsWorkload {
                 name: "test-tcp".to_string(),
                 namespace: "ns".to_string(),
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 protocol: XdsProtocol::Http as i32,
                 node: "local-node".to_string(),
                 ..Default::default()
@@ -668,7 +1171,7 @@ mod tests {
             "1.2.3.4",
             "127.0.0.2:80",
             XdsWorkload {
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 ..Default::default()
             },
             None,
@@ -682,7 +1185,7 @@ mod tests {
             "127.0.0.2",
             "127.0.0.1:80",
             XdsWorkload {
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 waypoint_addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 10])],
                 ..Default::default()
             },
@@ -702,7 +1205,7 @@ mod tests {
             "127.0.0.1",
             "127.0.0.2:80",
             XdsWorkload {
-                address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
                 waypoint_addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 10])],
                 ..Default::default()
             },
@@ -717,6 +1220,80 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_other_network() {
+        let cfg = Config {
+            network_gateways: HashMap::from([(
+                "network2".to_string(),
+                "10.0.0.1:15008".parse().unwrap(),
+            )]),
+            ..crate::config::parse_config().unwrap()
+        };
+        let vips = |vips: &[&str]| {
+            vips.iter()
+                .map(|vip| {
+                    (
+                        vip.to_string(),
+                        XdsPortList {
+                            ports: vec![XdsPort {
+                                service_port: 80,
+                                target_port: 8080,
+                            }],
+                        },
+                    )
+                })
+                .collect()
+        };
+        let workload = |ip: u8, network: &str, protocol: XdsProtocol, vip: &[&str]| XdsWorkload {
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+            network: network.to_string(),
+            protocol: protocol as i32,
+            virtual_ips: vips(vip),
+            ..Default::default()
+        };
+        let outbound = test_outbound(
+            cfg,
+            vec![
+                workload(1, "", XdsProtocol::Direct, &[]),
+                workload(2, "network2", XdsProtocol::Http, &["127.0.1.1"]),
+                workload(
+                    3,
+                    "network2",
+                    XdsProtocol::Direct,
+                    &["127.0.1.2", "127.0.1.3"],
+                ),
+                workload(4, "", XdsProtocol::Direct, &["127.0.1.2"]),
+                workload(5, "network3", XdsProtocol::Http, &["127.0.1.4"]),
+            ],
+        );
+        let build = |vip: &str| {
+            outbound.build_request("127.0.0.1".parse().unwrap(), vip.parse().unwrap(), &[])
+        };
+
+        let req = build("127.0.1.1:80").await.unwrap();
+        assert_eq!(req.request_type, RequestType::ToNetworkGateway);
+        assert_eq!(req.protocol, Protocol::HBONE);
+        assert_eq!(req.destination.to_string(), "127.0.0.2:8080");
+        assert_eq!(req.gateway.to_string(), "10.0.0.1:15008");
+        assert_eq!(
+            req.expected_identity,
+            Some(outbound.pi.cfg.network_gateway_identity.clone())
+        );
+        // Endpoints we cannot reach are skipped
+        for _ in 0..10 {
+            let req = build("127.0.1.2:80").await.unwrap();
+            assert_eq!(req.destination.to_string(), "127.0.0.4:8080");
+        }
+        assert!(matches!(
+            build("127.0.1.3:80").await,
+            Err(Error::NetworkUnreachable(_))
+        ));
+        assert!(matches!(
+            build("127.0.1.4:80").await,
+            Err(Error::NoNetworkGateway(n)) if n == "network3"
+        ));
+    }
+
     #[derive(PartialEq, Debug)]
     struct ExpectedRequest<'a> {
         protocol: Protocol,
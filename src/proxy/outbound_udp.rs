@@ -0,0 +1,172 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use drain::Watch;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use crate::proxy::udp::{self, Flow};
+use crate::proxy::{util, Error, ProxyInputs};
+use crate::socket;
+
+// How many datagrams may be queued for a flow that is still being opened, or is sending slowly.
+// Datagrams beyond this are dropped, as the network would.
+const FLOW_QUEUE_SIZE: usize = 64;
+
+/// OutboundUdp proxies UDP sent by workloads on this node, which is redirected to it with TPROXY.
+/// Each pair of source and original destination is a flow, which is relayed like a TCP
+/// connection would be: over HBONE where the destination supports it, and directly otherwise.
+pub(super) struct OutboundUdp {
+    pi: ProxyInputs,
+    socket: UdpSocket,
+    drain: Watch,
+}
+
+impl OutboundUdp {
+    pub(super) async fn new(
+        pi: ProxyInputs,
+        addr: SocketAddr,
+        drain: Watch,
+    ) -> Result<OutboundUdp, Error> {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| Error::Bind(addr, e))?;
+        // Both are required to receive redirected datagrams and learn where they were sent
+        socket::set_udp_transparent(&socket)?;
+        socket::set_recv_orig_dst(&socket)?;
+
+        info!(
+            address=%socket.local_addr().unwrap(),
+            component="outbound_udp",
+            "listener established",
+        );
+        Ok(OutboundUdp { pi, socket, drain })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    pub(super) async fn run(self) {
+        let accept = async move {
+            let mut flows: HashMap<(SocketAddr, SocketAddr), mpsc::Sender<Vec<u8>>> =
+                HashMap::new();
+            let mut buf = vec![0u8; udp::MAX_DATAGRAM_SIZE];
+            // Flows end by themselves once idle, and are periodically removed from the map after
+            let mut expiry =
+                tokio::time::interval(self.pi.cfg.udp_idle_timeout.max(Duration::from_secs(1)));
+            loop {
+                let res = tokio::select! {
+                    res = socket::recv_from_orig_dst(&self.socket, &mut buf) => res,
+                    _ = expiry.tick() => {
+                        flows.retain(|_, flow| !flow.is_closed());
+                        continue;
+                    }
+                };
+                let (n, src, dst) = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("Failed to receive datagram {}", e);
+                        continue;
+                    }
+                };
+                let mut payload = buf[..n].to_vec();
+                if let Some(flow) = flows.get(&(src, dst)) {
+                    match flow.try_send(payload) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(_)) => {
+                            debug!("dropping datagram from {src} to {dst}: flow is backed up");
+                            continue;
+                        }
+                        // The flow has ended, so this datagram starts a new one
+                        Err(TrySendError::Closed(p)) => payload = p,
+                    }
+                }
+                let (tx, rx) = mpsc::channel(FLOW_QUEUE_SIZE);
+                tx.try_send(payload).expect("new channel has capacity");
+                flows.insert((src, dst), tx);
+                tokio::spawn(proxy_flow(self.pi.clone(), src, dst, rx));
+            }
+        };
+
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                info!("outbound udp drained");
+            }
+        }
+    }
+}
+
+// proxy_flow relays the datagrams of the flow from `src` to `dst` until it ends, sending replies
+// back to `src` as if they came from `dst`.
+async fn proxy_flow(
+    pi: ProxyInputs,
+    src: SocketAddr,
+    dst: SocketAddr,
+    mut datagrams: mpsc::Receiver<Vec<u8>>,
+) {
+    let (replies_tx, mut replies) = mpsc::channel(FLOW_QUEUE_SIZE);
+    let mut flow = match Flow::open(&pi, src.ip(), dst, false, replies_tx).await {
+        Ok(flow) => flow,
+        Err(e) => {
+            warn!("dropping udp flow from {src} to {dst}: {e}");
+            return;
+        }
+    };
+    let reply_socket = match socket::bind_transparent_udp(dst) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("failed to bind reply socket for {dst}: {e}");
+            flow.close(Some(&Error::Io(e)));
+            return;
+        }
+    };
+
+    let idle_timeout = pi.cfg.udp_idle_timeout;
+    let mut deadline = Instant::now() + idle_timeout;
+    let res = loop {
+        tokio::select! {
+            datagram = datagrams.recv() => {
+                let Some(datagram) = datagram else { break Ok(()) };
+                deadline = Instant::now() + idle_timeout;
+                if let Err(e) = flow.send(&datagram).await {
+                    break Err(e);
+                }
+            }
+            reply = replies.recv() => {
+                // The upstream went away
+                let Some(reply) = reply else { break Ok(()) };
+                deadline = Instant::now() + idle_timeout;
+                flow.record_received(reply.payload.len());
+                if let Err(e) = reply_socket.send_to(&reply.payload, src).await {
+                    break Err(e.into());
+                }
+            }
+            _ = tokio::time::sleep_until(deadline) => break Err(Error::IdleTimeout(idle_timeout)),
+        }
+    };
+    debug!("udp flow from {src} to {dst} ended");
+    flow.close(res.as_ref().err());
+}
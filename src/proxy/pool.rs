@@ -0,0 +1,483 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use drain::Watch;
+use hyper::client::conn::SendRequest;
+use hyper::{Body, Request, Response};
+use tracing::{debug, info, trace};
+
+use crate::config;
+use crate::identity::Identity;
+use crate::metrics::pool::PoolCheckoutResult;
+use crate::metrics::{IncrementRecorder, Metrics};
+use crate::proxy::Error;
+
+/// Pool maintains HBONE connections to upstream gateways. Rather than performing a new TCP, TLS,
+/// and HTTP/2 handshake for every proxied connection, CONNECT requests are multiplexed as streams
+/// over existing connections that share the same Key.
+#[derive(Clone)]
+pub struct Pool {
+    state: Arc<PoolState>,
+}
+
+struct PoolState {
+    max_streams_per_conn: u16,
+    unused_release_timeout: Duration,
+    connections: Mutex<HashMap<Key, Vec<Arc<Connection>>>>,
+    // Locked while a connection is established for a key, see Connecting.
+    connecting: Mutex<HashMap<Key, Arc<tokio::sync::Mutex<()>>>>,
+    // Once draining, connections are used for a single stream, and closed after it.
+    draining: AtomicBool,
+    metrics: Arc<Metrics>,
+}
+
+/// Key determines which connections may be shared.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Key {
+    /// The identity presented to the gateway.
+    pub src_id: Identity,
+    /// The address the connection is bound to, when original source is enabled. Such connections
+    /// appear to come from a specific workload, so cannot be shared with others of the same identity.
+    pub src: Option<IpAddr>,
+    /// The gateway the connection is established to.
+    pub dst: SocketAddr,
+    /// The identity the gateway is expected to present.
+    pub dst_id: Option<Identity>,
+}
+
+struct Connection {
+    sender: tokio::sync::Mutex<SendRequest<Body>>,
+    streams: AtomicU16,
+    last_used: Mutex<Instant>,
+}
+
+impl Connection {
+    /// try_reserve claims a stream on the connection, if it has not reached `max` streams.
+    fn try_reserve(&self, max: u16) -> bool {
+        self.streams
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok()
+    }
+
+    fn is_unused_for(&self, timeout: Duration) -> bool {
+        self.streams.load(Ordering::SeqCst) == 0
+            && self.last_used.lock().unwrap().elapsed() >= timeout
+    }
+}
+
+/// PooledStream represents a stream reserved on a pooled connection. The reservation is released
+/// once it is dropped, so it should be held for as long as the tunnel is in use.
+pub struct PooledStream {
+    conn: Arc<Connection>,
+}
+
+impl Drop for PooledStream {
+    fn drop(&mut self) {
+        *self.conn.last_used.lock().unwrap() = Instant::now();
+        self.conn.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Pooled is the outcome of sending a request over a pooled connection.
+enum Pooled {
+    Sent(Result<(Response<Body>, PooledStream), Error>),
+    /// No pooled connection had spare capacity, so the request is returned unsent.
+    Unavailable(Request<Body>),
+}
+
+/// Connecting is held while establishing a connection for a key, so that concurrent requests for
+/// it can use that connection rather than each establishing their own.
+struct Connecting {
+    state: Arc<PoolState>,
+    key: Key,
+    _guard: tokio::sync::OwnedMutexGuard<()>,
+}
+
+impl Drop for Connecting {
+    fn drop(&mut self) {
+        let mut connecting = self.state.connecting.lock().unwrap();
+        // Only the map and our guard reference the lock if nobody else is waiting for it
+        if connecting
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 2)
+        {
+            connecting.remove(&self.key);
+        }
+    }
+}
+
+/// copy_request copies `req`, so it can be sent again. HBONE requests have no body, and the only
+/// extension they carry is the protocol of an extended CONNECT.
+fn copy_request(req: &Request<Body>) -> Request<Body> {
+    let mut copy = Request::new(Body::empty());
+    *copy.method_mut() = req.method().clone();
+    *copy.uri_mut() = req.uri().clone();
+    *copy.version_mut() = req.version();
+    *copy.headers_mut() = req.headers().clone();
+    if let Some(protocol) = req.extensions().get::<hyper::ext::Protocol>() {
+        copy.extensions_mut().insert(protocol.clone());
+    }
+    copy
+}
+
+impl Pool {
+    pub fn new(cfg: &config::Config, metrics: Arc<Metrics>) -> Pool {
+        Pool {
+            state: Arc::new(PoolState {
+                max_streams_per_conn: cfg.pool_max_streams_per_conn.max(1),
+                unused_release_timeout: cfg.pool_unused_release_timeout,
+                connections: Default::default(),
+                connecting: Default::default(),
+                draining: AtomicBool::new(false),
+                metrics,
+            }),
+        }
+    }
+
+    /// send_request sends `req` over a pooled connection for `key` with spare capacity. If there is
+    /// none, `connect` is used to establish a new connection, which is added to the pool. Concurrent
+    /// requests for the same key wait for a connection being established, rather than each
+    /// establishing their own. If `req` fails on a pooled connection, it is retried once on a new one.
+    pub async fn send_request<F, Fut>(
+        &self,
+        key: Key,
+        req: Request<Body>,
+        connect: F,
+    ) -> Result<(Response<Body>, PooledStream), Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<SendRequest<Body>, Error>>,
+    {
+        let retry = copy_request(&req);
+        let mut req = req;
+        let mut connecting = None;
+        let req = loop {
+            match self.send_pooled(&key, req).await {
+                Pooled::Sent(Ok(sent)) => return Ok(sent),
+                // A pooled connection may have been closed by the gateway just as it was reused.
+                // The tunnel has not been established, so sending the request again is safe.
+                Pooled::Sent(Err(e)) => {
+                    debug!(
+                        ?key,
+                        "request on pooled HBONE connection failed, retrying: {e}"
+                    );
+                    break retry;
+                }
+                Pooled::Unavailable(unsent) if connecting.is_none() => {
+                    // Once we are the one connecting, any connection established meanwhile is
+                    // checked for before establishing another
+                    connecting = Some(self.connecting(&key).await);
+                    req = unsent;
+                }
+                Pooled::Unavailable(unsent) => break unsent,
+            }
+        };
+
+        let _connecting = match connecting {
+            Some(connecting) => connecting,
+            None => self.connecting(&key).await,
+        };
+        let sender = connect().await?;
+        self.state.metrics.increment(&PoolCheckoutResult::Miss);
+        let conn = Arc::new(Connection {
+            sender: tokio::sync::Mutex::new(sender),
+            streams: AtomicU16::new(1),
+            last_used: Mutex::new(Instant::now()),
+        });
+        self.add(key, conn.clone());
+        let stream = PooledStream { conn };
+        let response = stream.conn.sender.lock().await.send_request(req);
+        Ok((response.await?, stream))
+    }
+
+    /// send_pooled sends `req` over a pooled connection for `key` with spare capacity, if any.
+    async fn send_pooled(&self, key: &Key, req: Request<Body>) -> Pooled {
+        while let Some(conn) = self.reserve(key) {
+            let stream = PooledStream { conn };
+            let mut sender = stream.conn.sender.lock().await;
+            match futures::future::poll_fn(|cx| sender.poll_ready(cx)).await {
+                Ok(()) => {
+                    trace!(?key, "reusing pooled HBONE connection");
+                    self.state.metrics.increment(&PoolCheckoutResult::Hit);
+                    let response = sender.send_request(req);
+                    drop(sender);
+                    return match response.await {
+                        Ok(response) => Pooled::Sent(Ok((response, stream))),
+                        Err(e) => {
+                            self.remove(key, &stream.conn);
+                            Pooled::Sent(Err(e.into()))
+                        }
+                    };
+                }
+                Err(e) => {
+                    debug!(?key, "pooled HBONE connection is no longer usable: {e}");
+                    drop(sender);
+                    self.remove(key, &stream.conn);
+                }
+            }
+        }
+        Pooled::Unavailable(req)
+    }
+
+    /// connecting waits until no other connection is being established for `key`. Until the
+    /// returned value is dropped, others wait for us in turn.
+    async fn connecting(&self, key: &Key) -> Connecting {
+        let lock = self
+            .state
+            .connecting
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        Connecting {
+            _guard: lock.lock_owned().await,
+            state: self.state.clone(),
+            key: key.clone(),
+        }
+    }
+
+    /// run periodically releases connections which have had no active streams for longer than the
+    /// configured timeout. Dropping a connection closes it once hyper has no more work for it.
+    /// Once `drain` is signaled, all connections are released and no more are pooled.
+    pub async fn run(self, drain: Watch) {
+        let period = self
+            .state
+            .unused_release_timeout
+            .max(Duration::from_secs(1));
+        let mut interval = tokio::time::interval(period);
+        let drained = drain.signaled();
+        tokio::pin!(drained);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.release_unused(),
+                _ = &mut drained => {
+                    self.drain();
+                    info!("pool drained");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// drain stops connections from being reused. Connections with active streams are closed once
+    /// those streams complete.
+    fn drain(&self) {
+        let mut connections = self.state.connections.lock().unwrap();
+        self.state.draining.store(true, Ordering::SeqCst);
+        connections.clear();
+    }
+
+    fn release_unused(&self) {
+        let timeout = self.state.unused_release_timeout;
+        let mut connections = self.state.connections.lock().unwrap();
+        connections.retain(|key, conns| {
+            conns.retain(|c| {
+                let unused = c.is_unused_for(timeout);
+                if unused {
+                    debug!(?key, "releasing unused HBONE connection");
+                }
+                !unused
+            });
+            !conns.is_empty()
+        });
+    }
+
+    fn add(&self, key: Key, conn: Arc<Connection>) {
+        let mut connections = self.state.connections.lock().unwrap();
+        // Checked with the lock held, so the connection cannot be added after the pool is drained
+        if self.state.draining.load(Ordering::SeqCst) {
+            trace!(
+                ?key,
+                "established new HBONE connection, not pooling as we are draining"
+            );
+            return;
+        }
+        trace!(?key, "established new pooled HBONE connection");
+        connections.entry(key).or_default().push(conn);
+    }
+
+    fn reserve(&self, key: &Key) -> Option<Arc<Connection>> {
+        let connections = self.state.connections.lock().unwrap();
+        connections
+            .get(key)?
+            .iter()
+            .find(|c| c.try_reserve(self.state.max_streams_per_conn))
+            .cloned()
+    }
+
+    fn remove(&self, key: &Key, conn: &Arc<Connection>) {
+        let mut connections = self.state.connections.lock().unwrap();
+        if let Some(conns) = connections.get_mut(key) {
+            conns.retain(|c| !Arc::ptr_eq(c, conn));
+            if conns.is_empty() {
+                connections.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::AtomicUsize;
+
+    use hyper::service::service_fn;
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+    use crate::test_helpers;
+
+    fn key() -> Key {
+        Key {
+            src_id: Identity::default(),
+            src: None,
+            dst: "127.0.0.1:15008".parse().unwrap(),
+            dst_id: None,
+        }
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("127.0.0.2:80")
+            .method(hyper::Method::CONNECT)
+            .version(hyper::Version::HTTP_2)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// Gateway counts the connections established to it. Requests on the first connection fail
+    /// after `failing_after` of them have been served.
+    #[derive(Clone, Default)]
+    struct Gateway {
+        connections: Arc<AtomicUsize>,
+        failing_after: Option<usize>,
+    }
+
+    impl Gateway {
+        async fn connect(&self) -> Result<SendRequest<Body>, Error> {
+            let first = self.connections.fetch_add(1, Ordering::SeqCst) == 0;
+            let failing_after = self.failing_after.filter(|_| first);
+            let served = Arc::new(AtomicUsize::new(0));
+            let (client, server) = tokio::io::duplex(1024);
+            let service = service_fn(move |_| {
+                let served = served.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match failing_after {
+                        Some(n) if served >= n => Err(io::Error::other("failing")),
+                        _ => Ok(Response::new(Body::empty())),
+                    }
+                }
+            });
+            tokio::spawn(
+                hyper::server::conn::Http::new()
+                    .http2_only(true)
+                    .serve_connection(server, service),
+            );
+            // Connecting takes long enough for concurrent requests to overlap
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let (sender, connection) = hyper::client::conn::Builder::new()
+                .http2_only(true)
+                .handshake(client)
+                .await
+                .map_err(Error::HttpHandshake)?;
+            tokio::spawn(connection);
+            Ok(sender)
+        }
+
+        fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+    }
+
+    fn pool() -> (Pool, Registry) {
+        let mut registry = Registry::default();
+        let metrics = Arc::new(Metrics::from(&mut registry));
+        (Pool::new(&test_helpers::test_config(), metrics), registry)
+    }
+
+    fn checkouts(registry: &Registry, result: &str) -> u64 {
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, registry).unwrap();
+        let prefix = format!("istio_hbone_pool_checkouts_total{{result=\"{result}\"}} ");
+        encoded
+            .lines()
+            .find_map(|l| l.strip_prefix(&prefix))
+            .map_or(0, |v| v.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_connection() {
+        let (pool, registry) = pool();
+        let gateway = Gateway::default();
+        let sent = futures::future::join_all(
+            (0..5).map(|_| pool.send_request(key(), request(), || gateway.connect())),
+        )
+        .await;
+
+        assert!(sent.iter().all(|r| r.is_ok()));
+        assert_eq!(gateway.connections(), 1);
+        assert_eq!(checkouts(&registry, "Miss"), 1);
+        assert_eq!(checkouts(&registry, "Hit"), 4);
+        assert!(pool.state.connecting.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_connect_is_not_a_miss() {
+        let (pool, registry) = pool();
+        let refused = || async { Err(io::Error::from(io::ErrorKind::ConnectionRefused).into()) };
+        assert!(pool.send_request(key(), request(), refused).await.is_err());
+        assert_eq!(checkouts(&registry, "Miss"), 0);
+
+        let gateway = Gateway::default();
+        assert!(pool
+            .send_request(key(), request(), || gateway.connect())
+            .await
+            .is_ok());
+        assert_eq!(checkouts(&registry, "Miss"), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_request_is_retried_on_new_connection() {
+        let (pool, registry) = pool();
+        let gateway = Gateway {
+            failing_after: Some(1),
+            ..Default::default()
+        };
+        let (_, first) = pool
+            .send_request(key(), request(), || gateway.connect())
+            .await
+            .unwrap();
+        drop(first);
+        let retried = pool
+            .send_request(key(), request(), || gateway.connect())
+            .await;
+
+        assert!(retried.is_ok());
+        assert_eq!(gateway.connections(), 2);
+        assert_eq!(checkouts(&registry, "Hit"), 1);
+        assert_eq!(checkouts(&registry, "Miss"), 2);
+        // The connection the request failed on is no longer pooled
+        assert_eq!(pool.state.connections.lock().unwrap()[&key()].len(), 1);
+    }
+}
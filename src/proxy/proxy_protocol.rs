@@ -0,0 +1,372 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::config::Config;
+use crate::identity::Identity;
+use crate::proxy::Error;
+use crate::socket;
+
+// The signature that starts a version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// TLV types for the identity of the peer, from the range reserved for custom use.
+const PP2_TYPE_SPIFFE_ID: u8 = 0xe0;
+const PP2_TYPE_TRUST_DOMAIN: u8 = 0xe1;
+
+// How long a trusted peer has to send the header once connected.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+// The longest version 1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// accept returns the source and destination of a connection. Connections from peers in the
+/// trusted CIDRs must start with a PROXY protocol header, which carries the addresses of the
+/// connection the peer accepted; for all other connections, these are the peer address and the
+/// original destination.
+pub(super) async fn accept(
+    cfg: &Config,
+    stream: &mut TcpStream,
+) -> Result<(SocketAddr, SocketAddr), Error> {
+    let peer = socket::to_canonical(stream.peer_addr()?);
+    let orig_dst = socket::orig_dst_addr_or_default(stream);
+    if !cfg
+        .proxy_protocol_trusted_cidrs
+        .iter()
+        .any(|cidr| cidr.contains(&peer.ip()))
+    {
+        return Ok((peer, orig_dst));
+    }
+    let header = tokio::time::timeout(HEADER_TIMEOUT, read_header(stream))
+        .await
+        .map_err(|_| Error::ProxyProtocol("timed out reading header".to_string()))??;
+    match header {
+        Some((src, dst)) => {
+            debug!(%peer, %src, %dst, "accepted PROXY protocol header");
+            Ok((src, dst))
+        }
+        // The peer is not relaying a connection, such as for a health check
+        None => Ok((peer, orig_dst)),
+    }
+}
+
+/// header encodes a version 2 header for a connection from `src` to `dst`. The SPIFFE ID and trust
+/// domain of the peer's `identity`, if it was verified, are sent as TLVs.
+pub(super) fn header(src: SocketAddr, dst: SocketAddr, identity: Option<&Identity>) -> Vec<u8> {
+    let mut addresses = Vec::with_capacity(36);
+    let family = match (src, dst) {
+        (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+            addresses.extend_from_slice(&src.ip().octets());
+            addresses.extend_from_slice(&dst.ip().octets());
+            // TCP over IPv4
+            0x11
+        }
+        // Both addresses must be of the same family, so IPv4 addresses are mapped when mixed
+        _ => {
+            let v6 = |addr: SocketAddr| match addr.ip() {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            addresses.extend_from_slice(&v6(src).octets());
+            addresses.extend_from_slice(&v6(dst).octets());
+            // TCP over IPv6
+            0x21
+        }
+    };
+    addresses.extend_from_slice(&src.port().to_be_bytes());
+    addresses.extend_from_slice(&dst.port().to_be_bytes());
+    if let Some(identity @ Identity::Spiffe { trust_domain, .. }) = identity {
+        for (tlv, value) in [
+            (PP2_TYPE_SPIFFE_ID, identity.to_string()),
+            (PP2_TYPE_TRUST_DOMAIN, trust_domain.clone()),
+        ] {
+            addresses.push(tlv);
+            addresses.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            addresses.extend_from_slice(value.as_bytes());
+        }
+    }
+
+    let mut header = V2_SIGNATURE.to_vec();
+    // Version 2, PROXY
+    header.extend_from_slice(&[0x21, family]);
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.extend(addresses);
+    header
+}
+
+// read_header reads a version 1 or 2 header, returning the source and destination it carries.
+// Returns None for headers without addresses, such as those from health checks, for which the
+// connection's own addresses apply. Nothing after the header is consumed.
+async fn read_header<R: AsyncRead + Unpin>(
+    r: &mut R,
+) -> Result<Option<(SocketAddr, SocketAddr)>, Error> {
+    // The shortest header of either version is longer than the version 2 signature
+    let mut start = [0u8; V2_SIGNATURE.len()];
+    r.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        read_v2(r).await
+    } else if start.starts_with(b"PROXY ") {
+        let mut line = start.to_vec();
+        let mut byte = [0u8];
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(Error::ProxyProtocol("header too long".to_string()));
+            }
+            r.read_exact(&mut byte).await?;
+            line.push(byte[0]);
+        }
+        parse_v1(&line[..line.len() - 2])
+    } else {
+        Err(Error::ProxyProtocol("missing header".to_string()))
+    }
+}
+
+// parse_v1 parses a version 1 header, without its trailing CRLF.
+#[allow(clippy::result_large_err)]
+fn parse_v1(line: &[u8]) -> Result<Option<(SocketAddr, SocketAddr)>, Error> {
+    let invalid = || Error::ProxyProtocol("invalid version 1 header".to_string());
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let mut parts = line.split(' ').skip(1);
+    let ipv4 = match parts.next() {
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        // The rest of the line may contain anything, and must be ignored
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid()),
+    };
+    let (Some(src), Some(dst), Some(src_port), Some(dst_port), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(invalid());
+    };
+    let parse_ip = |ip: &str| -> Result<IpAddr, Error> {
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        if ip.is_ipv4() != ipv4 {
+            return Err(invalid());
+        }
+        Ok(ip)
+    };
+    let parse_port = |port: &str| port.parse::<u16>().map_err(|_| invalid());
+    Ok(Some((
+        socket::to_canonical(SocketAddr::new(parse_ip(src)?, parse_port(src_port)?)),
+        socket::to_canonical(SocketAddr::new(parse_ip(dst)?, parse_port(dst_port)?)),
+    )))
+}
+
+// read_v2 reads the rest of a version 2 header, after its signature.
+async fn read_v2<R: AsyncRead + Unpin>(
+    r: &mut R,
+) -> Result<Option<(SocketAddr, SocketAddr)>, Error> {
+    // Version and command, address family and transport, then the length of the rest
+    let mut header = [0u8; 4];
+    r.read_exact(&mut header).await?;
+    let mut rest = vec![0u8; BigEndian::read_u16(&header[2..]) as usize];
+    r.read_exact(&mut rest).await?;
+
+    if header[0] >> 4 != 2 {
+        return Err(Error::ProxyProtocol(format!(
+            "unsupported version {}",
+            header[0] >> 4
+        )));
+    }
+    match header[0] & 0x0f {
+        // LOCAL, sent by the peer on its own behalf
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        cmd => return Err(Error::ProxyProtocol(format!("unsupported command {cmd}"))),
+    }
+    let truncated = || Error::ProxyProtocol("truncated addresses".to_string());
+    // Any TLVs follow the addresses, and are ignored
+    let addresses = match header[1] {
+        // TCP over IPv4
+        0x11 => {
+            let b = rest.get(..12).ok_or_else(truncated)?;
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&b[..4]).unwrap());
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&b[4..8]).unwrap());
+            (
+                SocketAddr::new(src.into(), BigEndian::read_u16(&b[8..])),
+                SocketAddr::new(dst.into(), BigEndian::read_u16(&b[10..])),
+            )
+        }
+        // TCP over IPv6
+        0x21 => {
+            let b = rest.get(..36).ok_or_else(truncated)?;
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&b[..16]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&b[16..32]).unwrap());
+            (
+                SocketAddr::new(src.into(), BigEndian::read_u16(&b[32..])),
+                SocketAddr::new(dst.into(), BigEndian::read_u16(&b[34..])),
+            )
+        }
+        // Other families, such as UNSPEC and UNIX, must be treated as LOCAL
+        _ => return Ok(None),
+    };
+    Ok(Some((
+        socket::to_canonical(addresses.0),
+        socket::to_canonical(addresses.1),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20 | command, family]);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[test_case(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\n", Some(("10.0.0.1:1234", "10.0.0.2:80")); "v1 ipv4")]
+    #[test_case(b"PROXY TCP6 fd00::1 fd00::2 1234 443\r\n", Some(("[fd00::1]:1234", "[fd00::2]:443")); "v1 ipv6")]
+    #[test_case(b"PROXY UNKNOWN\r\n", None; "v1 unknown")]
+    #[test_case(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n", None; "v1 unknown with addresses")]
+    #[tokio::test]
+    async fn read_v1(header: &[u8], expected: Option<(&str, &str)>) {
+        let mut stream = header.to_vec();
+        stream.extend_from_slice(b"data");
+        let mut r = stream.as_slice();
+        let addresses = read_header(&mut r).await.unwrap();
+        let expected = expected.map(|(src, dst)| (src.parse().unwrap(), dst.parse().unwrap()));
+        assert_eq!(addresses, expected);
+        // The data after the header is left for the application
+        assert_eq!(r, b"data");
+    }
+
+    #[test_case(b"GET / HTTP/1.1\r\n\r\n"; "no header")]
+    #[test_case(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234\r\n"; "missing port")]
+    #[test_case(b"PROXY TCP4 fd00::1 fd00::2 1234 80\r\n"; "wrong family")]
+    #[test_case(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 65536\r\n"; "invalid port")]
+    #[test_case(b"PROXY UDP4 10.0.0.1 10.0.0.2 1234 80\r\n"; "udp")]
+    #[test_case(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80 and a lot more that makes the header too long for the spec to allow\r\n"; "too long")]
+    #[tokio::test]
+    async fn read_invalid(stream: &[u8]) {
+        let mut r = stream;
+        assert!(read_header(&mut r).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_v2_ipv4() {
+        let mut stream = v2(
+            0x1,
+            0x11,
+            &[
+                10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0xd2, 0x00, 0x50, 0x01, 0x00, 0x01, 0xff,
+            ],
+        );
+        stream.extend_from_slice(b"data");
+        let mut r = stream.as_slice();
+        let addresses = read_header(&mut r).await.unwrap();
+        assert_eq!(
+            addresses,
+            Some((
+                "10.0.0.1:1234".parse().unwrap(),
+                "10.0.0.2:80".parse().unwrap()
+            ))
+        );
+        // The TLV after the addresses is skipped, but nothing more
+        assert_eq!(r, b"data");
+    }
+
+    #[tokio::test]
+    async fn read_v2_ipv6() {
+        let mut addresses = Vec::new();
+        addresses.extend_from_slice(&"fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+        // IPv4-mapped addresses are canonicalized
+        addresses.extend_from_slice(&"::ffff:10.0.0.2".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&[0x04, 0xd2, 0x01, 0xbb]);
+        let stream = v2(0x1, 0x21, &addresses);
+        let addresses = read_header(&mut stream.as_slice()).await.unwrap();
+        assert_eq!(
+            addresses,
+            Some((
+                "[fd00::1]:1234".parse().unwrap(),
+                "10.0.0.2:443".parse().unwrap()
+            ))
+        );
+    }
+
+    #[test_case(0x0, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0xd2, 0x00, 0x50]; "local")]
+    #[test_case(0x1, 0x00, &[]; "unspec")]
+    #[test_case(0x1, 0x31, &[0u8; 216]; "unix")]
+    #[tokio::test]
+    async fn read_v2_without_addresses(command: u8, family: u8, addresses: &[u8]) {
+        let stream = v2(command, family, addresses);
+        assert_eq!(read_header(&mut stream.as_slice()).await.unwrap(), None);
+    }
+
+    #[test_case("10.0.0.1:0", "10.0.0.2:80", 0x11; "ipv4")]
+    #[test_case("[fd00::1]:0", "[fd00::2]:80", 0x21; "ipv6")]
+    #[test_case("10.0.0.1:0", "[fd00::2]:80", 0x21; "mixed")]
+    #[tokio::test]
+    async fn header_round_trip(src: &str, dst: &str, family: u8) {
+        let (src, dst): (SocketAddr, SocketAddr) = (src.parse().unwrap(), dst.parse().unwrap());
+        let header = header(src, dst, None);
+        assert_eq!(header[13], family);
+        let addresses = read_header(&mut header.as_slice()).await.unwrap();
+        assert_eq!(addresses, Some((src, dst)));
+    }
+
+    #[test]
+    fn header_identity() {
+        let identity = Identity::Spiffe {
+            trust_domain: "cluster.local".to_string(),
+            namespace: "ns".to_string(),
+            service_account: "sa".to_string(),
+        };
+        let header = header(
+            "10.0.0.1:0".parse().unwrap(),
+            "10.0.0.2:80".parse().unwrap(),
+            Some(&identity),
+        );
+        let spiffe_id = b"spiffe://cluster.local/ns/ns/sa/sa";
+        let expected_tlvs = [
+            &[PP2_TYPE_SPIFFE_ID, 0x00, spiffe_id.len() as u8][..],
+            spiffe_id,
+            &[PP2_TYPE_TRUST_DOMAIN, 0x00, 13],
+            b"cluster.local",
+        ]
+        .concat();
+        // The TLVs follow the signature, 4 bytes of header, and 12 bytes of addresses
+        assert_eq!(header[28..], expected_tlvs);
+        assert_eq!(
+            BigEndian::read_u16(&header[14..16]) as usize,
+            12 + expected_tlvs.len()
+        );
+    }
+
+    #[test_case(&v2(0x1, 0x11, &[10, 0, 0, 1]); "truncated")]
+    #[test_case(&v2(0x2, 0x11, &[]); "unknown command")]
+    #[test_case(&[&V2_SIGNATURE[..], &[0x11, 0x11, 0x00, 0x00]].concat(); "version 1")]
+    #[tokio::test]
+    async fn read_v2_invalid(stream: &[u8]) {
+        let mut r = stream;
+        assert!(read_header(&mut r).await.is_err());
+    }
+}
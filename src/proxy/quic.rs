@@ -0,0 +1,731 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HBONE over HTTP/3. QUIC avoids head of line blocking between the tunnels multiplexed on a
+//! connection, which HTTP/2 suffers from on lossy networks. Gateways advertise HTTP/3 with the
+//! Alt-Svc header of their HTTP/2 responses, and it is used for later tunnels to them; if it fails
+//! before the request is sent, the tunnel falls back to HTTP/2.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, Bytes, BytesMut};
+use drain::Watch;
+use hyper::header::{HeaderMap, ALT_SVC};
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, trace, warn, Instrument};
+
+use crate::config::Config;
+use crate::identity::SecretManager;
+use crate::proxy::inbound::Inbound;
+use crate::proxy::{pool, ConnectionLimits, Error, PendingTunnel, ProxyInputs, Tunnel};
+use crate::rbac;
+use crate::socket::{self, to_canonical};
+use crate::tls::quic::{quic_peer_identity, NoCertificate};
+
+// How long HTTP/3 is not used for a gateway after it failed.
+const BROKEN_BACKOFF: Duration = Duration::from_secs(300);
+// How long an Alt-Svc advertisement is valid for if it does not say, from RFC 7838.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(86400);
+// Keeps connections with idle tunnels from hitting the QUIC idle timeout.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+// The buffer between a tunnel's stream and the connection it is relayed to.
+const TUNNEL_BUFFER_SIZE: usize = 64 * 1024;
+
+type ServerStream<S> = h3::server::RequestStream<S, Bytes>;
+type ClientStream<S> = h3::client::RequestStream<S, Bytes>;
+
+/// InboundH3 serves HBONE over HTTP/3, on a UDP port alongside the inbound listener. Requests are
+/// served just as they are over HTTP/2, including mTLS and authorization policy.
+pub(super) struct InboundH3 {
+    pi: ProxyInputs,
+    endpoint: quinn::Endpoint,
+    drain: Watch,
+}
+
+impl InboundH3 {
+    pub(super) async fn new(
+        pi: ProxyInputs,
+        addr: SocketAddr,
+        drain: Watch,
+    ) -> Result<InboundH3, Error> {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| Error::Bind(addr, e))?;
+        // Best effort, as for the inbound listener. This allows receiving datagrams redirected from
+        // workloads, and replying to them from the workload's address.
+        let transparent = socket::set_udp_transparent(&socket).is_ok();
+        // The certificate depends on the workload each connection is addressed to, so is chosen as
+        // connections are accepted
+        let endpoint = quinn::Endpoint::new(
+            quinn::EndpointConfig::default(),
+            Some(NoCertificate::server_config()),
+            socket.into_std()?,
+            Arc::new(quinn::TokioRuntime),
+        )?;
+        info!(
+            address=%endpoint.local_addr().unwrap(),
+            component="inbound_h3",
+            transparent,
+            "listener established",
+        );
+        Ok(InboundH3 {
+            pi,
+            endpoint,
+            drain,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.endpoint.local_addr().unwrap()
+    }
+
+    pub(super) async fn run(self) {
+        let port = self.address().port();
+        let accept = async {
+            while let Some(incoming) = self.endpoint.accept().await {
+                let pi = self.pi.clone();
+                let drain = self.drain.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = serve_connection(pi, incoming, port, drain).await {
+                            warn!("HTTP/3 connection failed: {e}");
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+        };
+        tokio::select! {
+            _ = accept => {}
+            _ = self.drain.clone().signaled() => {
+                info!("inbound h3 drained");
+            }
+        }
+    }
+}
+
+// serve_connection serves the requests on a QUIC connection until it is closed. Once draining, the
+// peer is told to open no more requests, and in-flight requests are allowed to complete.
+async fn serve_connection(
+    pi: ProxyInputs,
+    incoming: quinn::Incoming,
+    port: u16,
+    drain: Watch,
+) -> Result<(), Error> {
+    let Some(dst_ip) = incoming.local_ip() else {
+        incoming.refuse();
+        return Err(Error::Io(io::Error::other(
+            "destination address is unknown",
+        )));
+    };
+    let dst = to_canonical(SocketAddr::new(dst_ip, port));
+    let identity = pi
+        .workloads
+        .fetch_workload(&dst.ip())
+        .await
+        .ok_or(Error::UnknownDestination(dst.ip()))?
+        .identity();
+    debug!(destination=%dst, %identity, "fetching cert");
+    let cert = pi.cert_manager.fetch_certificate(&identity).await?;
+    let conn = incoming
+        .accept_with(Arc::new(cert.quic_server_config()?))?
+        .await?;
+    let rbac_conn = rbac::Connection {
+        src_identity: quic_peer_identity(&conn),
+        src_ip: to_canonical(conn.remote_address()).ip(),
+        dst,
+    };
+    debug!(conn=%rbac_conn, "accepted HTTP/3 connection");
+
+    let mut h3 = h3::server::Connection::new(h3_quinn::Connection::new(conn.clone())).await?;
+    // Each request holds a sender until its tunnel ends, so the receiver sees the connection idle
+    let (active, mut idle) = mpsc::channel::<()>(1);
+    let mut active = Some(active);
+    let shutdown = drain.signaled();
+    tokio::pin!(shutdown);
+    let mut draining = None;
+    loop {
+        tokio::select! {
+            resolver = h3.accept() => {
+                let resolver = match resolver {
+                    Ok(Some(resolver)) => resolver,
+                    Ok(None) => break,
+                    // The peer closed the connection
+                    Err(e) if e.is_h3_no_error() => break,
+                    Err(e) => return Err(e.into()),
+                };
+                let pi = pi.clone();
+                let rbac_conn = rbac_conn.clone();
+                let active = active.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = serve_request(pi, rbac_conn, resolver, active).await {
+                            debug!("HTTP/3 request failed: {e}");
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            release = &mut shutdown, if draining.is_none() => {
+                h3.shutdown(0).await?;
+                // Held until the connection closes, to delay the end of the drain until then
+                draining = Some(release);
+                active = None;
+            }
+            // Clients do not close connections once told to go away, so as for HTTP/2, the
+            // connection is closed once its last request is done
+            _ = idle.recv(), if active.is_none() => {
+                let code = h3::error::Code::H3_NO_ERROR.value();
+                conn.close(code.try_into().expect("valid code"), b"draining");
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn serve_request(
+    pi: ProxyInputs,
+    conn: rbac::Connection,
+    resolver: h3::server::RequestResolver<h3_quinn::Connection, Bytes>,
+    active: Option<mpsc::Sender<()>>,
+) -> Result<(), Error> {
+    let (req, mut stream) = resolver.resolve_request().await?;
+    let Some(req) = to_hyper_request(req) else {
+        let response = http1::Response::builder()
+            .status(http1::StatusCode::BAD_REQUEST)
+            .body(())
+            .expect("valid response");
+        stream.send_response(response).await?;
+        return Ok(stream.finish().await?);
+    };
+    let (tunnel_tx, tunnel_rx) = oneshot::channel();
+    let tunnel: PendingTunnel = Box::pin(async move {
+        tunnel_rx.await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stream closed before the response",
+            )
+        })
+    });
+    let response = Inbound::serve_connect(
+        pi.workloads,
+        conn,
+        pi.cfg.enable_original_source.unwrap_or_default(),
+        // The destination service is not known for inbound connections, so global limits apply
+        ConnectionLimits::new(&pi.cfg, None),
+        pi.cfg.socket_options,
+        pi.cfg.udp_idle_timeout,
+        req,
+        tunnel,
+        pi.metrics,
+        pi.rate_limiter,
+        pi.connections,
+    )
+    .await?;
+    let status = response.status();
+    stream.send_response(to_h3_response(response)).await?;
+    if status != StatusCode::OK {
+        return Ok(stream.finish().await?);
+    }
+    let (send, recv) = stream.split();
+    // If the tunnel is no longer wanted, the stream is dropped, which resets it
+    let _ = tunnel_tx.send(Box::new(bridge(send, recv, active)) as Box<dyn Tunnel>);
+    Ok(())
+}
+
+// to_hyper_request converts an HTTP/3 request to the form served over HTTP/2. The URI of a
+// CONNECT request is just its authority, while HTTP/3 also fills in a scheme and path.
+fn to_hyper_request(req: http1::Request<()>) -> Option<Request<Body>> {
+    let (parts, ()) = req.into_parts();
+    let uri = match parts.uri.authority() {
+        Some(authority) => authority.as_str(),
+        None => "/",
+    };
+    let mut builder = Request::builder()
+        .method(parts.method.as_str())
+        .uri(uri)
+        .version(hyper::Version::HTTP_3);
+    for (name, value) in parts.headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder.body(Body::empty()).ok()
+}
+
+fn to_h3_response(response: Response<Body>) -> http1::Response<()> {
+    let mut builder = http1::Response::builder().status(response.status().as_u16());
+    for (name, value) in response.headers() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder.body(()).expect("valid response")
+}
+
+fn to_h3_request(request: &Request<Body>) -> http1::Request<()> {
+    let mut builder = http1::Request::builder()
+        .method(request.method().as_str())
+        .uri(request.uri().to_string());
+    for (name, value) in request.headers() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder.body(()).expect("valid request")
+}
+
+/// alt_svc returns the Alt-Svc header value advertising HTTP/3 on `port` of the same host.
+pub(super) fn alt_svc(port: u16) -> hyper::header::HeaderValue {
+    hyper::header::HeaderValue::from_str(&format!("h3=\":{port}\"")).expect("valid header")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum AltSvc {
+    // The alternatives previously advertised are no longer valid.
+    Clear,
+    // HTTP/3 is served on `port` of the same host, for `max_age`.
+    H3 { port: u16, max_age: Duration },
+}
+
+// parse_alt_svc parses an Alt-Svc header value, as defined in RFC 7838. Only HTTP/3 alternatives
+// on the same host are of interest, as gateways are addressed by IP.
+fn parse_alt_svc(value: &str) -> Option<AltSvc> {
+    if value.trim() == "clear" {
+        return Some(AltSvc::Clear);
+    }
+    value.split(',').find_map(|alternative| {
+        let mut params = alternative.split(';');
+        let (protocol, authority) = params.next()?.split_once('=')?;
+        if protocol.trim() != "h3" {
+            return None;
+        }
+        let port = authority
+            .trim()
+            .strip_prefix('"')?
+            .strip_suffix('"')?
+            .strip_prefix(':')?
+            .parse()
+            .ok()?;
+        let max_age = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim() == "ma")
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_MAX_AGE);
+        Some(AltSvc::H3 { port, max_age })
+    })
+}
+
+/// Client sends HBONE requests over HTTP/3 to gateways that advertised it. Like pool::Pool,
+/// requests that share a pool::Key are multiplexed over a single connection.
+#[derive(Clone)]
+pub struct Client {
+    state: Arc<ClientState>,
+}
+
+struct ClientState {
+    cert_manager: Arc<SecretManager>,
+    unused_release_timeout: Duration,
+    // Endpoints are bound lazily, one for each address family.
+    endpoints: Mutex<HashMap<IpAddr, quinn::Endpoint>>,
+    alternatives: Mutex<HashMap<SocketAddr, Alternative>>,
+    connections: Mutex<HashMap<pool::Key, Arc<Connection>>>,
+}
+
+enum Alternative {
+    // The gateway serves HTTP/3 on `port`, until `expires`.
+    Advertised { port: u16, expires: Instant },
+    // HTTP/3 to the gateway failed, so is not used until `until`.
+    Broken { until: Instant },
+}
+
+struct Connection {
+    quic: quinn::Connection,
+    sender: h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>,
+    streams: AtomicU16,
+    last_used: Mutex<Instant>,
+}
+
+impl Connection {
+    fn is_unused_for(&self, timeout: Duration) -> bool {
+        self.streams.load(Ordering::SeqCst) == 0
+            && self.last_used.lock().unwrap().elapsed() >= timeout
+    }
+}
+
+/// PooledConnection holds a tunnel's claim on a pooled connection, which is not released while
+/// any are held.
+pub struct PooledConnection {
+    conn: Arc<Connection>,
+}
+
+impl PooledConnection {
+    fn new(conn: Arc<Connection>) -> PooledConnection {
+        conn.streams.fetch_add(1, Ordering::SeqCst);
+        PooledConnection { conn }
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        *self.conn.last_used.lock().unwrap() = Instant::now();
+        self.conn.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// SendError is why a request could not be sent over HTTP/3.
+#[derive(Debug)]
+pub enum SendError {
+    /// The request never reached the gateway, so it can be sent over HTTP/2 instead.
+    NotSent(Error),
+    /// The request may have been acted on by the gateway, so it must not be sent again.
+    Sent(Error),
+}
+
+impl Client {
+    pub fn new(cfg: &Config, cert_manager: Arc<SecretManager>) -> Client {
+        Client {
+            state: Arc::new(ClientState {
+                cert_manager,
+                unused_release_timeout: cfg.pool_unused_release_timeout,
+                endpoints: Default::default(),
+                alternatives: Default::default(),
+                connections: Default::default(),
+            }),
+        }
+    }
+
+    /// learn records the HTTP/3 alternative the gateway advertised in the headers of a response,
+    /// if any.
+    pub fn learn(&self, gateway: SocketAddr, headers: &HeaderMap) {
+        let Some(alt_svc) = headers
+            .get(ALT_SVC)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_alt_svc)
+        else {
+            return;
+        };
+        let mut alternatives = self.state.alternatives.lock().unwrap();
+        if let Some(Alternative::Broken { until }) = alternatives.get(&gateway) {
+            if *until > Instant::now() {
+                return;
+            }
+        }
+        match alt_svc {
+            AltSvc::Clear => {
+                alternatives.remove(&gateway);
+            }
+            AltSvc::H3 { port, max_age } => {
+                let expires = Instant::now() + max_age;
+                alternatives.insert(gateway, Alternative::Advertised { port, expires });
+            }
+        }
+    }
+
+    /// alternative returns where the gateway serves HTTP/3, if it is known to.
+    pub fn alternative(&self, gateway: SocketAddr) -> Option<SocketAddr> {
+        match self.state.alternatives.lock().unwrap().get(&gateway)? {
+            Alternative::Advertised { port, expires } if *expires > Instant::now() => {
+                Some(SocketAddr::new(gateway.ip(), *port))
+            }
+            _ => None,
+        }
+    }
+
+    /// mark_broken stops HTTP/3 from being used for the gateway for a while, after it failed.
+    pub fn mark_broken(&self, gateway: SocketAddr) {
+        let until = Instant::now() + BROKEN_BACKOFF;
+        self.state
+            .alternatives
+            .lock()
+            .unwrap()
+            .insert(gateway, Alternative::Broken { until });
+    }
+
+    /// send_request sends `request` to `addr` over a pooled connection for `key`, establishing one
+    /// if needed, and returns the tunnel once the gateway accepts it.
+    pub async fn send_request(
+        &self,
+        key: pool::Key,
+        addr: SocketAddr,
+        request: &Request<Body>,
+    ) -> Result<(DuplexStream, PooledConnection), SendError> {
+        let conn = match self.checkout(&key) {
+            Some(conn) => {
+                trace!(?key, "reusing pooled HTTP/3 connection");
+                conn
+            }
+            None => self.connect(key, addr).await.map_err(SendError::NotSent)?,
+        };
+        let conn = PooledConnection::new(conn);
+        let mut sender = conn.conn.sender.clone();
+        let mut stream = sender
+            .send_request(to_h3_request(request))
+            .await
+            .map_err(|e| SendError::NotSent(e.into()))?;
+        let response = stream
+            .recv_response()
+            .await
+            .map_err(|e| SendError::Sent(e.into()))?;
+        let code = response.status().as_u16();
+        if code != 200 {
+            return Err(SendError::Sent(Error::HttpStatus(
+                StatusCode::from_u16(code).expect("valid status"),
+            )));
+        }
+        let (send, recv) = stream.split();
+        Ok((bridge(send, recv, None), conn))
+    }
+
+    fn checkout(&self, key: &pool::Key) -> Option<Arc<Connection>> {
+        let mut connections = self.state.connections.lock().unwrap();
+        let conn = connections.get(key)?;
+        if conn.quic.close_reason().is_some() {
+            connections.remove(key);
+            return None;
+        }
+        Some(conn.clone())
+    }
+
+    async fn connect(&self, key: pool::Key, addr: SocketAddr) -> Result<Arc<Connection>, Error> {
+        let cert = self
+            .state
+            .cert_manager
+            .fetch_certificate(&key.src_id)
+            .await?;
+        let mut cfg = cert.quic_client_config(key.dst_id.as_ref())?;
+        let mut transport = quinn::TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        cfg.transport_config(Arc::new(transport));
+        // The gateway is addressed by IP, so its identity is verified rather than this name
+        let quic = self
+            .endpoint(addr)?
+            .connect_with(cfg, addr, &addr.ip().to_string())?
+            .await?;
+        let (mut driver, sender) = h3::client::new(h3_quinn::Connection::new(quic.clone())).await?;
+        let conn = Arc::new(Connection {
+            quic,
+            sender,
+            streams: AtomicU16::new(0),
+            last_used: Mutex::new(Instant::now()),
+        });
+        trace!(?key, "established new pooled HTTP/3 connection");
+        self.state
+            .connections
+            .lock()
+            .unwrap()
+            .insert(key.clone(), conn.clone());
+
+        let state = self.state.clone();
+        let pooled = conn.clone();
+        tokio::spawn(async move {
+            let period = state.unused_release_timeout.max(Duration::from_secs(1));
+            let mut interval = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    e = futures::future::poll_fn(|cx| driver.poll_close(cx)) => {
+                        debug!(?key, "HTTP/3 connection closed: {e}");
+                        break;
+                    }
+                    _ = interval.tick() => {
+                        if pooled.is_unused_for(state.unused_release_timeout) {
+                            debug!(?key, "releasing unused HTTP/3 connection");
+                            let code = h3::error::Code::H3_NO_ERROR.value();
+                            pooled.quic.close(code.try_into().expect("valid code"), b"unused");
+                        }
+                    }
+                }
+            }
+            state.remove(&key, &pooled);
+        });
+        Ok(conn)
+    }
+
+    fn endpoint(&self, addr: SocketAddr) -> Result<quinn::Endpoint, Error> {
+        let bind = match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let mut endpoints = self.state.endpoints.lock().unwrap();
+        if let Some(endpoint) = endpoints.get(&bind) {
+            return Ok(endpoint.clone());
+        }
+        let endpoint = quinn::Endpoint::client(SocketAddr::new(bind, 0))?;
+        endpoints.insert(bind, endpoint.clone());
+        Ok(endpoint)
+    }
+}
+
+impl ClientState {
+    fn remove(&self, key: &pool::Key, conn: &Arc<Connection>) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.get(key).is_some_and(|c| Arc::ptr_eq(c, conn)) {
+            connections.remove(key);
+        }
+    }
+}
+
+// The halves of a request stream, which differ between clients and servers.
+#[async_trait::async_trait]
+trait SendHalf: Send + 'static {
+    async fn send(&mut self, data: Bytes) -> Result<(), h3::error::StreamError>;
+    async fn finish(&mut self) -> Result<(), h3::error::StreamError>;
+}
+
+#[async_trait::async_trait]
+trait RecvHalf: Send + 'static {
+    async fn recv(&mut self) -> Result<Option<Bytes>, h3::error::StreamError>;
+}
+
+#[async_trait::async_trait]
+impl SendHalf for ServerStream<h3_quinn::SendStream<Bytes>> {
+    async fn send(&mut self, data: Bytes) -> Result<(), h3::error::StreamError> {
+        self.send_data(data).await
+    }
+
+    async fn finish(&mut self) -> Result<(), h3::error::StreamError> {
+        ServerStream::finish(self).await
+    }
+}
+
+#[async_trait::async_trait]
+impl SendHalf for ClientStream<h3_quinn::SendStream<Bytes>> {
+    async fn send(&mut self, data: Bytes) -> Result<(), h3::error::StreamError> {
+        self.send_data(data).await
+    }
+
+    async fn finish(&mut self) -> Result<(), h3::error::StreamError> {
+        ClientStream::finish(self).await
+    }
+}
+
+#[async_trait::async_trait]
+impl RecvHalf for ServerStream<h3_quinn::RecvStream> {
+    async fn recv(&mut self) -> Result<Option<Bytes>, h3::error::StreamError> {
+        let data = self.recv_data().await?;
+        Ok(data.map(|mut data| data.copy_to_bytes(data.remaining())))
+    }
+}
+
+#[async_trait::async_trait]
+impl RecvHalf for ClientStream<h3_quinn::RecvStream> {
+    async fn recv(&mut self) -> Result<Option<Bytes>, h3::error::StreamError> {
+        let data = self.recv_data().await?;
+        Ok(data.map(|mut data| data.copy_to_bytes(data.remaining())))
+    }
+}
+
+// bridge relays the data of an HTTP/3 request stream to and from the returned stream, so it can be
+// used like the upgraded stream of an HTTP/2 request. Closing either side for writing closes the
+// other, and if either goes away, so does the other. `active` is held until both directions end.
+fn bridge(
+    mut send: impl SendHalf,
+    mut recv: impl RecvHalf,
+    active: Option<mpsc::Sender<()>>,
+) -> DuplexStream {
+    let (tunnel, bridged) = tokio::io::duplex(TUNNEL_BUFFER_SIZE);
+    let (mut reader, mut writer) = tokio::io::split(bridged);
+    let sending = active.clone();
+    tokio::spawn(
+        async move {
+            let _sending = sending;
+            let mut buf = BytesMut::with_capacity(TUNNEL_BUFFER_SIZE);
+            loop {
+                match reader.read_buf(&mut buf).await {
+                    Ok(0) => {
+                        if let Err(e) = send.finish().await {
+                            debug!("failed to finish HTTP/3 stream: {e}");
+                        }
+                        return;
+                    }
+                    Ok(_) => {
+                        if let Err(e) = send.send(buf.split().freeze()).await {
+                            debug!("failed to send on HTTP/3 stream: {e}");
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                }
+            }
+        }
+        .in_current_span(),
+    );
+    tokio::spawn(
+        async move {
+            let _receiving = active;
+            loop {
+                match recv.recv().await {
+                    Ok(Some(data)) => {
+                        if writer.write_all(&data).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => {
+                        let _ = writer.shutdown().await;
+                        return;
+                    }
+                    Err(e) => {
+                        debug!("failed to receive on HTTP/3 stream: {e}");
+                        return;
+                    }
+                }
+            }
+        }
+        .in_current_span(),
+    );
+    tunnel
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("h3=\":15009\"", Some(AltSvc::H3 { port: 15009, max_age: DEFAULT_MAX_AGE }); "h3")]
+    #[test_case("h2=\":443\", h3=\":15009\"; ma=60", Some(AltSvc::H3 { port: 15009, max_age: Duration::from_secs(60) }); "with max age")]
+    #[test_case("h3=\"other:15009\"", None; "other host")]
+    #[test_case("h3-29=\":15009\"", None; "draft version")]
+    #[test_case("h2=\":443\"", None; "no h3")]
+    #[test_case("clear", Some(AltSvc::Clear); "clear")]
+    fn alt_svc_header(value: &str, expected: Option<AltSvc>) {
+        assert_eq!(parse_alt_svc(value), expected);
+    }
+
+    #[tokio::test]
+    async fn alternatives() {
+        let client = Client::new(
+            &crate::config::parse_config().unwrap(),
+            crate::identity::mock::new_secret_manager(Duration::from_secs(10)),
+        );
+        let gateway: SocketAddr = "127.0.0.1:15008".parse().unwrap();
+        let headers = |value: &str| HeaderMap::from_iter([(ALT_SVC, value.parse().unwrap())]);
+        assert_eq!(client.alternative(gateway), None);
+
+        client.learn(gateway, &headers("h3=\":15009\""));
+        assert_eq!(
+            client.alternative(gateway),
+            Some("127.0.0.1:15009".parse().unwrap())
+        );
+
+        // Once broken, advertisements are ignored until the backoff ends
+        client.mark_broken(gateway);
+        client.learn(gateway, &headers("h3=\":15009\""));
+        assert_eq!(client.alternative(gateway), None);
+    }
+}
@@ -15,14 +15,22 @@
 use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder};
 use drain::Watch;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info, warn};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
 
-use crate::proxy::outbound::OutboundConnection;
+use crate::config::Config;
+use crate::identity::Identity;
+use crate::proxy::outbound::{Handshake, OutboundConnection};
+use crate::proxy::udp::{self, Flow};
 use crate::proxy::{util, Error, ProxyInputs, TraceParent};
 use crate::socket;
 
@@ -30,6 +38,83 @@ pub(super) struct Socks5 {
     pi: ProxyInputs,
     listener: TcpListener,
     drain: Watch,
+    auth: Arc<Auth>,
+}
+
+/// Auth determines how SOCKS5 clients may authenticate, and which source workload they connect as.
+struct Auth {
+    allow_unauthenticated: bool,
+    /// credentials maps each username to its credential.
+    credentials: HashMap<String, Credential>,
+}
+
+/// Credential allows a client that authenticates with `password` to connect as a source workload.
+struct Credential {
+    password: String,
+    source: CredentialSource,
+}
+
+enum CredentialSource {
+    /// Connect as the workload with this address.
+    Workload(IpAddr),
+    /// Connect as a workload with this identity, preferably one on our node.
+    Identity(Identity),
+}
+
+/// RawCredential is a credential as written in the credentials file. Exactly one of `workload` and
+/// `identity` must be set.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RawCredential {
+    username: String,
+    password: String,
+    #[serde(default)]
+    workload: Option<IpAddr>,
+    #[serde(default)]
+    identity: Option<String>,
+}
+
+impl Auth {
+    async fn new(cfg: &Config) -> Result<Auth, Error> {
+        let mut credentials = HashMap::new();
+        if let Some(source) = &cfg.socks5_credentials {
+            let data = source
+                .read_to_string()
+                .await
+                .map_err(Error::Socks5Credentials)?;
+            let raw: Vec<RawCredential> =
+                serde_yaml::from_str(&data).map_err(|e| Error::Socks5Credentials(e.into()))?;
+            for c in raw {
+                let source = match (c.workload, c.identity) {
+                    (Some(ip), None) => CredentialSource::Workload(ip),
+                    (None, Some(id)) => CredentialSource::Identity(
+                        id.parse()
+                            .map_err(|e| Error::Socks5Credentials(anyhow::anyhow!("{e}")))?,
+                    ),
+                    _ => {
+                        return Err(Error::Socks5Credentials(anyhow::anyhow!(
+                            "credential {} must set exactly one of workload or identity",
+                            c.username
+                        )))
+                    }
+                };
+                let credential = Credential {
+                    password: c.password,
+                    source,
+                };
+                if credentials.insert(c.username.clone(), credential).is_some() {
+                    return Err(Error::Socks5Credentials(anyhow::anyhow!(
+                        "duplicate username {}",
+                        c.username
+                    )));
+                }
+            }
+        }
+        Ok(Auth {
+            allow_unauthenticated: cfg.socks5_allow_unauthenticated,
+            credentials,
+        })
+    }
 }
 
 impl Socks5 {
@@ -37,6 +122,8 @@ impl Socks5 {
         let listener: TcpListener = TcpListener::bind(pi.cfg.socks5_addr)
             .await
             .map_err(|e| Error::Bind(pi.cfg.socks5_addr, e))?;
+        pi.cfg.socket_options.apply(&listener)?;
+        let auth = Arc::new(Auth::new(&pi.cfg).await?);
 
         info!(
             address=%listener.local_addr().unwrap(),
@@ -48,6 +135,7 @@ impl Socks5 {
             pi,
             listener,
             drain,
+            auth,
         })
     }
 
@@ -67,8 +155,9 @@ impl Socks5 {
                             pi: self.pi.clone(),
                             id: TraceParent::new(),
                         };
+                        let auth = self.auth.clone();
                         tokio::spawn(async move {
-                            if let Err(err) = handle(oc, stream).await {
+                            if let Err(err) = handle(oc, stream, &auth).await {
                                 log::error!("handshake error: {}", err);
                             }
                         });
@@ -94,9 +183,14 @@ impl Socks5 {
 
 // hande will process a SOCKS5 connection. This supports a minimal subset of the protocol,
 // sufficient to integrate with common clients:
-// - only unauthenticated requests
-// - only CONNECT, with IPv4 or IPv6
-async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(), anyhow::Error> {
+// - only unauthenticated or username/password (RFC 1929) requests
+// - only CONNECT and UDP ASSOCIATE, with IPv4, IPv6, or a domain name
+// - no fragmentation of UDP datagrams
+async fn handle(
+    mut oc: OutboundConnection,
+    mut stream: TcpStream,
+    auth: &Auth,
+) -> Result<(), anyhow::Error> {
     // Version(5), Number of auth methods
     let mut version = [0u8; 2];
     stream.read_exact(&mut version).await?;
@@ -115,15 +209,22 @@ async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(),
     let mut methods = vec![0u8; nmethods as usize];
     stream.read_exact(&mut methods).await?;
 
-    // Client must include 'unauthenticated' (0).
-    if !methods.into_iter().any(|x| x == 0) {
-        return Err(anyhow::anyhow!("unsupported auth method"));
-    }
+    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
 
-    // Select 'unauthenticated' (0).
-    stream.write_all(&[0x05, 0x00]).await?;
+    // Prefer 'username/password' (2) if we have credentials, otherwise 'unauthenticated' (0).
+    let source = if !auth.credentials.is_empty() && methods.contains(&0x02) {
+        stream.write_all(&[0x05, 0x02]).await?;
+        authenticate(&oc, &mut stream, auth).await?
+    } else if auth.allow_unauthenticated && methods.contains(&0x00) {
+        stream.write_all(&[0x05, 0x00]).await?;
+        remote_addr.ip()
+    } else {
+        // No acceptable methods
+        stream.write_all(&[0x05, 0xFF]).await?;
+        return Err(anyhow::anyhow!("unsupported auth method"));
+    };
 
-    // Version(5), Command - only support CONNECT (1)
+    // Version(5), Command - only support CONNECT (1) and UDP ASSOCIATE (3)
     let mut version_command = [0u8; 2];
     stream.read_exact(&mut version_command).await?;
     let version = version_command[0];
@@ -132,7 +233,8 @@ async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(),
         return Err(anyhow::anyhow!("unsupported version"));
     }
 
-    if version_command[1] != 1 {
+    let command = version_command[1];
+    if command != CONNECT && command != UDP_ASSOCIATE {
         return Err(anyhow::anyhow!("unsupported command"));
     }
 
@@ -161,9 +263,14 @@ async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(),
             stream.read_exact(&mut domain_length).await?;
             let mut domain = vec![0u8; domain_length[0] as usize];
             stream.read_exact(&mut domain).await?;
-            // TODO: DNS lookup, if we want to integrate with HTTP-based apps without
-            // a DNS server.
-            return Err(anyhow::anyhow!("unsupported host"));
+            let domain = String::from_utf8(domain)?;
+            ip = match util::resolve(&oc.pi, &domain).await {
+                Some(ip) => ip,
+                None => {
+                    stream.write_all(&HOST_UNREACHABLE).await?;
+                    return Err(anyhow::anyhow!("unknown host {domain}"));
+                }
+            };
         }
         _ => {
             return Err(anyhow::anyhow!("unsupported host"));
@@ -176,7 +283,10 @@ async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(),
 
     let host = SocketAddr::new(ip, port);
 
-    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+    if command == UDP_ASSOCIATE {
+        // The address is where the client will send datagrams from, which is often left unspecified
+        return associate(oc, stream, remote_addr, source, host).await;
+    }
 
     // Send dummy values - the client generally ignores it.
     let buf = [
@@ -187,13 +297,251 @@ async fn handle(mut oc: OutboundConnection, mut stream: TcpStream) -> Result<(),
     ];
     stream.write_all(&buf).await?;
 
-    info!("accepted connection from {remote_addr} to {host}");
+    info!("accepted connection from {remote_addr} as {source} to {host}");
     tokio::spawn(async move {
-        let res = oc.proxy_to(stream, remote_addr.ip(), host, true).await;
+        let res = oc
+            .proxy_to(stream, source, host, true, Handshake::None)
+            .await;
         match res {
             Ok(_) => {}
+            Err(ref e) if e.is_deliberate_close() => info!("outbound proxy closed: {}", e),
             Err(ref e) => warn!("outbound proxy failed: {}", e),
         };
     });
     Ok(())
 }
+
+// authenticate runs the username/password sub-negotiation (RFC 1929), and returns the address of
+// the workload the client may connect as.
+async fn authenticate(
+    oc: &OutboundConnection,
+    stream: &mut TcpStream,
+    auth: &Auth,
+) -> Result<IpAddr, anyhow::Error> {
+    // Version(1), Username length, Username, Password length, Password
+    let mut version = [0u8];
+    stream.read_exact(&mut version).await?;
+    if version[0] != 0x01 {
+        return Err(anyhow::anyhow!("unsupported auth version"));
+    }
+    let mut username_length = [0u8];
+    stream.read_exact(&mut username_length).await?;
+    let mut username = vec![0u8; username_length[0] as usize];
+    stream.read_exact(&mut username).await?;
+    let mut password_length = [0u8];
+    stream.read_exact(&mut password_length).await?;
+    let mut password = vec![0u8; password_length[0] as usize];
+    stream.read_exact(&mut password).await?;
+
+    let username = String::from_utf8_lossy(&username);
+    let credential = auth
+        .credentials
+        .get(username.as_ref())
+        // Compare in constant time, so the password cannot be guessed from response times
+        .filter(|c| {
+            c.password.len() == password.len()
+                && boring::memcmp::eq(c.password.as_bytes(), &password)
+        });
+    let source = match credential.map(|c| &c.source) {
+        Some(CredentialSource::Workload(ip)) => Some(*ip),
+        Some(CredentialSource::Identity(identity)) => oc
+            .pi
+            .workloads
+            .find_workload_by_identity(identity)
+            .and_then(|wl| wl.workload_ips.first().copied()),
+        None => None,
+    };
+    match source {
+        Some(ip) => {
+            // Version(1), Success(0)
+            stream.write_all(&[0x01, 0x00]).await?;
+            Ok(ip)
+        }
+        None => {
+            // Version(1), Failure(1)
+            stream.write_all(&[0x01, 0x01]).await?;
+            match credential {
+                Some(_) => Err(anyhow::anyhow!("no workload found for user {username}")),
+                None => Err(anyhow::anyhow!("authentication failed for user {username}")),
+            }
+        }
+    }
+}
+
+const CONNECT: u8 = 0x01;
+const UDP_ASSOCIATE: u8 = 0x03;
+
+// associate sets up a UDP association for the client at `remote_addr`, connecting as `source`.
+// Datagrams the client sends to the relay socket are proxied to their destination, and replies are
+// sent back through the relay. The association lasts as long as the `control` connection, unless
+// it is idle for longer than the UDP idle timeout.
+async fn associate(
+    oc: OutboundConnection,
+    mut control: TcpStream,
+    remote_addr: SocketAddr,
+    source: IpAddr,
+    client: SocketAddr,
+) -> Result<(), anyhow::Error> {
+    let relay = UdpSocket::bind(SocketAddr::new(control.local_addr()?.ip(), 0)).await?;
+    let bound = relay.local_addr()?;
+
+    // Version(5), Success(0), RSV, then the address of the relay
+    let mut buf = vec![0x05u8, 0x00, 0x00];
+    write_address(&mut buf, bound);
+    control.write_all(&buf).await?;
+
+    info!("accepted udp association from {remote_addr} as {source} on {bound}");
+    tokio::spawn(async move {
+        // Only the client may use the relay. If it did not say which port it sends from, we take
+        // the port of its first datagram.
+        let client_port = Some(client.port()).filter(|p| *p != 0);
+        let res = relay_datagrams(
+            &oc.pi,
+            control,
+            relay,
+            source,
+            remote_addr.ip(),
+            client_port,
+        )
+        .await;
+        match res {
+            Ok(_) => {}
+            Err(ref e) => warn!("udp association failed: {}", e),
+        };
+    });
+    Ok(())
+}
+
+// relay_datagrams proxies datagrams between the client and the destinations it sends to, until the
+// association ends. Each destination gets its own flow.
+async fn relay_datagrams(
+    pi: &ProxyInputs,
+    mut control: TcpStream,
+    relay: UdpSocket,
+    source: IpAddr,
+    client_ip: IpAddr,
+    mut client_port: Option<u16>,
+) -> Result<(), Error> {
+    let idle_timeout = pi.cfg.udp_idle_timeout;
+    let (replies_tx, mut replies) = mpsc::channel(16);
+    let mut flows: HashMap<SocketAddr, Flow> = HashMap::new();
+    let mut control_buf = [0u8; 1];
+    let mut buf = vec![0u8; udp::MAX_DATAGRAM_SIZE];
+    let mut deadline = Instant::now() + idle_timeout;
+    let res = loop {
+        tokio::select! {
+            // The client must not send anything else on the control connection; it is only closed
+            res = control.read(&mut control_buf) => match res {
+                Ok(0) | Err(_) => break Ok(()),
+                Ok(_) => {}
+            },
+            res = relay.recv_from(&mut buf) => {
+                let (n, from) = match res {
+                    Ok(res) => res,
+                    Err(e) => break Err(e.into()),
+                };
+                let from = socket::to_canonical(from);
+                if from.ip() != client_ip || client_port.is_some_and(|p| p != from.port()) {
+                    debug!("dropping datagram from unexpected address {from}");
+                    continue;
+                }
+                client_port = Some(from.port());
+                let Some((target, payload)) = parse_datagram(pi, &buf[..n]).await else {
+                    debug!("dropping malformed datagram from {from}");
+                    continue;
+                };
+                deadline = Instant::now() + idle_timeout;
+                // A flow whose tunnel went away is replaced with a new one
+                if flows.get(&target).is_some_and(Flow::is_closed) {
+                    if let Some(flow) = flows.remove(&target) {
+                        flow.close(None);
+                    }
+                }
+                let flow = match flows.entry(target) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => match Flow::open(pi, source, target, true, replies_tx.clone()).await {
+                        Ok(flow) => e.insert(flow),
+                        Err(err) => {
+                            warn!("dropping datagram from {source} to {target}: {err}");
+                            continue;
+                        }
+                    },
+                };
+                if let Err(e) = flow.send(payload).await {
+                    debug!("failed to send datagram to {target}: {e}");
+                }
+            }
+            Some(datagram) = replies.recv() => {
+                let (Some(port), Some(flow)) = (client_port, flows.get_mut(&datagram.target)) else {
+                    continue;
+                };
+                deadline = Instant::now() + idle_timeout;
+                flow.record_received(datagram.payload.len());
+                // RSV, FRAG(0), then the address the client sent to, so it can match the reply
+                let mut packet = vec![0x00u8, 0x00, 0x00];
+                write_address(&mut packet, datagram.target);
+                packet.extend_from_slice(&datagram.payload);
+                if let Err(e) = relay.send_to(&packet, SocketAddr::new(client_ip, port)).await {
+                    break Err(e.into());
+                }
+            }
+            _ = tokio::time::sleep_until(deadline) => break Err(Error::IdleTimeout(idle_timeout)),
+        }
+    };
+    for (_, flow) in flows.drain() {
+        flow.close(res.as_ref().err());
+    }
+    res
+}
+
+// parse_datagram parses the header of a datagram from the client, returning its destination and
+// payload.
+async fn parse_datagram<'a>(pi: &ProxyInputs, buf: &'a [u8]) -> Option<(SocketAddr, &'a [u8])> {
+    // RSV(2), FRAG, ATYP
+    let header = buf.get(..4)?;
+    if header[2] != 0 {
+        // Fragments are not supported, and must be dropped
+        return None;
+    }
+    let (ip, rest): (IpAddr, _) = match header[3] {
+        0x01 => {
+            let hostb: [u8; 4] = buf.get(4..8)?.try_into().ok()?;
+            (hostb.into(), &buf[8..])
+        }
+        0x04 => {
+            let hostb: [u8; 16] = buf.get(4..20)?.try_into().ok()?;
+            (hostb.into(), &buf[20..])
+        }
+        0x03 => {
+            let domain_length = *buf.get(4)? as usize;
+            let domain = std::str::from_utf8(buf.get(5..5 + domain_length)?).ok()?;
+            (util::resolve(pi, domain).await?, &buf[5 + domain_length..])
+        }
+        _ => return None,
+    };
+    let port = BigEndian::read_u16(rest.get(..2)?);
+    Some((SocketAddr::new(ip, port), &rest[2..]))
+}
+
+// write_address appends ATYP, the address, and the port of `addr`.
+fn write_address(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(0x01);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(0x04);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+// Reply sent when a domain cannot be resolved.
+const HOST_UNREACHABLE: [u8; 10] = [
+    0x05u8, // version
+    0x04, 0x00, // host unreachable, rsv
+    0x01, 0x00, 0x00, 0x00, 0x00, // IPv4
+    0x00, 0x00, // port
+];
@@ -0,0 +1,502 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::upgrade::Upgraded;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, trace};
+
+use crate::metrics::traffic::{self, Reporter};
+use crate::metrics::{IncrementRecorder, Metrics, Recorder};
+use crate::proxy::outbound::{OutboundConnection, RequestType};
+use crate::proxy::{
+    pool, ConnectionInfo, Error, ProxyInputs, TraceParent, TrackedConnection, Tunnel,
+};
+use crate::workload::Protocol;
+use crate::{rbac, socket};
+
+/// The largest payload of a UDP datagram.
+pub(super) const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// The protocol of the extended CONNECT request that opens a UDP tunnel over HBONE.
+pub(super) const CONNECT_UDP_PROTOCOL: &str = "connect-udp";
+/// Signals that the tunnel carries capsules, which both ends of a UDP tunnel must send.
+pub(super) const CAPSULE_PROTOCOL_HEADER: &str = "capsule-protocol";
+
+// The capsule type of an HTTP datagram, from RFC 9297.
+const DATAGRAM_CAPSULE: u64 = 0x00;
+// The context of datagrams carrying a UDP payload, from RFC 9298.
+const UDP_PAYLOAD_CONTEXT: u64 = 0x00;
+// The largest capsule we accept: a full datagram, plus its context ID.
+const MAX_CAPSULE_SIZE: u64 = MAX_DATAGRAM_SIZE as u64 + 8;
+
+/// Datagram is a payload received from the upstream of the flow to `target`.
+pub(super) struct Datagram {
+    pub target: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+// Upstream is where a flow sends its datagrams.
+enum Upstream {
+    // A socket connected directly to the destination.
+    Direct(Arc<UdpSocket>),
+    // An HBONE tunnel to the destination's node. The pooled stream holds our slot on the HBONE
+    // connection until the flow is closed.
+    Hbone {
+        tunnel: WriteHalf<Upgraded>,
+        _pooled: pool::PooledStream,
+    },
+}
+
+/// Flow relays datagrams from a source workload to a single destination. Destinations that support
+/// HBONE are reached through a UDP tunnel, which their node decapsulates and enforces policy on.
+/// Other destinations are sent datagrams directly from a socket dedicated to the flow, after
+/// checking the destination's authorization policies. Replies are forwarded to the `replies`
+/// channel given when the flow was opened.
+pub(super) struct Flow {
+    upstream: Upstream,
+    reader: JoinHandle<()>,
+    metrics: Arc<Metrics>,
+    connection_metrics: traffic::ConnectionOpen,
+    tracked: TrackedConnection,
+    sent: u64,
+    received: u64,
+}
+
+impl Flow {
+    /// open starts a flow from the workload at `source` to `target`, which may be a workload or a
+    /// service VIP. Destinations behind a waypoint or on another network cannot be reached, as
+    /// neither supports UDP. Unless `block_passthrough` is set, destinations outside the mesh are
+    /// sent datagrams directly.
+    pub(super) async fn open(
+        pi: &ProxyInputs,
+        source: IpAddr,
+        target: SocketAddr,
+        block_passthrough: bool,
+        replies: mpsc::Sender<Datagram>,
+    ) -> Result<Flow, Error> {
+        let tracked = pi.connections.track();
+        let oc = OutboundConnection {
+            pi: pi.clone(),
+            id: TraceParent::new(),
+        };
+        let req = oc.build_request(source, target, &[]).await?;
+        if block_passthrough && req.destination_workload.is_none() {
+            return Err(Error::UnknownDestination(target.ip()));
+        }
+        let direct = matches!(
+            req.request_type,
+            RequestType::Direct | RequestType::DirectLocal
+        );
+        let passthrough = req.request_type == RequestType::Passthrough;
+        let (upstream, reader, security) = match req.protocol {
+            Protocol::HBONE if direct => {
+                let (upgraded, pooled) = oc.connect_hbone_udp(&req, source).await?;
+                let (tunnel, writer) = tokio::io::split(upgraded);
+                let reader = tokio::spawn(read_capsules(tunnel, target, replies));
+                (
+                    Upstream::Hbone {
+                        tunnel: writer,
+                        _pooled: pooled,
+                    },
+                    reader,
+                    traffic::SecurityPolicy::mutual_tls,
+                )
+            }
+            Protocol::TCP if direct || passthrough => {
+                if !passthrough {
+                    let conn = rbac::Connection {
+                        src_identity: Some(req.source.identity()),
+                        src_ip: source,
+                        dst: req.destination,
+                    };
+                    if !pi.workloads.assert_rbac(&conn).await {
+                        info!(%conn, "RBAC rejected");
+                        return Err(Error::RbacRejected(conn));
+                    }
+                    tracked.authorized(conn);
+                }
+                let socket = Arc::new(connect(None, req.destination).await?);
+                let reader = tokio::spawn(read_replies(socket.clone(), target, replies));
+                (
+                    Upstream::Direct(socket),
+                    reader,
+                    traffic::SecurityPolicy::unknown,
+                )
+            }
+            // HBONE destinations are only reachable through a tunnel to their node; datagrams for
+            // them must never fall back to plaintext
+            _ => return Err(Error::UdpUnreachable(target)),
+        };
+        debug!(%source, %target, endpoint=%req.destination, "opened udp flow");
+
+        let connection_metrics = traffic::ConnectionOpen {
+            reporter: Reporter::source,
+            source: Some(req.source),
+            derived_source: None,
+            destination: req.destination_workload,
+            destination_service: pi.workloads.find_service(&target),
+            request_protocol: traffic::RequestProtocol::udp,
+            connection_security_policy: security,
+        };
+        pi.metrics.increment(&connection_metrics);
+        tracked.describe(ConnectionInfo {
+            open: connection_metrics.clone(),
+            request_type: Some(req.request_type),
+            protocol: req.protocol,
+            gateway: Some(req.gateway),
+        });
+
+        Ok(Flow {
+            upstream,
+            reader,
+            metrics: pi.metrics.clone(),
+            connection_metrics,
+            tracked,
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    /// send relays a datagram from the source to the destination.
+    pub(super) async fn send(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if let Some(policy) = self.tracked.revocation() {
+            return Err(Error::AuthorizationRevoked(policy));
+        }
+        match &mut self.upstream {
+            Upstream::Direct(socket) => {
+                socket.send(payload).await?;
+            }
+            Upstream::Hbone { tunnel, .. } => {
+                tunnel.write_all(&encode_datagram(payload)).await?;
+                tunnel.flush().await?;
+            }
+        }
+        self.sent += payload.len() as u64;
+        self.tracked.transferred((self.sent, self.received));
+        Ok(())
+    }
+
+    /// record_received accounts for a datagram of `len` bytes relayed back to the source.
+    pub(super) fn record_received(&mut self, len: usize) {
+        self.received += len as u64;
+        self.tracked.transferred((self.sent, self.received));
+    }
+
+    /// is_closed returns whether the upstream has gone away, such as when the tunnel was reset, or
+    /// the flow was denied by a policy change. The flow should be reopened to send more datagrams.
+    pub(super) fn is_closed(&self) -> bool {
+        self.reader.is_finished() || self.tracked.revocation().is_some()
+    }
+
+    /// close stops relaying replies, and records the flow as closed for `reason`.
+    pub(super) fn close(self, reason: Option<&Error>) {
+        self.reader.abort();
+        trace!(sent = self.sent, recv = self.received, "udp flow complete");
+        self.metrics.record(
+            &traffic::BytesTransferred::from(&self.connection_metrics),
+            (self.sent, self.received),
+        );
+        let mut close = traffic::ConnectionClose::from(&self.connection_metrics);
+        let revoked = self.tracked.revocation().map(Error::AuthorizationRevoked);
+        if let Some(e) = reason.or(revoked.as_ref()) {
+            close.set_response_flags(e.response_flags());
+        }
+        self.metrics.increment(&close);
+    }
+}
+
+/// connect opens a UDP socket to `dst`, sending from `orig_src` when it is set, like
+/// freebind_connect does for TCP.
+pub(super) async fn connect(orig_src: Option<IpAddr>, dst: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = match orig_src {
+        Some(src) => socket::bind_transparent_udp(SocketAddr::new(src, 0))?,
+        None => {
+            let local: IpAddr = if dst.is_ipv4() {
+                Ipv4Addr::UNSPECIFIED.into()
+            } else {
+                Ipv6Addr::UNSPECIFIED.into()
+            };
+            UdpSocket::bind(SocketAddr::new(local, 0)).await?
+        }
+    };
+    socket.connect(dst).await?;
+    Ok(socket)
+}
+
+/// serve_tunnel relays datagrams between an inbound UDP tunnel and `socket`, which is connected to
+/// the destination workload, until either end goes away or the tunnel is idle for `idle_timeout`.
+pub(super) async fn serve_tunnel(
+    tunnel: impl Tunnel + 'static,
+    socket: UdpSocket,
+    idle_timeout: Duration,
+    metrics: Arc<Metrics>,
+    connection_metrics: traffic::ConnectionOpen,
+    tracked: TrackedConnection,
+) {
+    let mut connection_close =
+        metrics.increment_defer::<_, traffic::ConnectionClose>(&connection_metrics);
+    let target = socket.peer_addr().expect("socket must be connected");
+    let (tunnel, mut writer) = tokio::io::split(tunnel);
+    let (datagrams_tx, mut datagrams) = mpsc::channel(16);
+    let reader = tokio::spawn(read_capsules(tunnel, target, datagrams_tx));
+
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    let (mut sent, mut received) = (0u64, 0u64);
+    let res = loop {
+        tokio::select! {
+            datagram = datagrams.recv() => {
+                // The tunnel was closed
+                let Some(datagram) = datagram else { break Ok(()) };
+                match socket.send(&datagram.payload).await {
+                    Ok(n) => sent += n as u64,
+                    Err(e) => debug!(%target, "udp send failed: {e}"),
+                }
+                tracked.transferred((sent, received));
+            }
+            reply = socket.recv(&mut buf) => {
+                let n = match reply {
+                    Ok(n) => n,
+                    // The destination may come back, as in read_replies
+                    Err(e) => {
+                        debug!(%target, "udp receive failed: {e}");
+                        continue;
+                    }
+                };
+                if let Err(e) = writer.write_all(&encode_datagram(&buf[..n])).await {
+                    break Err(Error::Io(e));
+                }
+                if let Err(e) = writer.flush().await {
+                    break Err(Error::Io(e));
+                }
+                received += n as u64;
+                tracked.transferred((sent, received));
+            }
+            _ = tokio::time::sleep(idle_timeout) => break Err(Error::IdleTimeout(idle_timeout)),
+            policy = tracked.revoked() => break Err(Error::AuthorizationRevoked(policy)),
+        }
+    };
+    reader.abort();
+    trace!(sent, recv = received, "udp tunnel complete");
+    metrics.record(
+        &traffic::BytesTransferred::from(&connection_metrics),
+        (sent, received),
+    );
+    if let Err(e) = res {
+        connection_close.update(|c| c.set_response_flags(e.response_flags()));
+    }
+}
+
+async fn read_replies(socket: Arc<UdpSocket>, target: SocketAddr, replies: mpsc::Sender<Datagram>) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let n = match socket.recv(&mut buf).await {
+            Ok(n) => n,
+            // Errors such as ICMP port unreachable are reported on the next receive, but do not
+            // end the flow; the destination may come back.
+            Err(e) => {
+                debug!(%target, "udp receive failed: {e}");
+                continue;
+            }
+        };
+        let datagram = Datagram {
+            target,
+            payload: buf[..n].to_vec(),
+        };
+        if replies.send(datagram).await.is_err() {
+            return;
+        }
+    }
+}
+
+// read_capsules forwards the datagrams received on a UDP tunnel until it is closed.
+async fn read_capsules<T: AsyncRead + Send>(
+    mut tunnel: ReadHalf<T>,
+    target: SocketAddr,
+    datagrams: mpsc::Sender<Datagram>,
+) {
+    loop {
+        let payload = match read_datagram(&mut tunnel).await {
+            Ok(Some(payload)) => payload,
+            Ok(None) => return,
+            Err(e) => {
+                debug!(%target, "udp tunnel failed: {e}");
+                return;
+            }
+        };
+        if datagrams.send(Datagram { target, payload }).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// connect_udp_uri returns the URI to request a UDP tunnel to `target`, following the default
+/// template of RFC 9298. The authority is the target too, as HBONE requests are sent to the
+/// destination's node rather than a proxy.
+pub(super) fn connect_udp_uri(target: SocketAddr) -> String {
+    // Colons are reserved in paths, so are percent-encoded in IPv6 addresses
+    let host = target.ip().to_string().replace(':', "%3A");
+    format!(
+        "https://{target}/.well-known/masque/udp/{host}/{}/",
+        target.port()
+    )
+}
+
+/// parse_connect_udp_path returns the target of a UDP tunnel request with `path`, following the
+/// template used by connect_udp_uri. Only IP addresses are accepted as the target host.
+pub(super) fn parse_connect_udp_path(path: &str) -> Option<SocketAddr> {
+    let target = path.strip_prefix("/.well-known/masque/udp/")?;
+    let target = target.strip_suffix('/').unwrap_or(target);
+    let (host, port) = target.split_once('/')?;
+    let host = host.replace("%3A", ":").replace("%3a", ":");
+    Some(SocketAddr::new(host.parse().ok()?, port.parse().ok()?))
+}
+
+// encode_datagram encapsulates a UDP payload in a datagram capsule.
+fn encode_datagram(payload: &[u8]) -> Vec<u8> {
+    let mut capsule = Vec::with_capacity(payload.len() + 10);
+    encode_varint(DATAGRAM_CAPSULE, &mut capsule);
+    // The length covers the context ID too, which is always a single byte
+    encode_varint(payload.len() as u64 + 1, &mut capsule);
+    encode_varint(UDP_PAYLOAD_CONTEXT, &mut capsule);
+    capsule.extend_from_slice(payload);
+    capsule
+}
+
+// read_datagram reads capsules until one carries a UDP payload, which it returns. Other capsules
+// are skipped, as RFC 9297 requires. Returns None if the stream ends between capsules.
+async fn read_datagram<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let Some(capsule_type) = read_varint(r).await? else {
+            return Ok(None);
+        };
+        let Some(len) = read_varint(r).await? else {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        };
+        if len > MAX_CAPSULE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("capsule of {len} bytes is too large"),
+            ));
+        }
+        let mut value = vec![0u8; len as usize];
+        r.read_exact(&mut value).await?;
+        if capsule_type != DATAGRAM_CAPSULE {
+            trace!(capsule_type, "skipping unknown capsule");
+            continue;
+        }
+        let mut value = value.as_slice();
+        match read_varint(&mut value).await? {
+            Some(UDP_PAYLOAD_CONTEXT) => return Ok(Some(value.to_vec())),
+            // We never register other contexts, so the peer cannot use them
+            context => trace!(?context, "skipping datagram with unknown context"),
+        }
+    }
+}
+
+// encode_varint appends `v` as a variable-length integer, as defined in RFC 9000.
+fn encode_varint(v: u64, out: &mut Vec<u8>) {
+    match v {
+        0..=0x3f => out.push(v as u8),
+        0x40..=0x3fff => out.extend_from_slice(&(v as u16 | 0x4000).to_be_bytes()),
+        0x4000..=0x3fff_ffff => out.extend_from_slice(&(v as u32 | 0x8000_0000).to_be_bytes()),
+        _ => out.extend_from_slice(&(v | 0xc000_0000_0000_0000).to_be_bytes()),
+    }
+}
+
+// read_varint reads a variable-length integer, as defined in RFC 9000. Returns None if the stream
+// ends before it.
+async fn read_varint<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Option<u64>> {
+    let mut first = [0u8];
+    if r.read(&mut first).await? == 0 {
+        return Ok(None);
+    }
+    // The two most significant bits are the log2 of the length in bytes
+    let len = 1 << (first[0] >> 6);
+    let mut bytes = [0u8; 8];
+    bytes[8 - len] = first[0] & 0x3f;
+    r.read_exact(&mut bytes[9 - len..]).await?;
+    Ok(Some(u64::from_be_bytes(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    // Examples from RFC 9000, appendix A.1
+    #[test_case(37, &[0x25]; "one byte")]
+    #[test_case(15293, &[0x7b, 0xbd]; "two bytes")]
+    #[test_case(494878333, &[0x9d, 0x7f, 0x3e, 0x7d]; "four bytes")]
+    #[test_case(151288809941952652, &[0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c]; "eight bytes")]
+    #[tokio::test]
+    async fn varint(v: u64, encoded: &[u8]) {
+        let mut out = Vec::new();
+        encode_varint(v, &mut out);
+        assert_eq!(out, encoded);
+        assert_eq!(read_varint(&mut &encoded[..]).await.unwrap(), Some(v));
+    }
+
+    #[tokio::test]
+    async fn datagram_capsules() {
+        let mut stream = encode_datagram(b"hello");
+        // An unknown capsule, which is skipped
+        stream.extend_from_slice(&[0x17, 0x02, 0xff, 0xff]);
+        stream.extend(encode_datagram(b""));
+        stream.extend(encode_datagram(&[0u8; 100]));
+
+        let mut r = stream.as_slice();
+        assert_eq!(
+            read_datagram(&mut r).await.unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(read_datagram(&mut r).await.unwrap(), Some(Vec::new()));
+        assert_eq!(read_datagram(&mut r).await.unwrap(), Some(vec![0u8; 100]));
+        assert_eq!(read_datagram(&mut r).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn truncated_capsule() {
+        let stream = encode_datagram(b"hello");
+        let mut r = &stream[..stream.len() - 1];
+        assert!(read_datagram(&mut r).await.is_err());
+    }
+
+    #[test_case("10.0.0.1:53", "https://10.0.0.1:53/.well-known/masque/udp/10.0.0.1/53/"; "ipv4")]
+    #[test_case("[ff06::c3]:443", "https://[ff06::c3]:443/.well-known/masque/udp/ff06%3A%3Ac3/443/"; "ipv6")]
+    fn connect_udp_uri(target: &str, expected: &str) {
+        let target: SocketAddr = target.parse().unwrap();
+        let uri = super::connect_udp_uri(target);
+        assert_eq!(uri, expected);
+        let uri: hyper::Uri = uri.parse().unwrap();
+        assert_eq!(super::parse_connect_udp_path(uri.path()), Some(target));
+    }
+
+    #[test_case("/.well-known/masque/udp/10.0.0.1/53", Some("10.0.0.1:53"); "no trailing slash")]
+    #[test_case("/.well-known/masque/udp/10.0.0.1/", None; "no port")]
+    #[test_case("/.well-known/masque/udp/example.com/53/", None; "hostname")]
+    #[test_case("/.well-known/masque/ip/10.0.0.1/53/", None; "not udp")]
+    fn parse_connect_udp_path(path: &str, expected: Option<&str>) {
+        let expected = expected.map(|t| t.parse().unwrap());
+        assert_eq!(super::parse_connect_udp_path(path), expected);
+    }
+}
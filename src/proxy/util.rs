@@ -13,6 +13,11 @@
 // limitations under the License.
 
 use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+
+use tracing::{debug, warn};
+
+use crate::proxy::ProxyInputs;
 
 pub fn is_runtime_shutdown(e: &Error) -> bool {
     if e.kind() == ErrorKind::Other
@@ -22,3 +27,27 @@ pub fn is_runtime_shutdown(e: &Error) -> bool {
     }
     false
 }
+
+/// resolve finds the address of `domain`, as requested by an explicit proxy client. Services and
+/// workloads in the mesh are resolved first, so they can be reached without cluster DNS. Other
+/// domains are resolved with system DNS, if enabled.
+pub async fn resolve(pi: &ProxyInputs, domain: &str) -> Option<IpAddr> {
+    // Clients may send IP literals as domains, too
+    if let Ok(ip) = domain.parse() {
+        return Some(ip);
+    }
+    if let Some(ip) = pi.workloads.resolve_hostname(domain) {
+        debug!("resolved {domain} to {ip} from the mesh");
+        return Some(ip);
+    }
+    if !pi.cfg.socks5_dns_fallback {
+        return None;
+    }
+    match tokio::net::lookup_host((domain, 0)).await {
+        Ok(mut addrs) => addrs.next().map(|addr| addr.ip()),
+        Err(e) => {
+            warn!("failed to resolve {domain}: {e}");
+            None
+        }
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::config;
+use crate::identity::Identity;
+use crate::workload::Workload;
+
+/// RateLimiter limits the rate at which each source workload can open connections through
+/// ztunnel, using a token bucket per workload. Limits are enforced locally, so a workload sending
+/// through multiple ztunnels is limited by each independently.
+#[derive(Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    config: RateLimitConfig,
+    buckets: HashMap<String, Bucket>,
+}
+
+/// RateLimitConfig determines the limit for each source. The most specific limit applies: by
+/// identity, then by namespace, then the default.
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// The limit for sources without a more specific one. If unset, they are not limited.
+    #[serde(default)]
+    pub default: Option<Limit>,
+    /// Limits keyed by the namespace of the source.
+    #[serde(default)]
+    pub namespaces: HashMap<String, Limit>,
+    /// Limits keyed by the SPIFFE identity of the source.
+    #[serde(default)]
+    pub identities: HashMap<String, Limit>,
+}
+
+impl FromStr for RateLimitConfig {
+    type Err = serde_yaml::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(s)
+    }
+}
+
+/// Limit is a token bucket: sources can open `burst` connections at once, refilled at
+/// `connections_per_second`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Limit {
+    pub connections_per_second: u32,
+    pub burst: u32,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: Limit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(limit: Limit, now: Instant) -> Bucket {
+        Bucket {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.connections_per_second as f64)
+            .min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.limit.burst as f64
+    }
+}
+
+/// Rejected is returned when a source has exceeded its limit.
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+#[error("connection rate limit exceeded for {0}")]
+pub struct Rejected(pub String);
+
+impl RateLimiter {
+    pub fn new(cfg: &config::Config) -> RateLimiter {
+        RateLimiter {
+            state: Arc::new(Mutex::new(State {
+                config: cfg.connection_rate_limits.clone(),
+                buckets: Default::default(),
+            })),
+        }
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.state.lock().unwrap().config.clone()
+    }
+
+    /// set_config replaces the limits. Sources start with a full bucket under their new limit.
+    pub fn set_config(&self, config: RateLimitConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        state.buckets.clear();
+    }
+
+    /// check takes a token for a new connection from the source with `identity`, which runs as
+    /// `workload` if it is known. It fails if the source has no tokens left.
+    pub fn check(&self, identity: &Identity, workload: Option<&Workload>) -> Result<(), Rejected> {
+        let mut state = self.state.lock().unwrap();
+        let Some(limit) = state.config.limit_for(identity) else {
+            return Ok(());
+        };
+        // Each workload has its own bucket, even if it shares an identity with others
+        let key = match workload {
+            Some(w) => format!("{}/{}", w.namespace, w.name),
+            None => identity.to_string(),
+        };
+        let now = Instant::now();
+        let bucket = state
+            .buckets
+            .entry(key)
+            .or_insert_with_key(|_| Bucket::new(limit, now));
+        bucket.refill(now);
+        if bucket.tokens < 1.0 {
+            return Err(Rejected(identity.to_string()));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// run periodically forgets sources which have refilled their bucket, as they behave the same
+    /// as sources that have not been seen before.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            state.buckets.retain(|_, b| {
+                b.refill(now);
+                !b.is_full()
+            });
+            debug!(sources = state.buckets.len(), "pruned rate limit buckets");
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn limit_for(&self, identity: &Identity) -> Option<Limit> {
+        let Identity::Spiffe { namespace, .. } = identity;
+        self.identities
+            .get(&identity.to_string())
+            .or_else(|| self.namespaces.get(namespace))
+            .or(self.default.as_ref())
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(ns: &str, sa: &str) -> Identity {
+        Identity::Spiffe {
+            trust_domain: "cluster.local".to_string(),
+            namespace: ns.to_string(),
+            service_account: sa.to_string(),
+        }
+    }
+
+    fn limiter(config: RateLimitConfig) -> RateLimiter {
+        let rl = RateLimiter {
+            state: Arc::new(Mutex::new(State {
+                config: Default::default(),
+                buckets: Default::default(),
+            })),
+        };
+        rl.set_config(config);
+        rl
+    }
+
+    fn limit(burst: u32) -> Limit {
+        Limit {
+            connections_per_second: 0,
+            burst,
+        }
+    }
+
+    #[test]
+    fn most_specific_limit() {
+        let config: RateLimitConfig = r#"
+default: {connectionsPerSecond: 0, burst: 1}
+namespaces:
+  ns1: {connectionsPerSecond: 0, burst: 2}
+identities:
+  spiffe://cluster.local/ns/ns1/sa/special: {connectionsPerSecond: 0, burst: 3}
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(config.limit_for(&identity("other", "sa")), Some(limit(1)));
+        assert_eq!(config.limit_for(&identity("ns1", "sa")), Some(limit(2)));
+        assert_eq!(
+            config.limit_for(&identity("ns1", "special")),
+            Some(limit(3))
+        );
+        assert_eq!(
+            RateLimitConfig::default().limit_for(&identity("ns1", "sa")),
+            None
+        );
+    }
+
+    #[test]
+    fn token_bucket() {
+        let rl = limiter(RateLimitConfig {
+            default: Some(limit(2)),
+            ..Default::default()
+        });
+        let id = identity("ns", "sa");
+        assert!(rl.check(&id, None).is_ok());
+        assert!(rl.check(&id, None).is_ok());
+        assert_eq!(
+            rl.check(&id, None),
+            Err(Rejected("spiffe://cluster.local/ns/ns/sa/sa".to_string()))
+        );
+        // Other sources have their own bucket
+        assert!(rl.check(&identity("ns", "other"), None).is_ok());
+        // Updating the config resets the buckets
+        rl.set_config(rl.config());
+        assert!(rl.check(&id, None).is_ok());
+    }
+
+    #[test]
+    fn refill() {
+        let mut bucket = Bucket::new(
+            Limit {
+                connections_per_second: 10,
+                burst: 5,
+            },
+            Instant::now(),
+        );
+        bucket.tokens = 0.0;
+        let later = bucket.last_refill + Duration::from_millis(200);
+        bucket.refill(later);
+        assert!((bucket.tokens - 2.0).abs() < f64::EPSILON);
+        // Never more than the burst
+        bucket.refill(later + Duration::from_secs(10));
+        assert!(bucket.is_full());
+        assert!((bucket.tokens - 5.0).abs() < f64::EPSILON);
+    }
+}
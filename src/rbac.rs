@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::convert::Into;
 use std::fmt;
 use std::fmt::{Display, Formatter};
@@ -20,8 +21,10 @@ use std::net::{IpAddr, SocketAddr};
 use ipnet::IpNet;
 use tracing::{instrument, trace};
 
+use xds::istio::security::peer_authentication::Mode as XdsMtlsMode;
 use xds::istio::security::Address as XdsAddress;
 use xds::istio::security::Authorization as XdsRbac;
+use xds::istio::security::PeerAuthentication as XdsPeerAuthentication;
 use xds::istio::security::StringMatch as XdsStringMatch;
 
 use crate::identity::Identity;
@@ -76,6 +79,15 @@ impl Authorization {
         format!("{}/{}", self.namespace, self.name)
     }
 
+    /// applies_to returns true if the policy is one of those checked for connections to `wl`.
+    pub fn applies_to(&self, wl: &workload::Workload) -> bool {
+        match self.scope {
+            RbacScope::Global => true,
+            RbacScope::Namespace => self.namespace == wl.namespace,
+            RbacScope::WorkloadSelector => wl.authorization_policies.contains(&self.to_key()),
+        }
+    }
+
     #[instrument(level = "trace", skip_all, fields(policy=self.to_key()))]
     pub fn matches(&self, conn: &Connection) -> bool {
         let id = conn
@@ -359,17 +371,23 @@ impl TryFrom<&Match> for RbacMatch {
             destination_ports: resource
                 .destination_ports
                 .iter()
-                .map(|p| *p as u16)
-                .collect(),
+                .map(|p| port_from_xds(*p))
+                .collect::<Result<_, _>>()?,
             not_destination_ports: resource
                 .not_destination_ports
                 .iter()
-                .map(|p| *p as u16)
-                .collect(),
+                .map(|p| port_from_xds(*p))
+                .collect::<Result<_, _>>()?,
         })
     }
 }
 
+// port_from_xds converts an xDS port, rejecting those out of range rather than truncating them into
+// a different port.
+fn port_from_xds(port: u32) -> Result<u16, WorkloadError> {
+    u16::try_from(port).map_err(|_| WorkloadError::PortParse(port))
+}
+
 impl TryFrom<&XdsAddress> for IpNet {
     type Error = WorkloadError;
     fn try_from(resource: &XdsAddress) -> Result<Self, Self::Error> {
@@ -391,6 +409,79 @@ impl From<&XdsStringMatch> for Option<StringMatch> {
     }
 }
 
+/// PeerAuthentication sets whether the workloads it applies to accept plaintext connections.
+#[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PeerAuthentication {
+    pub name: String,
+    pub namespace: String,
+    pub scope: RbacScope,
+    /// mode applies to ports without a mode of their own. If unset, the policy with the next
+    /// broadest scope decides.
+    #[serde(default)]
+    pub mode: Option<MtlsMode>,
+    #[serde(default)]
+    pub port_level_mtls: BTreeMap<u16, MtlsMode>,
+}
+
+impl PeerAuthentication {
+    pub fn to_key(&self) -> String {
+        format!("{}/{}", self.namespace, self.name)
+    }
+
+    /// mode_for returns the mode this policy sets for `port`, or for ports without a mode of their
+    /// own if `port` is None.
+    pub fn mode_for(&self, port: Option<u16>) -> Option<MtlsMode> {
+        port.and_then(|p| self.port_level_mtls.get(&p).copied())
+            .or(self.mode)
+    }
+}
+
+#[derive(
+    Debug, Default, Hash, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize,
+)]
+pub enum MtlsMode {
+    /// Both plaintext and mTLS connections are accepted.
+    #[default]
+    Permissive,
+    /// Plaintext connections are refused.
+    Strict,
+    /// Peers send plaintext rather than tunneling over mTLS.
+    Disable,
+}
+
+// mtls_mode_from_xds converts an xDS mode, which may be unset.
+fn mtls_mode_from_xds(value: i32) -> Result<Option<MtlsMode>, WorkloadError> {
+    match XdsMtlsMode::from_i32(value) {
+        Some(XdsMtlsMode::Unset) => Ok(None),
+        Some(XdsMtlsMode::Permissive) => Ok(Some(MtlsMode::Permissive)),
+        Some(XdsMtlsMode::Strict) => Ok(Some(MtlsMode::Strict)),
+        Some(XdsMtlsMode::Disable) => Ok(Some(MtlsMode::Disable)),
+        None => Err(EnumParse("unknown type".into())),
+    }
+}
+
+impl TryFrom<&XdsPeerAuthentication> for PeerAuthentication {
+    type Error = WorkloadError;
+
+    fn try_from(resource: &XdsPeerAuthentication) -> Result<Self, Self::Error> {
+        let mut port_level_mtls = BTreeMap::new();
+        for (port, mode) in &resource.port_level_mtls {
+            // A port with an unset mode is the same as one without a mode
+            if let Some(mode) = mtls_mode_from_xds(*mode)? {
+                port_level_mtls.insert(port_from_xds(*port)?, mode);
+            }
+        }
+        Ok(PeerAuthentication {
+            name: resource.name.clone(),
+            namespace: resource.namespace.clone(),
+            scope: RbacScope::try_from(xds::istio::security::Scope::from_i32(resource.scope))?,
+            mode: mtls_mode_from_xds(resource.mode)?,
+            port_level_mtls,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use test_case::test_case;
@@ -426,6 +517,27 @@ mod tests {
         }
     }
 
+    #[test_case(RbacScope::Global, "other", &[], true; "global")]
+    #[test_case(RbacScope::Namespace, "ns", &[], true; "namespace")]
+    #[test_case(RbacScope::Namespace, "other", &[], false; "other namespace")]
+    #[test_case(RbacScope::WorkloadSelector, "ns", &["ns/policy"], true; "selected")]
+    #[test_case(RbacScope::WorkloadSelector, "ns", &["ns/other"], false; "not selected")]
+    fn applies_to(scope: RbacScope, namespace: &str, selected: &[&str], expected: bool) {
+        let pol = Authorization {
+            name: "policy".to_string(),
+            namespace: "ns".to_string(),
+            scope,
+            action: RbacAction::Allow,
+            groups: vec![],
+        };
+        let wl = workload::Workload {
+            namespace: namespace.to_string(),
+            authorization_policies: selected.iter().map(|s| s.to_string()).collect(),
+            ..crate::test_helpers::test_default_workload()
+        };
+        assert_eq!(pol.applies_to(&wl), expected);
+    }
+
     fn plaintext_conn() -> Connection {
         Connection {
             src_identity: None,
@@ -535,4 +647,73 @@ mod tests {
     fn string_match(matcher: StringMatch, matchee: &str, expect: bool) {
         assert_eq!(matcher.matches(matchee), expect)
     }
+
+    #[test_case(Some(MtlsMode::Strict), &[], None => Some(MtlsMode::Strict); "mode")]
+    #[test_case(Some(MtlsMode::Strict), &[], Some(8080) => Some(MtlsMode::Strict); "port without mode")]
+    #[test_case(Some(MtlsMode::Strict), &[(8080, MtlsMode::Permissive)], Some(8080) => Some(MtlsMode::Permissive); "port mode")]
+    #[test_case(Some(MtlsMode::Strict), &[(8080, MtlsMode::Permissive)], None => Some(MtlsMode::Strict); "no port")]
+    #[test_case(None, &[(8080, MtlsMode::Disable)], Some(9090) => None; "unset")]
+    fn peer_authentication_mode(
+        mode: Option<MtlsMode>,
+        ports: &[(u16, MtlsMode)],
+        port: Option<u16>,
+    ) -> Option<MtlsMode> {
+        let policy = PeerAuthentication {
+            name: "policy".to_string(),
+            namespace: "namespace".to_string(),
+            scope: RbacScope::Namespace,
+            mode,
+            port_level_mtls: ports.iter().copied().collect(),
+        };
+        policy.mode_for(port)
+    }
+
+    #[test]
+    fn peer_authentication_from_xds() {
+        let resource = XdsPeerAuthentication {
+            name: "policy".to_string(),
+            namespace: "namespace".to_string(),
+            scope: xds::istio::security::Scope::WorkloadSelector as i32,
+            mode: XdsMtlsMode::Strict as i32,
+            port_level_mtls: [
+                (8080, XdsMtlsMode::Disable as i32),
+                (9090, XdsMtlsMode::Unset as i32),
+            ]
+            .into(),
+        };
+        assert_eq!(
+            PeerAuthentication::try_from(&resource).unwrap(),
+            PeerAuthentication {
+                name: "policy".to_string(),
+                namespace: "namespace".to_string(),
+                scope: RbacScope::WorkloadSelector,
+                mode: Some(MtlsMode::Strict),
+                port_level_mtls: [(8080, MtlsMode::Disable)].into(),
+            }
+        );
+
+        // Ports out of range are rejected, rather than truncated into another port
+        let resource = XdsPeerAuthentication {
+            port_level_mtls: [(65536 + 8080, XdsMtlsMode::Disable as i32)].into(),
+            ..resource
+        };
+        assert_eq!(
+            PeerAuthentication::try_from(&resource),
+            Err(WorkloadError::PortParse(65536 + 8080))
+        );
+    }
+
+    #[test_case(&[80, 65536 + 80], &[]; "destination ports")]
+    #[test_case(&[], &[65536 + 80]; "not destination ports")]
+    fn match_invalid_port(destination_ports: &[u32], not_destination_ports: &[u32]) {
+        let resource = Match {
+            destination_ports: destination_ports.to_vec(),
+            not_destination_ports: not_destination_ports.to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            RbacMatch::try_from(&resource),
+            Err(WorkloadError::PortParse(65536 + 80))
+        );
+    }
 }
@@ -14,18 +14,70 @@
 
 use std::io::Error;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
 
+use socket2::{SockRef, TcpKeepalive};
 use tokio::io;
 use tokio::net::TcpListener;
 use tokio::net::TcpSocket;
+use tokio::net::UdpSocket;
 
 #[cfg(target_os = "linux")]
-use {
-    realm_io,
-    socket2::{Domain, SockRef},
-    std::io::ErrorKind,
-    tracing::warn,
-};
+use {realm_io, socket2::Domain, std::io::ErrorKind, tracing::warn};
+
+/// SocketOptions are applied to every socket used to proxy traffic, both downstream and upstream.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketOptions {
+    /// How long a connection is idle before TCP keepalive probes are sent. None disables keepalive.
+    pub keepalive_time: Option<Duration>,
+    /// The interval between keepalive probes. Only supported on Linux.
+    pub keepalive_interval: Option<Duration>,
+    /// The number of unacknowledged keepalive probes before the connection is dropped. Only
+    /// supported on Linux.
+    pub keepalive_retries: Option<u32>,
+    /// How long transmitted data may remain unacknowledged before the connection is dropped
+    /// (TCP_USER_TIMEOUT). Only supported on Linux.
+    pub user_timeout: Option<Duration>,
+    /// The size of the receive buffer (SO_RCVBUF).
+    pub recv_buffer_size: Option<usize>,
+    /// The size of the send buffer (SO_SNDBUF).
+    pub send_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    /// apply sets the options on `socket`. Sockets accepted from a listener inherit its options, so
+    /// applying them to a listener covers all of its connections.
+    pub fn apply(&self, socket: &impl AsRawFd) -> io::Result<()> {
+        let sock = SockRef::from(socket);
+        if let Some(time) = self.keepalive_time {
+            #[allow(unused_mut)]
+            let mut keepalive = TcpKeepalive::new().with_time(time);
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(interval) = self.keepalive_interval {
+                    keepalive = keepalive.with_interval(interval);
+                }
+                if let Some(retries) = self.keepalive_retries {
+                    keepalive = keepalive.with_retries(retries);
+                }
+            }
+            sock.set_tcp_keepalive(&keepalive)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(timeout) = self.user_timeout {
+            sock.set_tcp_user_timeout(Some(timeout))?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
 
 #[cfg(target_os = "linux")]
 pub fn set_transparent(l: &TcpListener) -> io::Result<()> {
@@ -49,6 +101,109 @@ pub fn set_freebind_and_transparent(socket: &TcpSocket) -> io::Result<()> {
     Ok(())
 }
 
+/// set_udp_transparent allows a UDP socket to receive datagrams addressed to other hosts, such as
+/// those redirected with TPROXY, or to bind to a non-local address to send replies from it.
+#[cfg(target_os = "linux")]
+pub fn set_udp_transparent(socket: &UdpSocket) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+    match socket.domain()? {
+        Domain::IPV4 => socket.set_ip_transparent(true),
+        Domain::IPV6 => linux::set_ipv6_transparent(&socket),
+        _ => Err(Error::new(ErrorKind::Unsupported, "unsupported domain")),
+    }
+}
+
+/// set_recv_orig_dst has the kernel report the original destination of each datagram received on
+/// `socket`, which is read with recv_from_orig_dst.
+#[cfg(target_os = "linux")]
+pub fn set_recv_orig_dst(socket: &UdpSocket) -> io::Result<()> {
+    let sock = SockRef::from(socket);
+    // Dual-stack IPv6 sockets also receive IPv4 datagrams, so need both options.
+    linux::set_bool_opt(&sock, libc::SOL_IP, libc::IP_RECVORIGDSTADDR)?;
+    if sock.domain()? == Domain::IPV6 {
+        linux::set_bool_opt(&sock, libc::SOL_IPV6, libc::IPV6_RECVORIGDSTADDR)?;
+    }
+    Ok(())
+}
+
+/// recv_from_orig_dst receives a datagram on a socket prepared with set_recv_orig_dst. It returns
+/// the size of the datagram, its source, and the destination it was originally sent to.
+#[cfg(target_os = "linux")]
+pub async fn recv_from_orig_dst(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, SocketAddr)> {
+    loop {
+        socket.readable().await?;
+        match socket.try_io(io::Interest::READABLE, || {
+            linux::recv_orig_dst(socket.as_raw_fd(), buf)
+        }) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+            Ok((n, src, dst)) => {
+                let dst =
+                    dst.ok_or_else(|| Error::other("original destination was not reported"))?;
+                return Ok((n, to_canonical(src), to_canonical(dst)));
+            }
+        }
+    }
+}
+
+/// bind_transparent_udp binds a UDP socket to `addr`, which need not be local, so that replies to
+/// captured datagrams appear to come from their original destination. Each flow replying from the
+/// same address binds its own socket.
+#[cfg(target_os = "linux")]
+pub fn bind_transparent_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = socket2::Socket::new(
+        Domain::for_address(addr),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    match addr {
+        SocketAddr::V4(_) => socket.set_ip_transparent(true)?,
+        SocketAddr::V6(_) => linux::set_ipv6_transparent(&SockRef::from(&socket))?,
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_transparent_udp(_: SocketAddr) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "IP_TRANSPARENT not supported on this operating system",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_udp_transparent(_: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "IP_TRANSPARENT not supported on this operating system",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_recv_orig_dst(_: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "IP_RECVORIGDSTADDR not supported on this operating system",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn recv_from_orig_dst(
+    _: &UdpSocket,
+    _: &mut [u8],
+) -> io::Result<(usize, SocketAddr, SocketAddr)> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "IP_RECVORIGDSTADDR not supported on this operating system",
+    ))
+}
+
 pub fn to_canonical(addr: SocketAddr) -> SocketAddr {
     // another match has to be used for IPv4 and IPv6 support
     // @zhlsunshine TODO: to_canonical() should be used when it becomes stable a function in Rust
@@ -118,14 +273,130 @@ pub fn set_transparent(_: &TcpListener) -> io::Result<()> {
     ))
 }
 
+/// idle_time returns how long it has been since data was last sent or received on the TCP socket
+/// `fd`. This is tracked by the kernel, so works regardless of how the socket is being copied.
+#[cfg(target_os = "linux")]
+pub fn idle_time(fd: RawFd) -> io::Result<Duration> {
+    let info = linux::tcp_info(fd)?;
+    let idle_ms = info.last_data_recv.min(info.last_data_sent);
+    Ok(Duration::from_millis(idle_ms as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn idle_time(_: RawFd) -> io::Result<Duration> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "TCP_INFO not supported on this operating system",
+    ))
+}
+
+/// bytes_transferred returns how many bytes have been read from the TCP socket `fd`, and how many
+/// written to it. Like idle_time, this is tracked by the kernel, so works regardless of how the
+/// socket is being copied. Bytes still waiting in the receive queue have not been read yet, while
+/// bytes waiting to be acknowledged by the peer have already been written.
+#[cfg(target_os = "linux")]
+pub fn bytes_transferred(fd: RawFd) -> io::Result<(u64, u64)> {
+    let info = linux::tcp_info(fd)?;
+    let (unread, unacked) = linux::queued(fd)?;
+    // The kernel counts a FIN as a byte of the stream, once it has been sent or received
+    let (fin_received, fin_sent) = linux::fin_exchanged(info.state);
+    Ok((
+        info.bytes_received
+            .saturating_sub(unread + fin_received as u64),
+        (info.bytes_acked + unacked).saturating_sub(fin_sent as u64),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bytes_transferred(_: RawFd) -> io::Result<(u64, u64)> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "TCP_INFO not supported on this operating system",
+    ))
+}
+
 #[cfg(target_os = "linux")]
 #[allow(unsafe_code)]
 mod linux {
-    use std::os::unix::io::AsRawFd;
+    use std::net::SocketAddr;
+    use std::os::unix::io::{AsRawFd, RawFd};
 
     use socket2::{SockAddr, SockRef};
     use tokio::io;
 
+    pub fn set_bool_opt(sock: &SockRef, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+        let optval: libc::c_int = 1;
+        // Safety: optval is a valid c_int, and its size is passed along with it.
+        let ret = unsafe {
+            libc::setsockopt(
+                sock.as_raw_fd(),
+                level,
+                name,
+                &optval as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&optval) as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// recv_orig_dst receives a datagram with recvmsg, returning its size, source, and original
+    /// destination if it was reported in the control messages.
+    pub fn recv_orig_dst(
+        fd: RawFd,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        // Safety: every pointer in msg refers to a local that outlives the recvmsg call, and the
+        // control messages are only read within the length the kernel reports.
+        unsafe {
+            let mut src: libc::sockaddr_storage = std::mem::zeroed();
+            let mut iov = libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            };
+            // u64 to align the buffer for cmsghdr
+            let mut control = [0u64; 32];
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = &mut src as *mut _ as *mut libc::c_void;
+            msg.msg_namelen = std::mem::size_of_val(&src) as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+            let n = libc::recvmsg(fd, &mut msg, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let src = SockAddr::new(src, msg.msg_namelen)
+                .as_socket()
+                .ok_or_else(|| io::Error::other("unsupported address"))?;
+
+            let mut dst = None;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let header = &*cmsg;
+                if (header.cmsg_level == libc::SOL_IP && header.cmsg_type == libc::IP_ORIGDSTADDR)
+                    || (header.cmsg_level == libc::SOL_IPV6
+                        && header.cmsg_type == libc::IPV6_ORIGDSTADDR)
+                {
+                    let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+                    let len = (header.cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                        .min(std::mem::size_of_val(&storage));
+                    std::ptr::copy_nonoverlapping(
+                        libc::CMSG_DATA(cmsg),
+                        &mut storage as *mut _ as *mut u8,
+                        len,
+                    );
+                    dst = SockAddr::new(storage, len as libc::socklen_t).as_socket();
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            Ok((n as usize, src, dst))
+        }
+    }
+
     pub fn set_ipv6_transparent(sock: &SockRef) -> io::Result<()> {
         unsafe {
             let optval: libc::c_int = 1;
@@ -184,6 +455,75 @@ mod linux {
         }
         .map(|(_, addr)| addr)
     }
+
+    /// TcpInfo is the prefix of `struct tcp_info` from linux/tcp.h, up to the fields we use. The
+    /// kernel only fills in as much of the struct as we ask for.
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct TcpInfo {
+        pub state: u8,
+        _ca_state: [u8; 7],
+        _counters: [u32; 9],
+        pub last_data_sent: u32,
+        _last_ack_sent: u32,
+        pub last_data_recv: u32,
+        _last_ack_recv: u32,
+        _metrics: [u32; 11],
+        _pacing_rates: [u64; 2],
+        pub bytes_acked: u64,
+        pub bytes_received: u64,
+    }
+
+    /// fin_exchanged returns whether a FIN has been received, and whether one has been sent, by a
+    /// TCP socket in `state`, from linux/tcp_states.h.
+    pub fn fin_exchanged(state: u8) -> (bool, bool) {
+        const FIN_WAIT1: u8 = 4;
+        const FIN_WAIT2: u8 = 5;
+        const TIME_WAIT: u8 = 6;
+        const CLOSE_WAIT: u8 = 8;
+        const LAST_ACK: u8 = 9;
+        const CLOSING: u8 = 11;
+        (
+            matches!(state, TIME_WAIT | CLOSE_WAIT | LAST_ACK | CLOSING),
+            matches!(
+                state,
+                FIN_WAIT1 | FIN_WAIT2 | TIME_WAIT | LAST_ACK | CLOSING
+            ),
+        )
+    }
+
+    /// queued returns how many bytes are in the receive queue of the TCP socket `fd` waiting to be
+    /// read, and in its send queue waiting to be acknowledged.
+    pub fn queued(fd: RawFd) -> io::Result<(u64, u64)> {
+        let ioctl = |request| {
+            let mut n: libc::c_int = 0;
+            // Safety: both requests write a single c_int.
+            match unsafe { libc::ioctl(fd, request, &mut n) } {
+                0 => Ok(n as u64),
+                _ => Err(io::Error::last_os_error()),
+            }
+        };
+        Ok((ioctl(libc::FIONREAD)?, ioctl(libc::TIOCOUTQ)?))
+    }
+
+    pub fn tcp_info(fd: RawFd) -> io::Result<TcpInfo> {
+        let mut info = TcpInfo::default();
+        let mut len = std::mem::size_of_val(&info) as libc::socklen_t;
+        // Safety: TcpInfo matches the kernel layout, and `getsockopt` writes at most `len` bytes.
+        unsafe {
+            let ret = libc::getsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                &mut info as *mut _ as *mut libc::c_void,
+                &mut len,
+            );
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(info)
+        }
+    }
 }
 
 #[cfg(all(target_os = "linux"))]
@@ -195,7 +535,7 @@ pub async fn relay(
 
     match realm_io::bidi_zero_copy(downstream, upstream).await {
         Ok(d) => Ok(d),
-        Err(ref e) if e.raw_os_error().map_or(false, |ec| ec == EINVAL) => {
+        Err(ref e) if e.raw_os_error() == Some(EINVAL) => {
             tokio::io::copy_bidirectional(downstream, upstream).await
         }
         Err(e) => Err(e),
@@ -209,3 +549,62 @@ pub async fn relay(
 ) -> Result<(u64, u64), Error> {
     tokio::io::copy_bidirectional(downstream, upstream).await
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::time::Duration;
+
+    use socket2::SockRef;
+    use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+    use super::SocketOptions;
+
+    #[tokio::test]
+    async fn recv_from_orig_dst() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        super::set_recv_orig_dst(&socket).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(b"hello", socket.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        let (n, src, dst) = super::recv_from_orig_dst(&socket, &mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert_eq!(src, client.local_addr().unwrap());
+        // Without TPROXY, the original destination is the socket's own address
+        assert_eq!(dst, socket.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn accepted_sockets_inherit_options() {
+        let opts = SocketOptions {
+            keepalive_time: Some(Duration::from_secs(30)),
+            keepalive_interval: Some(Duration::from_secs(10)),
+            keepalive_retries: Some(3),
+            user_timeout: Some(Duration::from_secs(20)),
+            recv_buffer_size: Some(64 * 1024),
+            send_buffer_size: Some(64 * 1024),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        opts.apply(&listener).unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+
+        let sock = SockRef::from(&accepted);
+        assert!(sock.keepalive().unwrap());
+        assert_eq!(sock.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(sock.keepalive_interval().unwrap(), Duration::from_secs(10));
+        assert_eq!(sock.keepalive_retries().unwrap(), 3);
+        assert_eq!(
+            sock.tcp_user_timeout().unwrap(),
+            Some(Duration::from_secs(20))
+        );
+        // The kernel doubles the requested size to allow for bookkeeping overhead
+        assert_eq!(sock.recv_buffer_size().unwrap(), 128 * 1024);
+        assert_eq!(sock.send_buffer_size().unwrap(), 128 * 1024);
+    }
+}
@@ -26,7 +26,7 @@ use tracing::trace;
 use crate::config::ConfigSource;
 use crate::config::{self, RootCert};
 use crate::workload::Protocol::{HBONE, TCP};
-use crate::workload::{LocalConfig, LocalWorkload, Workload};
+use crate::workload::{LocalConfig, LocalWorkload, Service, Workload};
 
 pub mod app;
 pub mod ca;
@@ -87,10 +87,12 @@ pub const TEST_WORKLOAD_HBONE: &str = "127.0.0.3";
 pub const TEST_WORKLOAD_TCP: &str = "127.0.0.4";
 pub const TEST_WORKLOAD_WAYPOINT: &str = "127.0.0.4";
 pub const TEST_VIP: &str = "127.10.0.1";
+pub const TEST_SERVICE_HOST: &str = "echo.default.svc.cluster.local";
 
 pub fn test_default_workload() -> Workload {
     Workload {
-        workload_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        uid: "".to_string(),
+        workload_ips: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
         waypoint_addresses: Vec::new(),
         gateway_address: None,
         protocol: Default::default(),
@@ -103,10 +105,14 @@ pub fn test_default_workload() -> Workload {
         canonical_name: "".to_string(),
         canonical_revision: "".to_string(),
         node: "".to_string(),
+        network: "".to_string(),
+        locality: Default::default(),
+        proxy_protocol: None,
         status: Default::default(),
         cluster_id: "Kubernetes".to_string(),
 
         authorization_policies: Vec::new(),
+        peer_authentication_policies: Vec::new(),
         native_hbone: false,
     }
 }
@@ -115,7 +121,7 @@ fn local_xds_config(echo_port: u16, waypoint_ip: Option<IpAddr>) -> anyhow::Resu
     let mut res: Vec<LocalWorkload> = vec![
         LocalWorkload {
             workload: Workload {
-                workload_ip: TEST_WORKLOAD_HBONE.parse()?,
+                workload_ips: vec![TEST_WORKLOAD_HBONE.parse()?],
                 protocol: HBONE,
                 name: "local-hbone".to_string(),
                 namespace: "default".to_string(),
@@ -127,7 +133,7 @@ fn local_xds_config(echo_port: u16, waypoint_ip: Option<IpAddr>) -> anyhow::Resu
         },
         LocalWorkload {
             workload: Workload {
-                workload_ip: TEST_WORKLOAD_TCP.parse()?,
+                workload_ips: vec![TEST_WORKLOAD_TCP.parse()?],
                 protocol: TCP,
                 name: "local-tcp".to_string(),
                 namespace: "default".to_string(),
@@ -139,7 +145,7 @@ fn local_xds_config(echo_port: u16, waypoint_ip: Option<IpAddr>) -> anyhow::Resu
         },
         LocalWorkload {
             workload: Workload {
-                workload_ip: TEST_WORKLOAD_SOURCE.parse()?,
+                workload_ips: vec![TEST_WORKLOAD_SOURCE.parse()?],
                 protocol: TCP,
                 name: "local-source".to_string(),
                 namespace: "default".to_string(),
@@ -153,7 +159,7 @@ fn local_xds_config(echo_port: u16, waypoint_ip: Option<IpAddr>) -> anyhow::Resu
     if let Some(waypoint_ip) = waypoint_ip {
         res.push(LocalWorkload {
             workload: Workload {
-                workload_ip: TEST_WORKLOAD_WAYPOINT.parse()?,
+                workload_ips: vec![TEST_WORKLOAD_WAYPOINT.parse()?],
                 protocol: HBONE,
                 name: "local-waypoint".to_string(),
                 namespace: "default".to_string(),
@@ -167,7 +173,15 @@ fn local_xds_config(echo_port: u16, waypoint_ip: Option<IpAddr>) -> anyhow::Resu
     }
     let lc = LocalConfig {
         workloads: res,
+        services: vec![Service {
+            name: "echo".to_string(),
+            namespace: "default".to_string(),
+            hostname: TEST_SERVICE_HOST.to_string(),
+            vips: vec![TEST_VIP.parse()?],
+            ports: HashMap::from([(80u16, echo_port)]),
+        }],
         policies: vec![],
+        peer_authentications: vec![],
     };
     let mut b = bytes::BytesMut::new().writer();
     serde_yaml::to_writer(&mut b, &lc)?;
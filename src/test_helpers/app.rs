@@ -24,7 +24,7 @@ use hyper::{body, Body, Client, Method, Request, Response};
 use itertools::Itertools;
 use prometheus_parse::Scrape;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpSocket, TcpStream};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
 
 use crate::app::Bound;
 use crate::identity::SecretManager;
@@ -144,12 +144,77 @@ impl TestApp {
     }
 
     pub async fn socks5_connect(&self, addr: SocketAddr) -> TcpStream {
+        let stream = self.socks5_stream().await;
+        socks5_connect(stream, addr).await.unwrap()
+    }
+
+    /// socks5_connect_hostname is like socks5_connect, but has the proxy resolve `host`.
+    pub async fn socks5_connect_hostname(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> anyhow::Result<TcpStream> {
+        let stream = self.socks5_stream().await;
+        socks5_connect_hostname(stream, host, port).await
+    }
+
+    /// socks5_connect_with_credentials is like socks5_connect, but authenticates with a username
+    /// and password.
+    pub async fn socks5_connect_with_credentials(
+        &self,
+        addr: SocketAddr,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<TcpStream> {
+        let stream = self.socks5_stream().await;
+        socks5_connect_with_credentials(stream, addr, username, password).await
+    }
+
+    /// socks5_udp_associate sets up a UDP association, with datagrams sent from TEST_WORKLOAD_SOURCE.
+    pub async fn socks5_udp_associate(&self) -> anyhow::Result<Socks5Udp> {
+        let stream = self.socks5_stream().await;
+        let socket = UdpSocket::bind(SocketAddr::from((
+            TEST_WORKLOAD_SOURCE.parse::<IpAddr>().unwrap(),
+            0,
+        )))
+        .await?;
+        socks5_udp_associate(stream, socket).await
+    }
+
+    /// http_connect sends a CONNECT request for `target` to the HTTP CONNECT proxy, and returns the
+    /// response status along with the connection.
+    pub async fn http_connect(&self, target: &str) -> anyhow::Result<(u16, TcpStream)> {
+        let addr = self
+            .proxy_addresses
+            .http_connect
+            .expect("http connect proxy must be enabled");
+        let mut stream = self.stream_from_source(addr).await;
+        let request = format!("CONNECT {target} HTTP/1.1\r\nhost: {target}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        // Read a byte at a time, so we do not consume any data after the response
+        let mut head = Vec::new();
+        let mut byte = [0u8];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).await?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head)?;
+        let status = head
+            .split(' ')
+            .nth(1)
+            .ok_or_else(|| anyhow::anyhow!("malformed response {head}"))?
+            .parse()?;
+        Ok((status, stream))
+    }
+
+    async fn socks5_stream(&self) -> TcpStream {
+        self.stream_from_source(self.proxy_addresses.socks5).await
+    }
+
+    async fn stream_from_source(&self, addr: SocketAddr) -> TcpStream {
         // Always use IPv4 address. In theory, we can resolve `localhost` to pick to support any machine
         // However, we need to make sure the WorkloadStore knows about both families then.
-        let socks_addr = with_ip(
-            self.proxy_addresses.socks5,
-            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-        );
+        let addr = with_ip(addr, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
         // Set source IP to TEST_WORKLOAD_SOURCE
         let socket = TcpSocket::new_v4().unwrap();
         socket
@@ -159,40 +224,178 @@ impl TestApp {
             )))
             .unwrap();
 
-        let stream = socket.connect(socks_addr).await.unwrap();
+        let stream = socket.connect(addr).await.unwrap();
         stream.set_nodelay(true).unwrap();
-        socks5_connect(stream, addr).await.unwrap()
+        stream
     }
 }
 
 pub async fn socks5_connect(mut stream: TcpStream, addr: SocketAddr) -> anyhow::Result<TcpStream> {
-    let addr_type = if addr.ip().is_ipv4() { 0x01u8 } else { 0x04u8 };
+    socks5_authenticate(&mut stream, None).await?;
+    socks5_request(stream, addr).await
+}
+
+pub async fn socks5_connect_hostname(
+    mut stream: TcpStream,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<TcpStream> {
+    socks5_authenticate(&mut stream, None).await?;
+    let mut domain = vec![host.len() as u8];
+    domain.extend_from_slice(host.as_bytes());
+    socks5_command(stream, 0x03, &domain, port).await
+}
+
+pub async fn socks5_connect_with_credentials(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<TcpStream> {
+    socks5_authenticate(&mut stream, Some((username, password))).await?;
+    socks5_request(stream, addr).await
+}
+
+/// Socks5Udp is a SOCKS5 UDP association. It lasts until dropped, which closes the control
+/// connection.
+pub struct Socks5Udp {
+    _control: TcpStream,
+    socket: UdpSocket,
+}
+
+impl Socks5Udp {
+    /// send_to sends `payload` to `addr` through the relay.
+    pub async fn send_to(&self, addr: SocketAddr, payload: &[u8]) -> anyhow::Result<()> {
+        let mut datagram = vec![0x0u8, 0x0u8, 0x0u8]; // RSV, FRAG
+        match socket::to_canonical(addr).ip() {
+            IpAddr::V4(ip) => {
+                datagram.push(0x01);
+                datagram.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                datagram.push(0x04);
+                datagram.extend_from_slice(&ip.octets());
+            }
+        }
+        datagram.extend_from_slice(&addr.port().to_be_bytes());
+        datagram.extend_from_slice(payload);
+        self.socket.send(&datagram).await?;
+        Ok(())
+    }
+
+    /// recv_from receives a datagram through the relay, returning the address it was sent from.
+    pub async fn recv_from(&self) -> anyhow::Result<(SocketAddr, Vec<u8>)> {
+        let mut buf = vec![0u8; 65_535];
+        let n = self.socket.recv(&mut buf).await?;
+        // Only IPv4 is used in tests
+        if n < 10 || buf[3] != 0x01 {
+            anyhow::bail!("unexpected socks5 datagram header {:?}", &buf[..n.min(10)]);
+        }
+        let ip: [u8; 4] = buf[4..8].try_into()?;
+        let port = u16::from_be_bytes([buf[8], buf[9]]);
+        Ok((SocketAddr::from((ip, port)), buf[10..n].to_vec()))
+    }
+}
+
+pub async fn socks5_udp_associate(
+    mut stream: TcpStream,
+    socket: UdpSocket,
+) -> anyhow::Result<Socks5Udp> {
+    socks5_authenticate(&mut stream, None).await?;
+    let local = socket.local_addr()?;
+    let IpAddr::V4(ip) = local.ip() else {
+        anyhow::bail!("only IPv4 is supported");
+    };
+    let mut cmd = vec![
+        0x05u8, // socks5
+        0x3u8,  // udp associate
+        0x0u8,  // RSV
+        0x1u8,  // IPv4
+    ];
+    cmd.extend_from_slice(&ip.octets());
+    cmd.extend_from_slice(&local.port().to_be_bytes());
+    stream.write_all(&cmd).await?;
+
+    let mut resp = [0u8; 10];
+    stream.read_exact(&mut resp).await?;
+    if resp[1] != 0 || resp[3] != 0x01 {
+        anyhow::bail!("socks5 udp associate failed with reply {:?}", resp);
+    }
+    let relay_ip: [u8; 4] = resp[4..8].try_into()?;
+    let relay = SocketAddr::from((relay_ip, u16::from_be_bytes([resp[8], resp[9]])));
+    socket.connect(relay).await?;
+    Ok(Socks5Udp {
+        _control: stream,
+        socket,
+    })
+}
+
+/// socks5_authenticate negotiates the auth method, using username/password auth if `credentials`
+/// are given.
+async fn socks5_authenticate(
+    stream: &mut TcpStream,
+    credentials: Option<(&str, &str)>,
+) -> anyhow::Result<()> {
+    let method = if credentials.is_some() { 0x2u8 } else { 0x0u8 };
     stream
         .write_all(&[
             0x05u8, // socks5
             0x1u8,  // 1 auth method
-            0x0u8,  // unauthenticated auth method
+            method,
         ])
         .await?;
     let mut auth = [0u8; 2];
     stream.read_exact(&mut auth).await?;
+    if auth[1] != method {
+        anyhow::bail!("socks5 auth method {method} rejected");
+    }
+    let Some((username, password)) = credentials else {
+        return Ok(());
+    };
+    let mut req = vec![0x01u8, username.len() as u8];
+    req.extend_from_slice(username.as_bytes());
+    req.push(password.len() as u8);
+    req.extend_from_slice(password.as_bytes());
+    stream.write_all(&req).await?;
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await?;
+    if status[1] != 0 {
+        anyhow::bail!("socks5 authentication failed");
+    }
+    Ok(())
+}
 
+async fn socks5_request(stream: TcpStream, addr: SocketAddr) -> anyhow::Result<TcpStream> {
+    let addr_type = if addr.ip().is_ipv4() { 0x01u8 } else { 0x04u8 };
+    let host = match socket::to_canonical(addr).ip() {
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+    };
+    socks5_command(stream, addr_type, &host, addr.port()).await
+}
+
+async fn socks5_command(
+    mut stream: TcpStream,
+    addr_type: u8,
+    host: &[u8],
+    port: u16,
+) -> anyhow::Result<TcpStream> {
     let mut cmd = vec![
         0x05u8, // socks5
         0x1u8,  // establish tcp stream
         0x0u8,  // RSV
         addr_type,
     ];
-    match socket::to_canonical(addr).ip() {
-        IpAddr::V6(ip) => cmd.extend_from_slice(&ip.octets()),
-        IpAddr::V4(ip) => cmd.extend_from_slice(&ip.octets()),
-    };
-    cmd.extend_from_slice(&addr.port().to_be_bytes());
+    cmd.extend_from_slice(host);
+    cmd.extend_from_slice(&port.to_be_bytes());
     stream.write_all(&cmd).await?;
 
-    // We don't care about response but need to clear out the stream
+    // We don't care about the bound address but need to clear out the stream
     let mut resp = [0u8; 10];
     stream.read_exact(&mut resp).await?;
+    if resp[1] != 0 {
+        anyhow::bail!("socks5 request failed with reply {}", resp[1]);
+    }
 
     Ok(stream)
 }
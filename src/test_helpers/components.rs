@@ -63,7 +63,9 @@ impl WorkloadManager {
         let veth = ns.interface();
         let lc = LocalConfig {
             workloads: self.workloads.clone(),
+            services: vec![],
             policies: vec![],
+            peer_authentications: vec![],
         };
         let mut b = bytes::BytesMut::new().writer();
         serde_yaml::to_writer(&mut b, &lc)?;
@@ -74,6 +76,10 @@ impl WorkloadManager {
             local_xds_config: Some(ConfigSource::Static(b.into_inner().freeze())),
             local_node: Some(node.to_string()),
             local_ip: Some(ns.ip()),
+            // UDP shares the outbound port with TCP, see scripts/ztunnel-redirect.sh
+            outbound_udp_addr: Some("127.0.0.1:15001".parse()?),
+            // Short enough for tests to wait for a flow to expire
+            udp_idle_timeout: Duration::from_secs(1),
             ..config::parse_config().unwrap()
         };
         let waypoints = self.waypoints.iter().map(|i| i.to_string()).join(" ");
@@ -90,8 +96,17 @@ impl WorkloadManager {
                 stats_address: helpers::with_ip(app.stats_address, ip),
                 proxy_addresses: proxy::Addresses {
                     outbound: helpers::with_ip(app.proxy_addresses.outbound, ip),
+                    outbound_udp: app
+                        .proxy_addresses
+                        .outbound_udp
+                        .map(|a| helpers::with_ip(a, ip)),
                     inbound: helpers::with_ip(app.proxy_addresses.inbound, ip),
+                    inbound_plaintext: helpers::with_ip(app.proxy_addresses.inbound_plaintext, ip),
                     socks5: helpers::with_ip(app.proxy_addresses.socks5, ip),
+                    http_connect: app
+                        .proxy_addresses
+                        .http_connect
+                        .map(|a| helpers::with_ip(a, ip)),
                 },
                 readiness_address: helpers::with_ip(app.readiness_address, ip),
                 cert_manager,
@@ -208,10 +223,12 @@ impl<'a> TestWorkloadBuilder<'a> {
             .manager
             .namespaces
             .child(&self.w.workload.node, &self.w.workload.name)?;
-        self.w.workload.workload_ip = network_namespace.ip();
+        self.w.workload.workload_ips = vec![network_namespace.ip()];
         info!(
             "registered {}/{} at {}",
-            self.w.workload.namespace, self.w.workload.name, self.w.workload.workload_ip
+            self.w.workload.namespace,
+            self.w.workload.name,
+            network_namespace.ip()
         );
         self.manager.workloads.push(self.w);
         if self.captured {
@@ -93,13 +93,19 @@ impl AdsServer {
 
         let workloads: Arc<Mutex<WorkloadStore>> = Arc::new(Mutex::new(WorkloadStore::default()));
         let xds_workloads = workloads.clone();
+        let xds_services = workloads.clone();
         let xds_rbac = workloads.clone();
+        let xds_peer_authn = workloads.clone();
 
         let xds_client = xds::Config::new(cfg)
             .with_workload_handler(xds_workloads)
+            .with_service_handler(xds_services)
             .with_authorization_handler(xds_rbac)
+            .with_peer_authentication_handler(xds_peer_authn)
             .watch(xds::WORKLOAD_TYPE.into())
+            .watch(xds::SERVICE_TYPE.into())
             .watch(xds::AUTHORIZATION_TYPE.into())
+            .watch(xds::PEER_AUTHENTICATION_TYPE.into())
             .build(metrics, ready.register_task("ads client"));
 
         let wi = WorkloadInformation {
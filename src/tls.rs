@@ -13,6 +13,8 @@
 // limitations under the License.
 
 pub mod boring;
+#[cfg(feature = "quic")]
+pub mod quic;
 
 use std::sync::Arc;
 
@@ -30,6 +32,14 @@ pub enum Error {
 
     #[error("invalid uri: {0}")]
     InvalidUri(#[from] Arc<InvalidUri>),
+
+    #[cfg(feature = "quic")]
+    #[error("unsupported private key type: {0:?}")]
+    UnsupportedKey(::boring::pkey::Id),
+
+    #[cfg(feature = "quic")]
+    #[error("rustls error: {0}")]
+    Rustls(#[from] rustls::Error),
 }
 
 impl From<InvalidUri> for Error {
@@ -196,6 +196,11 @@ impl Certs {
     pub fn x509(&self) -> &x509::X509 {
         &self.cert.x509
     }
+
+    #[cfg(feature = "quic")]
+    pub(super) fn private_key(&self) -> &pkey::PKey<pkey::Private> {
+        &self.key
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -483,7 +488,19 @@ where
     type AcceptFuture = Pin<Box<dyn Future<Output = Result<Self::Stream, Self::Error>> + Send>>;
 
     fn accept(&self, conn: AddrStream) -> Self::AcceptFuture {
-        let inner = conn.into_inner();
+        tls_listener::AsyncTls::<TcpStream>::accept(self, conn.into_inner())
+    }
+}
+
+impl<F> tls_listener::AsyncTls<TcpStream> for BoringTlsAcceptor<F>
+where
+    F: CertProvider + Clone + 'static,
+{
+    type Stream = tokio_boring::SslStream<TcpStream>;
+    type Error = TlsError;
+    type AcceptFuture = Pin<Box<dyn Future<Output = Result<Self::Stream, Self::Error>> + Send>>;
+
+    fn accept(&self, inner: TcpStream) -> Self::AcceptFuture {
         let mut acceptor = self.acceptor.clone();
         Box::pin(async move {
             let tls = acceptor.fetch_cert(&inner).await?;
@@ -0,0 +1,276 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS for HBONE over HTTP/3. QUIC integrates with the TLS stack at a lower level than boring
+//! exposes, so the handshake is done by rustls. Peer certificates are still verified by boring,
+//! against the same roots as HBONE over HTTP/2.
+//!
+//! rustls uses ring for its cryptography, which is not FIPS validated, so the quic feature cannot
+//! be combined with the fips feature.
+
+#[cfg(feature = "fips")]
+compile_error!("the quic feature uses non-FIPS cryptography, build with --no-default-features");
+
+use std::sync::Arc;
+
+use boring::pkey::Id;
+use boring::stack::Stack;
+use boring::x509::store::X509StoreBuilder;
+use boring::x509::{X509StoreContext, X509};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use tracing::info;
+
+use crate::identity::Identity;
+use crate::tls::{Certs, Error, SanChecker, TlsError};
+
+/// The ALPN protocol of HTTP/3.
+const ALPN_H3: &[u8] = b"h3";
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+impl Certs {
+    /// quic_server_config returns the configuration to accept HBONE over HTTP/3 with this
+    /// certificate, requiring clients to present a certificate issued by our roots.
+    pub fn quic_server_config(&self) -> Result<quinn::ServerConfig, Error> {
+        let provider = provider();
+        let verifier = Verifier::new(self, None, &provider);
+        let mut cfg = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_client_cert_verifier(Arc::new(verifier))
+            .with_single_cert(self.quic_cert_chain()?, self.quic_private_key()?)?;
+        cfg.alpn_protocols = vec![ALPN_H3.to_vec()];
+        let cfg = QuicServerConfig::try_from(cfg).expect("TLS 1.3 has an initial cipher suite");
+        Ok(quinn::ServerConfig::with_crypto(Arc::new(cfg)))
+    }
+
+    /// quic_client_config returns the configuration to connect to an HBONE over HTTP/3 server
+    /// with this certificate. As with connector, the server must present `dest_id`, if set.
+    pub fn quic_client_config(
+        &self,
+        dest_id: Option<&Identity>,
+    ) -> Result<quinn::ClientConfig, Error> {
+        let provider = provider();
+        let verifier = Verifier::new(self, dest_id.cloned(), &provider);
+        let mut cfg = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_client_auth_cert(self.quic_cert_chain()?, self.quic_private_key()?)?;
+        cfg.alpn_protocols = vec![ALPN_H3.to_vec()];
+        let cfg = QuicClientConfig::try_from(cfg).expect("TLS 1.3 has an initial cipher suite");
+        Ok(quinn::ClientConfig::new(Arc::new(cfg)))
+    }
+
+    // The leaf and intermediate certificates; as in setup_ctx, the root is left out.
+    fn quic_cert_chain(&self) -> Result<Vec<CertificateDer<'static>>, Error> {
+        let intermediates = self
+            .iter_chain()
+            .take(self.iter_chain().count().saturating_sub(1));
+        std::iter::once(self.x509())
+            .chain(intermediates)
+            .map(|c| Ok(CertificateDer::from(c.to_der()?)))
+            .collect()
+    }
+
+    fn quic_private_key(&self) -> Result<PrivateKeyDer<'static>, Error> {
+        let key = self.private_key();
+        let der = key.private_key_to_der()?;
+        // The DER encoding of a key depends on its type
+        match key.id() {
+            Id::RSA => Ok(PrivateKeyDer::Pkcs1(der.into())),
+            Id::EC => Ok(PrivateKeyDer::Sec1(der.into())),
+            id => Err(Error::UnsupportedKey(id)),
+        }
+    }
+}
+
+/// quic_peer_identity returns the identity of the certificate the peer of `conn` presented.
+pub fn quic_peer_identity(conn: &quinn::Connection) -> Option<Identity> {
+    let certs = conn
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    let cert = X509::from_der(certs.first()?).ok()?;
+    super::extract_sans(&cert).first().cloned()
+}
+
+/// NoCertificate fails every handshake. Servers must instead be configured for each connection,
+/// once the workload it is addressed to is known.
+#[derive(Debug)]
+pub struct NoCertificate;
+
+impl NoCertificate {
+    pub fn server_config() -> quinn::ServerConfig {
+        let cfg = rustls::ServerConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .expect("TLS 1.3 is supported")
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(NoCertificate));
+        let cfg = QuicServerConfig::try_from(cfg).expect("TLS 1.3 has an initial cipher suite");
+        quinn::ServerConfig::with_crypto(Arc::new(cfg))
+    }
+}
+
+impl ResolvesServerCert for NoCertificate {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        None
+    }
+}
+
+/// Verifier verifies peer certificates with boring, like Certs::connector and mtls_acceptor do.
+#[derive(Debug)]
+struct Verifier {
+    roots: Vec<X509>,
+    // The identity the peer must present, if any.
+    identity: Option<Identity>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl Verifier {
+    fn new(certs: &Certs, identity: Option<Identity>, provider: &CryptoProvider) -> Verifier {
+        Verifier {
+            roots: certs.iter_chain().cloned().collect(),
+            identity,
+            algorithms: provider.signature_verification_algorithms,
+        }
+    }
+
+    fn verify(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> Result<(), rustls::Error> {
+        self.verify_chain(end_entity, intermediates).map_err(|e| {
+            info!("failed verifying TLS: {e}");
+            rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure)
+        })
+    }
+
+    fn verify_chain(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> Result<(), TlsError> {
+        let cert = X509::from_der(end_entity).map_err(Error::SslError)?;
+        let mut store = X509StoreBuilder::new().map_err(Error::SslError)?;
+        for root in &self.roots {
+            store.add_cert(root.clone()).map_err(Error::SslError)?;
+        }
+        let store = store.build();
+        let mut chain = Stack::new().map_err(Error::SslError)?;
+        for intermediate in intermediates {
+            chain
+                .push(X509::from_der(intermediate).map_err(Error::SslError)?)
+                .map_err(Error::SslError)?;
+        }
+        let mut ctx = X509StoreContext::new().map_err(Error::SslError)?;
+        let verified = ctx
+            .init(&store, &cert, &chain, |ctx| {
+                Ok(match ctx.verify_cert()? {
+                    true => Ok(()),
+                    false => Err(TlsError::Verification(ctx.error())),
+                })
+            })
+            .map_err(Error::SslError)?;
+        verified?;
+        match &self.identity {
+            Some(identity) => cert.verify_san(identity),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // Servers are addressed by IP, so their identity is checked rather than their name
+        self.verify(end_entity, intermediates)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+impl ClientCertVerifier for Verifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.verify(end_entity, intermediates)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::Into;
 use std::default::Default;
 use std::net::{IpAddr, SocketAddr};
@@ -22,20 +22,25 @@ use std::{fmt, net};
 
 use rand::prelude::IteratorRandom;
 use thiserror::Error;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info, instrument, trace};
 
 use xds::istio::security::Authorization as XdsAuthorization;
+use xds::istio::security::PeerAuthentication as XdsPeerAuthentication;
+use xds::istio::workload::Service as XdsService;
 use xds::istio::workload::Workload as XdsWorkload;
 
 use crate::config::{ConfigSource, ProxyMode};
 use crate::identity::{Identity, SecretManager};
 use crate::metrics::Metrics;
-use crate::rbac::{Authorization, RbacScope};
+use crate::rbac::{Authorization, MtlsMode, PeerAuthentication, RbacScope};
 use crate::workload::WorkloadError::EnumParse;
 use crate::xds::{AdsClient, Demander, RejectedConfig, XdsUpdate};
 use crate::{config, rbac, readiness, xds};
 
+pub mod lb;
+pub mod outlier;
+
 #[derive(
     Default, Debug, Hash, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize,
 )]
@@ -81,7 +86,13 @@ impl TryFrom<Option<xds::istio::workload::WorkloadStatus>> for HealthStatus {
 #[derive(Debug, Hash, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Workload {
-    pub workload_ip: IpAddr,
+    /// uid identifies the workload, and is stable as its addresses change.
+    #[serde(default)]
+    pub uid: String,
+    /// workload_ips are the addresses of the workload. Dual-stack workloads have one of each family.
+    /// Configuration from before workloads had several addresses sets a single `workloadIp`.
+    #[serde(alias = "workloadIp", deserialize_with = "one_or_many")]
+    pub workload_ips: Vec<IpAddr>,
     #[serde(default)]
     pub waypoint_addresses: Vec<IpAddr>,
     #[serde(default)]
@@ -109,12 +120,21 @@ pub struct Workload {
 
     #[serde(default)]
     pub node: String,
+    #[serde(default)]
+    pub network: String,
+    #[serde(default)]
+    pub locality: Locality,
+
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocol>,
 
     #[serde(default)]
     pub native_hbone: bool,
 
     #[serde(default)]
     pub authorization_policies: Vec<String>,
+    #[serde(default)]
+    pub peer_authentication_policies: Vec<String>,
 
     #[serde(default)]
     pub status: HealthStatus,
@@ -123,7 +143,181 @@ pub struct Workload {
     pub cluster_id: String,
 }
 
+/// Locality identifies where a workload runs, from coarsest to finest granularity.
+#[derive(Default, Debug, Hash, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Locality {
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub zone: String,
+    #[serde(default)]
+    pub subzone: String,
+}
+
+impl From<xds::istio::workload::Locality> for Locality {
+    fn from(l: xds::istio::workload::Locality) -> Self {
+        Locality {
+            region: l.region,
+            zone: l.zone,
+            subzone: l.subzone,
+        }
+    }
+}
+
+/// ProxyProtocol opts a workload in to receiving a PROXY protocol v2 header on connections proxied
+/// to it over HBONE.
+#[derive(Default, Debug, Hash, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProxyProtocol {
+    /// The ports the header is sent to. If empty, it is sent to every port.
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+impl From<xds::istio::workload::ProxyProtocol> for ProxyProtocol {
+    fn from(p: xds::istio::workload::ProxyProtocol) -> Self {
+        ProxyProtocol {
+            ports: p
+                .ports
+                .into_iter()
+                .filter_map(|p| u16::try_from(p).ok())
+                .collect(),
+        }
+    }
+}
+
+/// NetworkAddress is the address of a workload on a network. Networks may have overlapping IP
+/// ranges, so an IP alone does not identify a workload. The empty network is the default network.
+#[derive(Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Clone)]
+pub struct NetworkAddress {
+    pub network: String,
+    pub address: IpAddr,
+}
+
+impl fmt::Display for NetworkAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.network.is_empty() {
+            write!(f, "{}", self.address)
+        } else {
+            write!(f, "{}/{}", self.network, self.address)
+        }
+    }
+}
+
+impl std::str::FromStr for NetworkAddress {
+    type Err = WorkloadError;
+
+    /// Parses the form used for workload resource names: `network/ip`, or just `ip` for the default
+    /// network.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, address) = s.rsplit_once('/').unwrap_or(("", s));
+        Ok(NetworkAddress {
+            network: network.to_string(),
+            address: address.parse()?,
+        })
+    }
+}
+
+/// one_or_many deserializes a list, or a single value as a list of one.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(v) => vec![v],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+impl serde::Serialize for NetworkAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for NetworkAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Service is a set of workloads reachable through shared VIPs, such as a Kubernetes Service.
+/// Workloads reference the VIPs of the services they are endpoints of, so a Service holds no endpoints.
+#[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Service {
+    pub name: String,
+    pub namespace: String,
+    pub hostname: String,
+    #[serde(default)]
+    pub vips: Vec<NetworkAddress>,
+    /// ports maps each service port to its default target port.
+    #[serde(default)]
+    pub ports: HashMap<u16, u16>,
+}
+
+impl Service {
+    /// resource_name returns the name of the service in XDS: `namespace/hostname`.
+    pub fn resource_name(&self) -> String {
+        format!("{}/{}", self.namespace, self.hostname)
+    }
+}
+
+impl TryFrom<&XdsService> for Service {
+    type Error = WorkloadError;
+    fn try_from(resource: &XdsService) -> Result<Self, Self::Error> {
+        let mut vips = Vec::new();
+        for addr in &resource.addresses {
+            vips.push(NetworkAddress {
+                network: addr.network.clone(),
+                address: byte_to_ip(&addr.address)?,
+            })
+        }
+        Ok(Service {
+            name: resource.name.clone(),
+            namespace: resource.namespace.clone(),
+            hostname: resource.hostname.clone(),
+            vips,
+            ports: resource
+                .ports
+                .iter()
+                .map(|p| {
+                    let port =
+                        |port: u32| u16::try_from(port).map_err(|_| WorkloadError::PortParse(port));
+                    Ok((port(p.service_port)?, port(p.target_port)?))
+                })
+                .collect::<Result<_, WorkloadError>>()?,
+        })
+    }
+}
+
 impl Workload {
+    /// network_addresses returns each address of the workload on its network.
+    pub fn network_addresses(&self) -> impl Iterator<Item = NetworkAddress> + '_ {
+        self.workload_ips.iter().map(|ip| NetworkAddress {
+            network: self.network.clone(),
+            address: *ip,
+        })
+    }
+    /// choose_workload_ip returns the address to dial the workload at for a connection addressed to
+    /// `target`, preferring an address of the same family. Workloads without an address of that
+    /// family are dialed at their first address.
+    pub fn choose_workload_ip(&self, target: IpAddr) -> Option<IpAddr> {
+        self.workload_ips
+            .iter()
+            .find(|ip| ip.is_ipv4() == target.is_ipv4())
+            .or_else(|| self.workload_ips.first())
+            .copied()
+    }
     pub fn identity(&self) -> Identity {
         Identity::Spiffe {
             trust_domain: self.trust_domain.to_string(),
@@ -131,11 +325,29 @@ impl Workload {
             service_account: self.service_account.clone(),
         }
     }
-    pub fn choose_waypoint_address(&self) -> Option<IpAddr> {
-        self.waypoint_addresses
-            .iter()
-            .choose(&mut rand::thread_rng())
-            .copied()
+    /// wants_proxy_protocol returns whether connections to the workload on `port` should start with
+    /// a PROXY protocol header.
+    pub fn wants_proxy_protocol(&self, port: u16) -> bool {
+        self.proxy_protocol
+            .as_ref()
+            .is_some_and(|p| p.ports.is_empty() || p.ports.contains(&port))
+    }
+    /// choose_waypoint_address picks a waypoint of the workload, preferring one of the same family as
+    /// `target`.
+    pub fn choose_waypoint_address(&self, target: IpAddr) -> Option<IpAddr> {
+        let same_family = || {
+            self.waypoint_addresses
+                .iter()
+                .filter(|ip| ip.is_ipv4() == target.is_ipv4())
+        };
+        if same_family().next().is_some() {
+            same_family().choose(&mut rand::thread_rng()).copied()
+        } else {
+            self.waypoint_addresses
+                .iter()
+                .choose(&mut rand::thread_rng())
+                .copied()
+        }
     }
 }
 
@@ -143,9 +355,9 @@ impl fmt::Display for Workload {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Workload{{{} at {} via {} ({:?})}}",
+            "Workload{{{} at {:?} via {} ({:?})}}",
             self.name,
-            self.workload_ip,
+            self.workload_ips,
             self.gateway_address
                 .map(|x| format!("{x}"))
                 .unwrap_or_else(|| "None".into()),
@@ -157,6 +369,8 @@ impl fmt::Display for Workload {
 #[derive(Debug, Hash, Eq, PartialEq, Clone, serde::Serialize)]
 pub struct Upstream {
     pub workload: Workload,
+    /// selected_workload_ip is the address of the workload the connection is sent to.
+    pub selected_workload_ip: IpAddr,
     pub port: u16,
 }
 
@@ -166,7 +380,7 @@ impl fmt::Display for Upstream {
             f,
             "Upstream{{{} at {}:{} via {} ({:?})}}",
             self.workload.name,
-            self.workload.workload_ip,
+            self.selected_workload_ip,
             self.port,
             self.workload
                 .gateway_address
@@ -200,10 +414,26 @@ impl TryFrom<&XdsWorkload> for Workload {
         for addr in &resource.waypoint_addresses {
             waypoint_addresses.push(byte_to_ip(addr)?)
         }
-        let address = byte_to_ip(&resource.address)?;
+        let mut workload_ips: Vec<IpAddr> = Vec::new();
+        for addr in &resource.addresses {
+            workload_ips.push(byte_to_ip(addr)?)
+        }
+        let Some(primary) = workload_ips.first() else {
+            return Err(WorkloadError::NoAddress);
+        };
+        let uid = if resource.uid.is_empty() {
+            NetworkAddress {
+                network: resource.network.clone(),
+                address: *primary,
+            }
+            .to_string()
+        } else {
+            resource.uid.clone()
+        };
         let workload_type = resource.workload_type().as_str_name().to_lowercase();
         Ok(Workload {
-            workload_ip: address,
+            uid,
+            workload_ips,
             waypoint_addresses,
             gateway_address: None,
 
@@ -230,6 +460,9 @@ impl TryFrom<&XdsWorkload> for Workload {
                 }
             },
             node: resource.node,
+            network: resource.network,
+            locality: resource.locality.map(Locality::from).unwrap_or_default(),
+            proxy_protocol: resource.proxy_protocol.map(ProxyProtocol::from),
 
             workload_name: resource.workload_name,
             workload_type,
@@ -242,6 +475,7 @@ impl TryFrom<&XdsWorkload> for Workload {
 
             native_hbone: resource.native_hbone,
             authorization_policies: resource.authorization_policies,
+            peer_authentication_policies: resource.peer_authentication_policies,
 
             cluster_id: {
                 let result = resource.cluster_id;
@@ -277,6 +511,26 @@ impl xds::Handler<XdsWorkload> for Arc<Mutex<WorkloadStore>> {
     }
 }
 
+impl xds::Handler<XdsService> for Arc<Mutex<WorkloadStore>> {
+    fn handle(&self, updates: Vec<XdsUpdate<XdsService>>) -> Result<(), Vec<RejectedConfig>> {
+        let mut wli = self.lock().unwrap();
+        let handle = |res: XdsUpdate<XdsService>| {
+            match res {
+                XdsUpdate::Update(w) => {
+                    info!("handling service update {}", w.name);
+                    wli.insert_xds_service(w.resource)?;
+                }
+                XdsUpdate::Remove(name) => {
+                    info!("handling service delete {}", name);
+                    wli.remove_service(name);
+                }
+            }
+            Ok(())
+        };
+        xds::handle_single_resource(updates, handle)
+    }
+}
+
 impl xds::Handler<XdsAuthorization> for Arc<Mutex<WorkloadStore>> {
     fn handle(&self, updates: Vec<XdsUpdate<XdsAuthorization>>) -> Result<(), Vec<RejectedConfig>> {
         let mut wli = self.lock().unwrap();
@@ -297,6 +551,29 @@ impl xds::Handler<XdsAuthorization> for Arc<Mutex<WorkloadStore>> {
     }
 }
 
+impl xds::Handler<XdsPeerAuthentication> for Arc<Mutex<WorkloadStore>> {
+    fn handle(
+        &self,
+        updates: Vec<XdsUpdate<XdsPeerAuthentication>>,
+    ) -> Result<(), Vec<RejectedConfig>> {
+        let mut wli = self.lock().unwrap();
+        let handle = |res: XdsUpdate<XdsPeerAuthentication>| {
+            match res {
+                XdsUpdate::Update(w) => {
+                    info!("handling peer authentication update {}", w.name);
+                    wli.insert_xds_peer_authentication(w.resource)?;
+                }
+                XdsUpdate::Remove(name) => {
+                    info!("handling peer authentication delete {}", name);
+                    wli.remove_peer_authentication(name);
+                }
+            }
+            Ok(())
+        };
+        xds::handle_single_resource(updates, handle)
+    }
+}
+
 impl WorkloadManager {
     pub async fn new(
         config: config::Config,
@@ -322,17 +599,26 @@ impl WorkloadManager {
             cert_tx: Some(tx),
             proxy_mode: config.proxy_mode.clone(),
             local_node: config.local_node.clone(),
+            local_network: config.network.clone(),
+            load_balancer: lb::LoadBalancer::new(&config),
+            outlier_detector: outlier::OutlierDetector::new(&config),
             ..Default::default()
         }));
         let xds_workloads = workloads.clone();
+        let xds_services = workloads.clone();
         let xds_rbac = workloads.clone();
+        let xds_peer_authn = workloads.clone();
         let xds_client = if config.xds_address.is_some() {
             Some(
                 xds::Config::new(config.clone())
                     .with_workload_handler(xds_workloads)
+                    .with_service_handler(xds_services)
                     .with_authorization_handler(xds_rbac)
+                    .with_peer_authentication_handler(xds_peer_authn)
                     .watch(xds::WORKLOAD_TYPE.into())
+                    .watch(xds::SERVICE_TYPE.into())
                     .watch(xds::AUTHORIZATION_TYPE.into())
+                    .watch(xds::PEER_AUTHENTICATION_TYPE.into())
                     .build(metrics, awaiting_ready),
             )
         } else {
@@ -386,7 +672,11 @@ pub struct LocalWorkload {
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct LocalConfig {
     pub workloads: Vec<LocalWorkload>,
+    #[serde(default)]
+    pub services: Vec<Service>,
     pub policies: Vec<Authorization>,
+    #[serde(default)]
+    pub peer_authentications: Vec<PeerAuthentication>,
 }
 
 impl LocalClient {
@@ -398,30 +688,52 @@ impl LocalClient {
         let r: LocalConfig = serde_yaml::from_str(&data)?;
         let mut wli = self.workloads.lock().unwrap();
         let workloads = r.workloads.len();
+        let services = r.services.len();
         let policies = r.policies.len();
+        let peer_authentications = r.peer_authentications.len();
         for wl in r.workloads {
-            let wip = wl.workload.workload_ip;
+            let mut workload = wl.workload;
+            if workload.uid.is_empty() {
+                let Some(addr) = workload.network_addresses().next() else {
+                    anyhow::bail!(
+                        "local workload {}/{} has no addresses",
+                        workload.namespace,
+                        workload.name
+                    );
+                };
+                workload.uid = addr.to_string();
+            }
+            let uid = workload.uid.clone();
             debug!(
-                "inserting local workloads {wip} ({}/{})",
-                &wl.workload.namespace, &wl.workload.name
+                "inserting local workloads {uid} ({}/{})",
+                &workload.namespace, &workload.name
             );
-            wli.insert_workload(wl.workload);
+            wli.insert_workload(workload);
             for (vip, ports) in wl.vips {
                 let ip = vip.parse::<IpAddr>()?;
                 for (service_port, target_port) in ports {
                     let addr = SocketAddr::from((ip, service_port));
-                    wli.vips.entry(addr).or_default().insert((wip, target_port));
+                    wli.vips
+                        .entry(addr)
+                        .or_default()
+                        .insert((uid.clone(), target_port));
                     wli.workload_to_vip
-                        .entry(wip)
+                        .entry(uid.clone())
                         .or_default()
                         .insert((addr, target_port));
                 }
             }
         }
+        for svc in r.services {
+            wli.insert_service(svc);
+        }
         for rbac in r.policies {
             wli.insert_authorization(rbac);
         }
-        info!(%workloads, %policies, "local config initialized");
+        for pa in r.peer_authentications {
+            wli.insert_peer_authentication(pa);
+        }
+        info!(%workloads, %services, %policies, %peer_authentications, "local config initialized");
         Ok(())
     }
 }
@@ -444,59 +756,55 @@ impl WorkloadInformation {
             debug!("destination workload not found");
             return false;
         };
+        self.info.lock().unwrap().assert_rbac(&wl, conn)
+    }
 
+    /// reassert_rbac checks an established connection again after the authorization policy
+    /// `changed` was inserted or removed. Connections to workloads the policy does not apply to are
+    /// not checked, unless the change is not known.
+    pub fn reassert_rbac(&self, conn: &rbac::Connection, changed: Option<&Authorization>) -> bool {
         let wli = self.info.lock().unwrap();
+        // A destination which has since gone away has nothing left to protect
+        let Some(wl) = wli.find_workload(&conn.dst.ip()) else {
+            return true;
+        };
+        if changed.is_some_and(|policy| !policy.applies_to(wl)) {
+            return true;
+        }
+        wli.assert_rbac(wl, conn)
+    }
 
-        // We can get policies from namespace, global, and workload...
-        let ns = wli
-            .policies_by_namespace
-            .get(&wl.namespace)
-            .into_iter()
-            .flatten();
-        let global = wli.policies_by_namespace.get("").into_iter().flatten();
-        let workload = wl.authorization_policies.iter();
-
-        // Aggregate all of them based on type
-        let (allow, deny): (Vec<_>, Vec<_>) = ns
-            .chain(global)
-            .chain(workload)
-            .filter_map(|k| wli.policies.get(k))
-            .partition(|p| p.action == rbac::RbacAction::Allow);
-
-        trace!(
-            allow = allow.len(),
-            deny = deny.len(),
-            "checking connection"
-        );
+    /// policy_changes returns a receiver of each authorization policy inserted or removed from now.
+    pub fn policy_changes(&self) -> broadcast::Receiver<Authorization> {
+        let mut wli = self.info.lock().unwrap();
+        wli.policy_tx
+            .get_or_insert_with(|| broadcast::channel(256).0)
+            .subscribe()
+    }
 
-        // Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
+    /// mtls_mode returns the mTLS mode in effect for `port` of `wl`.
+    pub fn mtls_mode(&self, wl: &Workload, port: u16) -> MtlsMode {
+        self.info.lock().unwrap().mtls_mode(wl, Some(port))
+    }
 
-        // "If there are any DENY policies that match the request, deny the request."
-        for pol in deny.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key(), "deny policy match");
-                return false;
-            } else {
-                trace!(policy = pol.to_key(), "deny policy does not match");
-            }
-        }
-        // "If there are no ALLOW policies for the workload, allow the request."
-        if allow.is_empty() {
-            debug!("no allow policies, allow");
-            return true;
-        }
-        // "If any of the ALLOW policies match the request, allow the request."
-        for pol in allow.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key(), "allow policy match");
-                return true;
-            } else {
-                trace!(policy = pol.to_key(), "allow policy does not match");
-            }
-        }
-        // "Deny the request."
-        debug!("no allow policies matched");
-        false
+    /// mtls_modes returns the mTLS modes in effect for each workload, keyed by uid.
+    pub fn mtls_modes(&self) -> BTreeMap<String, WorkloadMtls> {
+        let wli = self.info.lock().unwrap();
+        wli.workloads
+            .values()
+            .map(|wl| {
+                let ports = wli
+                    .peer_authentications_for(wl)
+                    .flat_map(|pa| pa.port_level_mtls.keys())
+                    .map(|port| (*port, wli.mtls_mode(wl, Some(*port))))
+                    .collect();
+                let modes = WorkloadMtls {
+                    mode: wli.mtls_mode(wl, None),
+                    ports,
+                };
+                (wl.uid.clone(), modes)
+            })
+            .collect()
     }
 
     // only support workload
@@ -512,10 +820,69 @@ impl WorkloadInformation {
         }
     }
 
-    pub async fn find_upstream(&self, addr: SocketAddr, hbone_port: u16) -> Option<Upstream> {
+    /// find_upstream finds the upstream for a connection from `source` to `addr`. If `addr` is a VIP,
+    /// endpoints in `excluded` will not be selected.
+    pub async fn find_upstream(
+        &self,
+        addr: SocketAddr,
+        source: IpAddr,
+        hbone_port: u16,
+        excluded: &[String],
+    ) -> Option<Upstream> {
         self.fetch_address(&addr).await;
+        let mut wi = self.info.lock().unwrap();
+        wi.find_upstream(addr, source, hbone_port, excluded)
+    }
+
+    /// record_outcome records whether a connection to the workload `uid` succeeded, for outlier
+    /// detection.
+    pub fn record_outcome(&self, uid: String, success: bool) {
+        let mut wi = self.info.lock().unwrap();
+        if success {
+            wi.outlier_detector.record_success(uid);
+        } else {
+            wi.outlier_detector.record_failure(uid);
+        }
+    }
+
+    /// find_service returns the service that `addr` is a VIP of, if known.
+    pub fn find_service(&self, addr: &SocketAddr) -> Option<Service> {
         let wi = self.info.lock().unwrap();
-        wi.find_upstream(addr, hbone_port)
+        wi.find_service(&addr.ip()).cloned()
+    }
+
+    /// resolve_hostname finds the address of the service or workload named `host` on the local
+    /// network. Services are matched by hostname, and workloads by `<name>.<namespace>`.
+    pub fn resolve_hostname(&self, host: &str) -> Option<IpAddr> {
+        let wi = self.info.lock().unwrap();
+        wi.resolve_hostname(host)
+    }
+
+    /// find_workload_by_identity finds a workload on the local network with `identity`, preferring
+    /// workloads on our own node.
+    pub fn find_workload_by_identity(&self, identity: &Identity) -> Option<Workload> {
+        let wi = self.info.lock().unwrap();
+        wi.find_workload_by_identity(identity).cloned()
+    }
+
+    /// find_destination_service returns the service a connection to `wl` on `port` was most likely
+    /// sent to. Connections to a workload no longer carry the VIP they were sent to, so this is only
+    /// known if `wl` is an endpoint of a single service on `port`.
+    pub fn find_destination_service(&self, wl: &Workload, port: u16) -> Option<Service> {
+        let wi = self.info.lock().unwrap();
+        wi.find_destination_service(wl, port).cloned()
+    }
+
+    /// is_vip returns true if `addr` is a known service VIP.
+    pub fn is_vip(&self, addr: &SocketAddr) -> bool {
+        self.workload_by_vip_exist(addr)
+    }
+
+    /// track_connection records an active connection to the workload `uid`, for load balancing,
+    /// until the returned guard is dropped.
+    pub fn track_connection(&self, uid: String) -> lb::ActiveConnectionGuard {
+        let wi = self.info.lock().unwrap();
+        wi.load_balancer.active_connections().track(uid)
     }
 
     // Support workload and VIP
@@ -542,6 +909,12 @@ impl WorkloadInformation {
         }
     }
 
+    /// find_native_hbone_workload returns the workload at `addr` if it natively speaks HBONE. Only
+    /// workloads already known are found, so this never waits for an on-demand update.
+    pub fn find_native_hbone_workload(&self, addr: &IpAddr) -> Option<Workload> {
+        self.find_workload(addr).filter(|wl| wl.native_hbone)
+    }
+
     // keep private so that we can ensure that we always use fetch_workload
     fn find_workload(&self, addr: &IpAddr) -> Option<Workload> {
         let wi = self.info.lock().unwrap();
@@ -555,27 +928,63 @@ impl WorkloadInformation {
     }
 }
 
+/// WorkloadMtls describes the mTLS modes in effect for a workload.
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkloadMtls {
+    /// mode applies to ports without a mode of their own.
+    pub mode: MtlsMode,
+    /// ports holds the modes of ports that policies set a mode for.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ports: BTreeMap<u16, MtlsMode>,
+}
+
 /// A WorkloadStore encapsulates all information about workloads in the mesh
 #[derive(serde::Serialize, Default, Debug)]
 pub struct WorkloadStore {
-    workloads: HashMap<IpAddr, Workload>,
-    /// workload_to_vip maintains a mapping of workload IP to VIP. This is used only to handle removals.
-    workload_to_vip: HashMap<IpAddr, HashSet<(SocketAddr, u16)>>,
-    /// vips maintains a mapping of socket address with service port to workload ip and socket address
-    /// with target ports in hashset.
-    vips: HashMap<SocketAddr, HashSet<(IpAddr, u16)>>,
+    /// workloads maintains a mapping of workload uid to workload.
+    workloads: HashMap<String, Workload>,
+    /// workloads_by_addr maintains a mapping of each address of a workload to its uid.
+    #[serde(skip_serializing)]
+    workloads_by_addr: HashMap<NetworkAddress, String>,
+    /// workload_to_vip maintains a mapping of workload uid to VIP. This is used only to handle removals.
+    workload_to_vip: HashMap<String, HashSet<(SocketAddr, u16)>>,
+    /// vips maintains a mapping of socket address with service port to workload uid and target
+    /// ports in hashset. Endpoints may be on any network.
+    vips: HashMap<SocketAddr, HashSet<(String, u16)>>,
+
+    /// services maintains a mapping of service resource name (namespace/hostname) to service.
+    services: HashMap<String, Service>,
+    /// services_by_vip maintains a mapping of VIP to the resource name of its service.
+    services_by_vip: HashMap<NetworkAddress, String>,
 
     /// policies maintains a mapping of ns/name to policy.
     policies: HashMap<String, rbac::Authorization>,
     // policies_by_namespace maintains a mapping of namespace (or "" for global) to policy names
     policies_by_namespace: HashMap<String, HashSet<String>>,
 
+    /// peer_authentications maintains a mapping of ns/name to peer authentication policy.
+    peer_authentications: HashMap<String, PeerAuthentication>,
+    // peer_authentications_by_namespace maintains a mapping of namespace (or "" for global) to
+    // policy names. These are ordered, so the same policy wins if several set a mode.
+    peer_authentications_by_namespace: HashMap<String, BTreeSet<String>>,
+
     #[serde(skip_serializing, default)]
     cert_tx: Option<mpsc::Sender<Identity>>,
+    /// policy_tx is sent each authorization policy inserted or removed, once subscribed to.
+    #[serde(skip_serializing, default)]
+    policy_tx: Option<broadcast::Sender<Authorization>>,
 
     // needed to determine whether or not to prefetch certs
     proxy_mode: ProxyMode,
     local_node: Option<String>,
+    /// local_network is the network this ztunnel runs on. Addresses without a network, such as
+    /// those of connections we receive, are on this network.
+    local_network: String,
+
+    /// load_balancer picks the upstream for connections to VIPs.
+    load_balancer: lb::LoadBalancer,
+    /// outlier_detector tracks failing endpoints, which are ejected from load balancing.
+    outlier_detector: outlier::OutlierDetector,
 }
 
 impl WorkloadStore {
@@ -590,10 +999,10 @@ impl WorkloadStore {
 
     fn insert_xds_workload(&mut self, w: XdsWorkload) -> anyhow::Result<()> {
         let workload = Workload::try_from(&w)?;
-        let wip = workload.workload_ip;
+        let uid = workload.uid.clone();
         // First, remove the entry entirely to make sure things are cleaned up properly. Note this is
         // under a lock, so there is no race here.
-        self.remove(wip.to_string());
+        self.remove(uid.clone());
         let widentity = workload.identity();
         let status = workload.status;
         self.insert_workload(workload);
@@ -607,9 +1016,9 @@ impl WorkloadStore {
                     self.vips
                         .entry(service_sock_addr)
                         .or_default()
-                        .insert((wip, port.target_port as u16));
+                        .insert((uid.clone(), port.target_port as u16));
                     self.workload_to_vip
-                        .entry(wip)
+                        .entry(uid.clone())
                         .or_default()
                         .insert((service_sock_addr, port.target_port as u16));
                 }
@@ -626,6 +1035,88 @@ impl WorkloadStore {
         Ok(())
     }
 
+    fn insert_xds_service(&mut self, s: XdsService) -> anyhow::Result<()> {
+        let svc = Service::try_from(&s)?;
+        self.insert_service(svc);
+        Ok(())
+    }
+
+    fn insert_service(&mut self, svc: Service) {
+        let name = svc.resource_name();
+        // Remove the previous version first, in case its VIPs changed
+        self.remove_service(name.clone());
+        for vip in &svc.vips {
+            self.services_by_vip.insert(vip.clone(), name.clone());
+        }
+        self.services.insert(name, svc);
+    }
+
+    fn remove_service(&mut self, name: String) {
+        let Some(svc) = self.services.remove(&name) else {
+            return;
+        };
+        for vip in svc.vips {
+            // The VIP may have since moved to another service
+            if self.services_by_vip.get(&vip) == Some(&name) {
+                self.services_by_vip.remove(&vip);
+            }
+        }
+    }
+
+    /// assert_rbac evaluates the authorization policies that apply to `wl` against `conn`.
+    fn assert_rbac(&self, wl: &Workload, conn: &rbac::Connection) -> bool {
+        // We can get policies from namespace, global, and workload...
+        let ns = self
+            .policies_by_namespace
+            .get(&wl.namespace)
+            .into_iter()
+            .flatten();
+        let global = self.policies_by_namespace.get("").into_iter().flatten();
+        let workload = wl.authorization_policies.iter();
+
+        // Aggregate all of them based on type
+        let (allow, deny): (Vec<_>, Vec<_>) = ns
+            .chain(global)
+            .chain(workload)
+            .filter_map(|k| self.policies.get(k))
+            .partition(|p| p.action == rbac::RbacAction::Allow);
+
+        trace!(
+            allow = allow.len(),
+            deny = deny.len(),
+            "checking connection"
+        );
+
+        // Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
+
+        // "If there are any DENY policies that match the request, deny the request."
+        for pol in deny.iter() {
+            if pol.matches(conn) {
+                debug!(policy = pol.to_key(), "deny policy match");
+                return false;
+            } else {
+                trace!(policy = pol.to_key(), "deny policy does not match");
+            }
+        }
+        // "If there are no ALLOW policies for the workload, allow the request."
+        if allow.is_empty() {
+            debug!("no allow policies, allow");
+            return true;
+        }
+        // "If any of the ALLOW policies match the request, allow the request."
+        for pol in allow.iter() {
+            if pol.matches(conn) {
+                debug!(policy = pol.to_key(), "allow policy match");
+                return true;
+            } else {
+                trace!(policy = pol.to_key(), "allow policy does not match");
+            }
+        }
+        // "Deny the request."
+        debug!("no allow policies matched");
+        false
+    }
+
     fn insert_xds_authorization(&mut self, r: XdsAuthorization) -> anyhow::Result<()> {
         let rbac = rbac::Authorization::try_from(&r)?;
         trace!("insert policy {}", serde_json::to_string(&rbac)?);
@@ -650,6 +1141,7 @@ impl WorkloadStore {
             }
             RbacScope::WorkloadSelector => {}
         }
+        self.policy_changed(&rbac);
         self.policies.insert(key, rbac);
     }
 
@@ -657,6 +1149,7 @@ impl WorkloadStore {
         let Some(rbac) = self.policies.remove(&name) else {
             return;
         };
+        self.policy_changed(&rbac);
         if let Some(key) = match rbac.scope {
             RbacScope::Global => Some("".to_string()),
             RbacScope::Namespace => Some(rbac.namespace),
@@ -671,58 +1164,234 @@ impl WorkloadStore {
         }
     }
 
-    fn insert_workload(&mut self, w: Workload) {
-        let wip = w.workload_ip;
-        self.workloads.insert(wip, w);
+    /// policy_changed notifies subscribers, such as the proxy re-checking its connections, that
+    /// `rbac` was inserted or removed.
+    fn policy_changed(&self, rbac: &Authorization) {
+        if let Some(tx) = &self.policy_tx {
+            // There may be no subscribers
+            let _ = tx.send(rbac.clone());
+        }
     }
 
-    fn remove(&mut self, ip: String) {
-        use std::str::FromStr;
-        let ip: IpAddr = match IpAddr::from_str(&ip) {
-            Err(e) => {
-                error!("received invalid resource removal {}, ignoring: {}", ip, e);
-                return;
+    fn insert_xds_peer_authentication(&mut self, r: XdsPeerAuthentication) -> anyhow::Result<()> {
+        let pa = PeerAuthentication::try_from(&r)?;
+        trace!("insert peer authentication {}", serde_json::to_string(&pa)?);
+        self.insert_peer_authentication(pa);
+        Ok(())
+    }
+
+    fn insert_peer_authentication(&mut self, pa: PeerAuthentication) {
+        let key = pa.to_key();
+        // Remove the previous version first, in case its scope changed
+        self.remove_peer_authentication(key.clone());
+        let ns = match pa.scope {
+            RbacScope::Global => Some(String::new()),
+            RbacScope::Namespace => Some(pa.namespace.clone()),
+            RbacScope::WorkloadSelector => None,
+        };
+        if let Some(ns) = ns {
+            self.peer_authentications_by_namespace
+                .entry(ns)
+                .or_default()
+                .insert(key.clone());
+        }
+        self.peer_authentications.insert(key, pa);
+    }
+
+    fn remove_peer_authentication(&mut self, name: String) {
+        let Some(pa) = self.peer_authentications.remove(&name) else {
+            return;
+        };
+        let ns = match pa.scope {
+            RbacScope::Global => String::new(),
+            RbacScope::Namespace => pa.namespace,
+            RbacScope::WorkloadSelector => return,
+        };
+        if let Some(names) = self.peer_authentications_by_namespace.get_mut(&ns) {
+            names.remove(&name);
+            if names.is_empty() {
+                self.peer_authentications_by_namespace.remove(&ns);
             }
-            Ok(i) => i,
+        }
+    }
+
+    // peer_authentications_for returns the peer authentication policies that apply to `wl`, from
+    // the most to the least specific.
+    fn peer_authentications_for<'a>(
+        &'a self,
+        wl: &'a Workload,
+    ) -> impl Iterator<Item = &'a PeerAuthentication> + 'a {
+        let workload = wl.peer_authentication_policies.iter();
+        let ns = self
+            .peer_authentications_by_namespace
+            .get(&wl.namespace)
+            .into_iter()
+            .flatten();
+        let global = self
+            .peer_authentications_by_namespace
+            .get("")
+            .into_iter()
+            .flatten();
+        workload
+            .chain(ns)
+            .chain(global)
+            .filter_map(|k| self.peer_authentications.get(k))
+    }
+
+    // mtls_mode returns the mode in effect for `port` of `wl`, or for its ports without a mode of
+    // their own if `port` is None. The most specific policy that sets a mode decides it.
+    fn mtls_mode(&self, wl: &Workload, port: Option<u16>) -> MtlsMode {
+        self.peer_authentications_for(wl)
+            .find_map(|pa| pa.mode_for(port))
+            .unwrap_or_default()
+    }
+
+    fn insert_workload(&mut self, w: Workload) {
+        for addr in w.network_addresses() {
+            self.workloads_by_addr.insert(addr, w.uid.clone());
+        }
+        self.workloads.insert(w.uid.clone(), w);
+    }
+
+    /// remove removes the workload `uid`, along with every address and VIP it is known by.
+    fn remove(&mut self, uid: String) {
+        self.outlier_detector.remove(&uid);
+        let Some(prev) = self.workloads.remove(&uid) else {
+            return;
         };
-        if let Some(prev) = self.workloads.remove(&ip) {
-            if let Some(vips) = self.workload_to_vip.remove(&prev.workload_ip) {
-                for (vip, target_port) in vips {
-                    if let Some(wls) = self.vips.get_mut(&vip) {
-                        let vip_hash_entry = (prev.workload_ip, target_port);
-                        wls.remove(&vip_hash_entry);
-                        if wls.is_empty() {
-                            self.vips.remove(&vip);
-                        }
+        for addr in prev.network_addresses() {
+            // The address may have since been reused by another workload
+            if self.workloads_by_addr.get(&addr) == Some(&uid) {
+                self.workloads_by_addr.remove(&addr);
+            }
+        }
+        if let Some(vips) = self.workload_to_vip.remove(&uid) {
+            for (vip, target_port) in vips {
+                if let Some(wls) = self.vips.get_mut(&vip) {
+                    wls.remove(&(uid.clone(), target_port));
+                    if wls.is_empty() {
+                        self.vips.remove(&vip);
                     }
                 }
             }
         }
     }
 
+    /// find_workload finds the workload with the address `addr` on the local network.
     fn find_workload(&self, addr: &IpAddr) -> Option<&Workload> {
-        self.workloads.get(addr)
+        let uid = self.workloads_by_addr.get(&self.local_address(*addr))?;
+        self.workloads.get(uid)
     }
 
-    fn find_upstream(&self, addr: SocketAddr, hbone_port: u16) -> Option<Upstream> {
+    /// find_service finds the service with the VIP `vip` on the local network.
+    fn find_service(&self, vip: &IpAddr) -> Option<&Service> {
+        let name = self.services_by_vip.get(&self.local_address(*vip))?;
+        self.services.get(name)
+    }
+
+    fn resolve_hostname(&self, host: &str) -> Option<IpAddr> {
+        // Accept fully qualified names
+        let host = host.strip_suffix('.').unwrap_or(host);
+        let vip = self
+            .services
+            .values()
+            .filter(|svc| svc.hostname == host)
+            .flat_map(|svc| svc.vips.iter())
+            .find(|vip| vip.network == self.local_network);
+        if let Some(vip) = vip {
+            return Some(vip.address);
+        }
+        let (name, namespace) = host.rsplit_once('.')?;
+        self.workloads
+            .values()
+            .find(|wl| {
+                wl.network == self.local_network && wl.name == name && wl.namespace == namespace
+            })
+            .and_then(|wl| wl.workload_ips.first().copied())
+    }
+
+    fn find_workload_by_identity(&self, identity: &Identity) -> Option<&Workload> {
+        self.workloads
+            .values()
+            .filter(|wl| wl.network == self.local_network && &wl.identity() == identity)
+            .max_by_key(|wl| Some(&wl.node) == self.local_node.as_ref())
+    }
+
+    fn find_destination_service(&self, wl: &Workload, port: u16) -> Option<&Service> {
+        let mut names = self
+            .workload_to_vip
+            .get(&wl.uid)?
+            .iter()
+            .filter(|(_, target_port)| *target_port == port)
+            .filter_map(|(vip, _)| self.services_by_vip.get(&self.local_address(vip.ip())));
+        let name = names.next()?;
+        if names.any(|n| n != name) {
+            // The workload serves multiple services on this port, so we cannot tell which was used
+            return None;
+        }
+        self.services.get(name)
+    }
+
+    fn local_address(&self, address: IpAddr) -> NetworkAddress {
+        NetworkAddress {
+            network: self.local_network.clone(),
+            address,
+        }
+    }
+
+    fn find_upstream(
+        &mut self,
+        addr: SocketAddr,
+        source: IpAddr,
+        hbone_port: u16,
+        excluded: &[String],
+    ) -> Option<Upstream> {
         if let Some(wl_vips) = self.vips.get(&addr) {
-            // Randomly pick an upstream
-            // TODO: do this more efficiently, and not just randomly
-            let (workload_ip, target_port) =
-                wl_vips.iter().choose(&mut rand::thread_rng()).unwrap();
-            if let Some(wl) = self.workloads.get(workload_ip) {
+            let remaining;
+            let wl_vips = if excluded.is_empty() {
+                wl_vips
+            } else {
+                remaining = wl_vips
+                    .iter()
+                    .filter(|(ep, _)| !excluded.contains(ep))
+                    .cloned()
+                    .collect();
+                &remaining
+            };
+            let healthy;
+            let wl_vips = match self.outlier_detector.filter_ejected(wl_vips) {
+                Some(h) => {
+                    healthy = h;
+                    &healthy
+                }
+                None => wl_vips,
+            };
+            // Only consider the endpoints closest to the source, if we know where it is
+            let nearby;
+            let endpoints = match self.find_workload(&source) {
+                Some(src) => {
+                    nearby = lb::closest(src, wl_vips, &self.workloads);
+                    &nearby
+                }
+                None => wl_vips,
+            };
+            let (uid, target_port) = self.load_balancer.pick(addr, source, endpoints)?;
+            if let Some(wl) = self.workloads.get(&uid) {
                 let mut us = Upstream {
                     workload: wl.to_owned(),
-                    port: *target_port,
+                    // Dial the endpoint with the same family as the VIP, if it has one
+                    selected_workload_ip: wl.choose_workload_ip(addr.ip())?,
+                    port: target_port,
                 };
                 Self::set_gateway_address(&mut us, hbone_port);
                 debug!("found upstream from VIP: {}", us);
                 return Some(us);
             }
         }
-        if let Some(wl) = self.workloads.get(&addr.ip()) {
+        if let Some(wl) = self.find_workload(&addr.ip()) {
             let mut us = Upstream {
                 workload: wl.to_owned(),
+                selected_workload_ip: addr.ip(),
                 port: addr.port(),
             };
             Self::set_gateway_address(&mut us, hbone_port);
@@ -736,13 +1405,17 @@ impl WorkloadStore {
         if us.workload.gateway_address.is_none() {
             us.workload.gateway_address = Some(match us.workload.protocol {
                 Protocol::HBONE => {
-                    let ip = us
-                        .workload
-                        .choose_waypoint_address()
-                        .unwrap_or(us.workload.workload_ip);
-                    SocketAddr::from((ip, hbone_port))
+                    match us.workload.choose_waypoint_address(us.selected_workload_ip) {
+                        Some(ip) => SocketAddr::from((ip, hbone_port)),
+                        // Native HBONE workloads serve HBONE themselves on the standard port, rather
+                        // than through the ztunnel on their node
+                        None if us.workload.native_hbone => {
+                            SocketAddr::from((us.selected_workload_ip, config::HBONE_PORT))
+                        }
+                        None => SocketAddr::from((us.selected_workload_ip, hbone_port)),
+                    }
                 }
-                Protocol::TCP => SocketAddr::from((us.workload.workload_ip, us.port)),
+                Protocol::TCP => SocketAddr::from((us.selected_workload_ip, us.port)),
             });
         }
     }
@@ -755,10 +1428,14 @@ pub enum WorkloadError {
     AddressParse(#[from] net::AddrParseError),
     #[error("failed to parse address, had {0} bytes")]
     ByteAddressParse(usize),
+    #[error("workload has no addresses")]
+    NoAddress,
     #[error("invalid cidr: {0}")]
     PrefixParse(#[from] ipnet::PrefixLenError),
     #[error("unknown enum: {0}")]
     EnumParse(String),
+    #[error("invalid port: {0}")]
+    PortParse(u32),
 }
 
 #[cfg(test)]
@@ -767,6 +1444,7 @@ mod tests {
     use std::net::{Ipv4Addr, Ipv6Addr};
 
     use bytes::Bytes;
+    use test_case::test_case;
 
     use crate::test_helpers;
     use crate::test_helpers::helpers::initialize_telemetry;
@@ -776,6 +1454,15 @@ mod tests {
 
     use super::*;
 
+    #[test_case("workloadIp: 10.0.0.1", &["10.0.0.1"]; "single address")]
+    #[test_case("workloadIps: [10.0.0.1, \"ff06::c3\"]", &["10.0.0.1", "ff06::c3"]; "addresses")]
+    fn local_config_workload_ips(field: &str, expected: &[&str]) {
+        let yaml = format!("workloads:\n- name: local\n  {field}\n  vips: {{}}\npolicies: []\n");
+        let lc: LocalConfig = serde_yaml::from_str(&yaml).unwrap();
+        let expected: Vec<IpAddr> = expected.iter().map(|ip| ip.parse().unwrap()).collect();
+        assert_eq!(lc.workloads[0].workload.workload_ips, expected);
+    }
+
     #[test]
     fn byte_to_ipaddr_garbage() {
         let garbage = "not_an_ip";
@@ -866,7 +1553,7 @@ mod tests {
         let xds_ip2 = Bytes::copy_from_slice(&[127, 0, 0, 2]);
 
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip1.clone(),
+            addresses: vec![xds_ip1.clone()],
             name: "some name".to_string(),
             ..Default::default()
         })
@@ -875,7 +1562,8 @@ mod tests {
         assert_eq!(
             wi.find_workload(&ip1),
             Some(&Workload {
-                workload_ip: ip1,
+                uid: "127.0.0.1".to_string(),
+                workload_ips: vec![ip1],
                 name: "some name".to_string(),
                 ..test_helpers::test_default_workload()
             })
@@ -885,7 +1573,8 @@ mod tests {
         assert_eq!(
             wi.find_workload(&ip1),
             Some(&Workload {
-                workload_ip: ip1,
+                uid: "127.0.0.1".to_string(),
+                workload_ips: vec![ip1],
                 name: "some name".to_string(),
                 ..test_helpers::test_default_workload()
             })
@@ -895,7 +1584,8 @@ mod tests {
         assert_eq!(
             wi.find_workload(&ip1),
             Some(&Workload {
-                workload_ip: ip1,
+                uid: "127.0.0.1".to_string(),
+                workload_ips: vec![ip1],
                 name: "some name".to_string(),
                 ..test_helpers::test_default_workload()
             })
@@ -917,54 +1607,54 @@ mod tests {
 
         // Add two workloads into the VIP
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip1.clone(),
+            addresses: vec![xds_ip1.clone()],
             name: "some name".to_string(),
             virtual_ips: vip.clone(),
             ..Default::default()
         })
         .unwrap();
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip2.clone(),
+            addresses: vec![xds_ip2.clone()],
             name: "some name2".to_string(),
             virtual_ips: vip.clone(),
             ..Default::default()
         })
         .unwrap();
 
-        assert_vips(&wi, vec!["some name", "some name2"]);
+        assert_vips(&mut wi, vec!["some name", "some name2"]);
         wi.remove("127.0.0.2".to_string());
-        assert_vips(&wi, vec!["some name"]);
+        assert_vips(&mut wi, vec!["some name"]);
         wi.remove("127.0.0.1".to_string());
-        assert_vips(&wi, vec![]);
+        assert_vips(&mut wi, vec![]);
 
         // Add 2 workload with VIP
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip1.clone(),
+            addresses: vec![xds_ip1.clone()],
             name: "some name".to_string(),
             virtual_ips: vip.clone(),
             ..Default::default()
         })
         .unwrap();
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip2.clone(),
+            addresses: vec![xds_ip2.clone()],
             name: "some name2".to_string(),
             virtual_ips: vip.clone(),
             ..Default::default()
         })
         .unwrap();
-        assert_vips(&wi, vec!["some name", "some name2"]);
+        assert_vips(&mut wi, vec!["some name", "some name2"]);
         // now update it without the VIP
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip1,
+            addresses: vec![xds_ip1],
             name: "some name".to_string(),
             ..Default::default()
         })
         .unwrap();
         // Should be remove
-        assert_vips(&wi, vec!["some name2"]);
+        assert_vips(&mut wi, vec!["some name2"]);
         // now update it without unhealthy
         wi.insert_xds_workload(XdsWorkload {
-            address: xds_ip2,
+            addresses: vec![xds_ip2],
             name: "some name2".to_string(),
             virtual_ips: vip,
             status: XdsStatus::Unhealthy as i32,
@@ -972,18 +1662,548 @@ mod tests {
         })
         .unwrap();
         // Should be removed
-        assert_vips(&wi, vec![]);
+        assert_vips(&mut wi, vec![]);
         assert_eq!(wi.vips.len(), 0);
     }
 
+    #[test]
+    fn dual_stack() {
+        let mut wi = WorkloadStore::default();
+        let port_list = XdsPortList {
+            ports: vec![XdsPort {
+                service_port: 80,
+                target_port: 8080,
+            }],
+        };
+        let v4: IpAddr = "127.0.0.1".parse().unwrap();
+        let v6: IpAddr = "fd00::1".parse().unwrap();
+        let workload = |addresses: &[IpAddr]| XdsWorkload {
+            uid: "cluster/pod".to_string(),
+            addresses: addresses
+                .iter()
+                .map(|ip| match ip {
+                    IpAddr::V4(ip) => Bytes::copy_from_slice(&ip.octets()),
+                    IpAddr::V6(ip) => Bytes::copy_from_slice(&ip.octets()),
+                })
+                .collect(),
+            name: "pod".to_string(),
+            virtual_ips: HashMap::from([
+                ("127.0.1.1".to_string(), port_list.clone()),
+                ("fd00::1:1".to_string(), port_list.clone()),
+            ]),
+            ..Default::default()
+        };
+        wi.insert_xds_workload(workload(&[v4, v6])).unwrap();
+        assert_eq!(wi.workloads.len(), 1);
+        assert_eq!(wi.find_workload(&v4).unwrap().uid, "cluster/pod");
+        assert_eq!(wi.find_workload(&v6).unwrap().uid, "cluster/pod");
+        // The workload is a single endpoint of each VIP, not one per address
+        for vip in ["127.0.1.1:80", "[fd00::1:1]:80"] {
+            assert_eq!(wi.vips[&vip.parse().unwrap()].len(), 1);
+        }
+
+        // Each VIP is served by the address of its own family
+        let source = "127.0.0.2".parse().unwrap();
+        let us = wi
+            .find_upstream("127.0.1.1:80".parse().unwrap(), source, 15008, &[])
+            .unwrap();
+        assert_eq!(us.selected_workload_ip, v4);
+        assert_eq!(us.workload.gateway_address, Some((v4, 8080).into()));
+        let us = wi
+            .find_upstream("[fd00::1:1]:80".parse().unwrap(), source, 15008, &[])
+            .unwrap();
+        assert_eq!(us.selected_workload_ip, v6);
+        assert_eq!(us.workload.gateway_address, Some((v6, 8080).into()));
+        // Connections to an address are sent to it
+        let us = wi
+            .find_upstream((v6, 8080).into(), source, 15008, &[])
+            .unwrap();
+        assert_eq!(us.selected_workload_ip, v6);
+
+        // Without an IPv6 address, the IPv6 VIP falls back to the IPv4 address
+        wi.insert_xds_workload(workload(&[v4])).unwrap();
+        assert_eq!(wi.find_workload(&v6), None);
+        let us = wi
+            .find_upstream("[fd00::1:1]:80".parse().unwrap(), source, 15008, &[])
+            .unwrap();
+        assert_eq!(us.selected_workload_ip, v4);
+
+        wi.remove("cluster/pod".to_string());
+        assert_eq!(wi.find_workload(&v4), None);
+        assert!(wi.workloads.is_empty());
+        assert!(wi.workloads_by_addr.is_empty());
+        assert!(wi.vips.is_empty());
+    }
+
+    #[test]
+    fn reused_address() {
+        let mut wi = WorkloadStore::default();
+        for uid in ["old", "new"] {
+            wi.insert_xds_workload(XdsWorkload {
+                uid: uid.to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+                name: uid.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        let ip = "127.0.0.1".parse().unwrap();
+        assert_eq!(wi.find_workload(&ip).unwrap().name, "new");
+        // Removing the previous owner of the address leaves the new one in place
+        wi.remove("old".to_string());
+        assert_eq!(wi.find_workload(&ip).unwrap().name, "new");
+        wi.remove("new".to_string());
+        assert_eq!(wi.find_workload(&ip), None);
+    }
+
+    #[test]
+    fn workload_without_address() {
+        let mut wi = WorkloadStore::default();
+        let err = wi
+            .insert_xds_workload(XdsWorkload {
+                name: "pod".to_string(),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<WorkloadError>().unwrap(),
+            WorkloadError::NoAddress
+        );
+    }
+
+    #[test]
+    fn mtls_mode() {
+        let mut wi = WorkloadStore::default();
+        for (ip, namespace, policies) in [
+            (1, "ns", vec!["ns/selected".to_string()]),
+            (2, "ns", vec![]),
+            (3, "other", vec![]),
+        ] {
+            wi.insert_xds_workload(XdsWorkload {
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+                namespace: namespace.to_string(),
+                peer_authentication_policies: policies,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        let policy = |name: &str, namespace: &str, scope, mode, ports: &[(u16, MtlsMode)]| {
+            PeerAuthentication {
+                name: name.to_string(),
+                namespace: namespace.to_string(),
+                scope,
+                mode,
+                port_level_mtls: ports.iter().copied().collect(),
+            }
+        };
+        wi.insert_peer_authentication(policy(
+            "mesh",
+            "istio-system",
+            RbacScope::Global,
+            Some(MtlsMode::Strict),
+            &[],
+        ));
+        // The namespace leaves the mode to the mesh, except for one port
+        wi.insert_peer_authentication(policy(
+            "ns",
+            "ns",
+            RbacScope::Namespace,
+            None,
+            &[(8080, MtlsMode::Permissive)],
+        ));
+        wi.insert_peer_authentication(policy(
+            "selected",
+            "ns",
+            RbacScope::WorkloadSelector,
+            Some(MtlsMode::Disable),
+            &[],
+        ));
+        let mode = |wi: &WorkloadStore, ip: u8, port: u16| {
+            let wl = wi.find_workload(&IpAddr::from([127, 0, 0, ip])).unwrap();
+            wi.mtls_mode(wl, Some(port))
+        };
+        // The most specific policy that sets a mode decides it
+        assert_eq!(mode(&wi, 1, 80), MtlsMode::Disable);
+        assert_eq!(mode(&wi, 1, 8080), MtlsMode::Disable);
+        assert_eq!(mode(&wi, 2, 80), MtlsMode::Strict);
+        assert_eq!(mode(&wi, 2, 8080), MtlsMode::Permissive);
+        assert_eq!(mode(&wi, 3, 8080), MtlsMode::Strict);
+
+        wi.remove_peer_authentication("istio-system/mesh".to_string());
+        assert_eq!(mode(&wi, 2, 80), MtlsMode::Permissive);
+        assert_eq!(mode(&wi, 3, 80), MtlsMode::Permissive);
+        assert!(!wi.peer_authentications_by_namespace.contains_key(""));
+    }
+
+    #[test]
+    fn reassert_rbac() {
+        let mut wi = WorkloadStore::default();
+        for (ip, namespace) in [(1, "ns"), (2, "other")] {
+            wi.insert_xds_workload(XdsWorkload {
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+                namespace: namespace.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        let wi = WorkloadInformation {
+            info: Arc::new(Mutex::new(wi)),
+            demand: None,
+        };
+        let mut changes = wi.policy_changes();
+        let deny = Authorization {
+            name: "deny".to_string(),
+            namespace: "ns".to_string(),
+            scope: RbacScope::Namespace,
+            action: rbac::RbacAction::Deny,
+            groups: vec![vec![vec![rbac::RbacMatch {
+                destination_ports: vec![80],
+                ..Default::default()
+            }]]],
+        };
+        let conn = |ip: u8| rbac::Connection {
+            src_identity: None,
+            src_ip: IpAddr::from([127, 0, 0, 3]),
+            dst: SocketAddr::from(([127, 0, 0, ip], 80)),
+        };
+
+        wi.info.lock().unwrap().insert_authorization(deny.clone());
+        assert_eq!(changes.try_recv().unwrap(), deny);
+        assert!(!wi.reassert_rbac(&conn(1), Some(&deny)));
+        // The policy does not apply to the other namespace, even if it would match
+        assert!(wi.reassert_rbac(&conn(2), Some(&deny)));
+        assert!(!wi.reassert_rbac(&conn(1), None));
+        assert!(wi.reassert_rbac(&conn(9), None));
+
+        wi.info.lock().unwrap().remove_rbac(deny.to_key());
+        assert_eq!(changes.try_recv().unwrap(), deny);
+        assert!(wi.reassert_rbac(&conn(1), Some(&deny)));
+        // Removing an unknown policy changes nothing
+        wi.info.lock().unwrap().remove_rbac(deny.to_key());
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn locality_vips() {
+        let mut wi = WorkloadStore::default();
+        let vip = HashMap::from([(
+            "127.0.1.1".to_string(),
+            XdsPortList {
+                ports: vec![XdsPort {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+            },
+        )]);
+        let locality = |zone: &str| {
+            Some(xds::istio::workload::Locality {
+                region: "region".to_string(),
+                zone: zone.to_string(),
+                subzone: "".to_string(),
+            })
+        };
+        wi.insert_xds_workload(XdsWorkload {
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+            name: "source".to_string(),
+            locality: locality("zone-a"),
+            ..Default::default()
+        })
+        .unwrap();
+        for (ip, name, zone) in [(2, "same zone", "zone-a"), (3, "other zone", "zone-b")] {
+            wi.insert_xds_workload(XdsWorkload {
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+                name: name.to_string(),
+                virtual_ips: vip.clone(),
+                locality: locality(zone),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        assert_eq!(
+            wi.find_workload(&"127.0.0.2".parse().unwrap())
+                .unwrap()
+                .locality
+                .zone,
+            "zone-a"
+        );
+
+        let source = "127.0.0.1".parse().unwrap();
+        let vip_addr = "127.0.1.1:80".parse().unwrap();
+        for _ in 0..100 {
+            let us = wi.find_upstream(vip_addr, source, 15008, &[]).unwrap();
+            assert_eq!(us.workload.name, "same zone");
+        }
+        // Excluded endpoints are skipped, even if they are closer
+        let us = wi
+            .find_upstream(vip_addr, source, 15008, &["127.0.0.2".to_string()])
+            .unwrap();
+        assert_eq!(us.workload.name, "other zone");
+        let excluded = ["127.0.0.2".to_string(), "127.0.0.3".to_string()];
+        assert!(wi
+            .find_upstream(vip_addr, source, 15008, &excluded)
+            .is_none());
+        // Once the local endpoint is gone we should fail over to the other zone
+        wi.remove("127.0.0.2".to_string());
+        let us = wi.find_upstream(vip_addr, source, 15008, &[]).unwrap();
+        assert_eq!(us.workload.name, "other zone");
+    }
+
+    #[test]
+    fn outlier_vips() {
+        let cfg = config::Config {
+            // Allow ejecting one of the two endpoints
+            outlier_max_ejection_percent: 50,
+            ..test_helpers::test_config()
+        };
+        let mut wi = WorkloadStore {
+            outlier_detector: outlier::OutlierDetector::new(&cfg),
+            ..Default::default()
+        };
+        let vip = HashMap::from([(
+            "127.0.1.1".to_string(),
+            XdsPortList {
+                ports: vec![XdsPort {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+            },
+        )]);
+        for (ip, name) in [(2, "healthy"), (3, "failing")] {
+            wi.insert_xds_workload(XdsWorkload {
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+                name: name.to_string(),
+                virtual_ips: vip.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        let failing = "127.0.0.3".to_string();
+        for _ in 0..cfg.outlier_consecutive_failures {
+            wi.outlier_detector.record_failure(failing.clone());
+        }
+        let vip_addr = "127.0.1.1:80".parse().unwrap();
+        let source = "127.0.0.1".parse().unwrap();
+        for _ in 0..100 {
+            let us = wi.find_upstream(vip_addr, source, 15008, &[]).unwrap();
+            assert_eq!(us.workload.name, "healthy");
+        }
+        let dump = serde_json::to_value(&wi).unwrap();
+        assert_eq!(
+            dump["outlier_detector"]["endpoints"]["127.0.0.3"]["ejected"],
+            true
+        );
+        // Removing the workload clears its state
+        wi.remove("127.0.0.3".to_string());
+        let dump = serde_json::to_value(&wi).unwrap();
+        assert!(dump["outlier_detector"]["endpoints"]
+            .as_object()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn overlapping_networks() {
+        let mut wi = WorkloadStore {
+            local_network: "network1".to_string(),
+            ..Default::default()
+        };
+        let vip = HashMap::from([(
+            "127.0.1.1".to_string(),
+            XdsPortList {
+                ports: vec![XdsPort {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+            },
+        )]);
+        // The same IP is in use on both networks
+        for network in ["network1", "network2"] {
+            wi.insert_xds_workload(XdsWorkload {
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                name: network.to_string(),
+                network: network.to_string(),
+                virtual_ips: vip.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        assert_eq!(wi.workloads.len(), 2);
+        // Addresses without a network are on our own network
+        assert_eq!(
+            wi.find_workload(&"127.0.0.2".parse().unwrap())
+                .unwrap()
+                .name,
+            "network1"
+        );
+        assert_vips(&mut wi, vec!["network1", "network2"]);
+
+        wi.remove("network2/127.0.0.2".to_string());
+        assert_vips(&mut wi, vec!["network1"]);
+        assert_eq!(
+            wi.find_workload(&"127.0.0.2".parse().unwrap())
+                .unwrap()
+                .name,
+            "network1"
+        );
+    }
+
+    #[test]
+    fn services() {
+        let mut wi = WorkloadStore::default();
+        let svc = |name: &str, vip: [u8; 4]| XdsService {
+            name: name.to_string(),
+            namespace: "ns".to_string(),
+            hostname: format!("{name}.ns.svc.cluster.local"),
+            addresses: vec![xds::istio::workload::NetworkAddress {
+                network: "".to_string(),
+                address: Bytes::copy_from_slice(&vip),
+            }],
+            ports: vec![XdsPort {
+                service_port: 80,
+                target_port: 8080,
+            }],
+        };
+        let vips = |vips: &[&str]| {
+            vips.iter()
+                .map(|vip| {
+                    (
+                        vip.to_string(),
+                        XdsPortList {
+                            ports: vec![XdsPort {
+                                service_port: 80,
+                                target_port: 8080,
+                            }],
+                        },
+                    )
+                })
+                .collect()
+        };
+        wi.insert_xds_service(svc("a", [127, 0, 1, 1])).unwrap();
+        wi.insert_xds_service(svc("b", [127, 0, 1, 2])).unwrap();
+        wi.insert_xds_workload(XdsWorkload {
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+            name: "only-a".to_string(),
+            virtual_ips: vips(&["127.0.1.1"]),
+            ..Default::default()
+        })
+        .unwrap();
+        wi.insert_xds_workload(XdsWorkload {
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+            name: "both".to_string(),
+            virtual_ips: vips(&["127.0.1.1", "127.0.1.2"]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            wi.find_service(&"127.0.1.1".parse().unwrap()).unwrap().name,
+            "a"
+        );
+        assert_eq!(wi.find_service(&"127.0.0.1".parse().unwrap()), None);
+
+        let only_a = wi
+            .find_workload(&"127.0.0.1".parse().unwrap())
+            .unwrap()
+            .clone();
+        assert_eq!(
+            wi.find_destination_service(&only_a, 8080).unwrap().name,
+            "a"
+        );
+        assert_eq!(wi.find_destination_service(&only_a, 9090), None);
+        // A workload in multiple services cannot be attributed to either
+        let both = wi
+            .find_workload(&"127.0.0.2".parse().unwrap())
+            .unwrap()
+            .clone();
+        assert_eq!(wi.find_destination_service(&both, 8080), None);
+
+        // Moving the VIP to another service removes it from the old one
+        wi.insert_xds_service(svc("b", [127, 0, 1, 1])).unwrap();
+        assert_eq!(
+            wi.find_service(&"127.0.1.1".parse().unwrap()).unwrap().name,
+            "b"
+        );
+        assert_eq!(wi.find_service(&"127.0.1.2".parse().unwrap()), None);
+        wi.remove_service("ns/a.ns.svc.cluster.local".to_string());
+        assert_eq!(
+            wi.find_service(&"127.0.1.1".parse().unwrap()).unwrap().name,
+            "b"
+        );
+        wi.remove_service("ns/b.ns.svc.cluster.local".to_string());
+        assert_eq!(wi.find_service(&"127.0.1.1".parse().unwrap()), None);
+        assert!(wi.services.is_empty());
+    }
+
+    #[test]
+    fn service_ports_out_of_range() {
+        let svc = |service_port: u32, target_port: u32| XdsService {
+            name: "a".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "a.ns.svc.cluster.local".to_string(),
+            ports: vec![XdsPort {
+                service_port,
+                target_port,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            Service::try_from(&svc(80, 8080)).unwrap().ports,
+            HashMap::from([(80, 8080)])
+        );
+        // Rather than being truncated, to 80 and 8080 here
+        assert!(matches!(
+            Service::try_from(&svc(65536 + 80, 8080)),
+            Err(WorkloadError::PortParse(p)) if p == 65536 + 80
+        ));
+        assert!(matches!(
+            Service::try_from(&svc(80, 65536 + 8080)),
+            Err(WorkloadError::PortParse(p)) if p == 65536 + 8080
+        ));
+    }
+
+    #[test]
+    fn resolve_hostname() {
+        let mut wi = WorkloadStore::default();
+        wi.insert_service(Service {
+            name: "svc".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "svc.ns.svc.cluster.local".to_string(),
+            vips: vec!["127.0.1.1".parse().unwrap()],
+            ports: Default::default(),
+        });
+        wi.insert_workload(Workload {
+            workload_ips: vec!["127.0.0.2".parse().unwrap()],
+            name: "pod".to_string(),
+            namespace: "ns".to_string(),
+            ..test_helpers::test_default_workload()
+        });
+        let resolve = |host: &str| wi.resolve_hostname(host).map(|ip| ip.to_string());
+        assert_eq!(
+            resolve("svc.ns.svc.cluster.local").as_deref(),
+            Some("127.0.1.1")
+        );
+        assert_eq!(
+            resolve("svc.ns.svc.cluster.local.").as_deref(),
+            Some("127.0.1.1")
+        );
+        assert_eq!(resolve("pod.ns").as_deref(), Some("127.0.0.2"));
+        assert_eq!(resolve("pod.other"), None);
+        assert_eq!(resolve("svc.ns"), None);
+    }
+
     #[track_caller]
-    fn assert_vips(wi: &WorkloadStore, want: Vec<&str>) {
+    fn assert_vips(wi: &mut WorkloadStore, want: Vec<&str>) {
         let mut wants: HashSet<String> = HashSet::from_iter(want.iter().map(|x| x.to_string()));
         let mut found: HashSet<String> = HashSet::new();
         // VIP has randomness. We will try to fetch the VIP 1k times and assert the we got the expected results
         // at least once, and no unexpected results
         for _ in 0..1000 {
-            if let Some(us) = wi.find_upstream("127.0.1.1:80".parse().unwrap(), 15008) {
+            if let Some(us) = wi.find_upstream(
+                "127.0.1.1:80".parse().unwrap(),
+                "127.0.0.1".parse().unwrap(),
+                15008,
+                &[],
+            ) {
                 let n = &us.workload.name; // borrow name instead of cloning
                 found.insert(n.to_owned()); // insert an owned copy of the borrowed n
                 wants.remove(n); // remove using the borrow
@@ -1011,14 +2231,21 @@ mod tests {
             workloads: workloads.clone(),
         };
         local_client.run().await.expect("client should run");
-        let store = workloads.lock().unwrap();
+        let mut store = workloads.lock().unwrap();
         let wl = store.find_workload(&"127.0.0.1".parse().unwrap());
         // Make sure we get a valid workload
         assert!(wl.is_some());
         assert_eq!(wl.unwrap().service_account, "default");
-        let us = store.find_upstream("127.10.0.1:80".parse().unwrap(), 15008);
+        let us = store.find_upstream(
+            "127.10.0.1:80".parse().unwrap(),
+            "127.0.0.1".parse().unwrap(),
+            15008,
+            &[],
+        );
         // Make sure we get a valid VIP
         assert!(us.is_some());
         assert_eq!(us.unwrap().port, 8080);
+        let svc = store.find_service(&"127.10.0.1".parse().unwrap());
+        assert_eq!(svc.unwrap().hostname, "local.default.svc.cluster.local");
     }
 }
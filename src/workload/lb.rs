@@ -0,0 +1,378 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use rand::seq::IteratorRandom;
+
+use crate::config;
+use crate::workload::Workload;
+
+/// LoadBalancerPolicy determines how an endpoint is picked for a connection to a service VIP.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LoadBalancerPolicy {
+    /// Pick an endpoint at random.
+    #[default]
+    Random,
+    /// Cycle through endpoints in order.
+    RoundRobin,
+    /// Pick the endpoint with the fewest active connections from this proxy.
+    LeastActive,
+    /// Pick an endpoint based on a hash of the source IP, so a given client consistently reaches
+    /// the same endpoint while the set of endpoints is unchanged.
+    ConsistentHash,
+}
+
+impl FromStr for LoadBalancerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "RANDOM" => Ok(LoadBalancerPolicy::Random),
+            "ROUND_ROBIN" => Ok(LoadBalancerPolicy::RoundRobin),
+            "LEAST_ACTIVE" => Ok(LoadBalancerPolicy::LeastActive),
+            "CONSISTENT_HASH" => Ok(LoadBalancerPolicy::ConsistentHash),
+            _ => Err(format!("unknown load balancing policy {s}")),
+        }
+    }
+}
+
+/// LoadBalancer picks endpoints for service VIPs, according to the configured policies.
+#[derive(serde::Serialize, Default, Debug)]
+pub struct LoadBalancer {
+    /// The policy used for services without an explicit policy.
+    default_policy: LoadBalancerPolicy,
+    /// Policies for specific services, keyed by VIP.
+    service_policies: HashMap<IpAddr, LoadBalancerPolicy>,
+
+    #[serde(skip_serializing)]
+    round_robin: HashMap<SocketAddr, usize>,
+    #[serde(skip_serializing)]
+    active: ActiveConnections,
+}
+
+impl LoadBalancer {
+    pub fn new(cfg: &config::Config) -> LoadBalancer {
+        LoadBalancer {
+            default_policy: cfg.lb_policy,
+            service_policies: cfg.service_lb_policies.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn policy(&self, vip: &SocketAddr) -> LoadBalancerPolicy {
+        self.service_policies
+            .get(&vip.ip())
+            .copied()
+            .unwrap_or(self.default_policy)
+    }
+
+    /// pick selects one of `endpoints` to serve a connection from `source` to `vip`.
+    pub fn pick(
+        &mut self,
+        vip: SocketAddr,
+        source: IpAddr,
+        endpoints: &HashSet<(String, u16)>,
+    ) -> Option<(String, u16)> {
+        if endpoints.is_empty() {
+            return None;
+        }
+        let pick = match self.policy(&vip) {
+            LoadBalancerPolicy::Random => endpoints.iter().choose(&mut rand::thread_rng()),
+            LoadBalancerPolicy::RoundRobin => {
+                // Sort so the order is stable between calls
+                let mut sorted: Vec<_> = endpoints.iter().collect();
+                sorted.sort();
+                let next = self.round_robin.entry(vip).or_default();
+                let pick = sorted[*next % sorted.len()];
+                *next = next.wrapping_add(1);
+                Some(pick)
+            }
+            LoadBalancerPolicy::LeastActive => {
+                let active = self.active.0.lock().unwrap();
+                let count = |ep: &String| active.get(ep).copied().unwrap_or_default();
+                let least = endpoints.iter().map(|(ep, _)| count(ep)).min()?;
+                // Break ties randomly, so we do not always favor the same endpoint
+                endpoints
+                    .iter()
+                    .filter(|(ep, _)| count(ep) == least)
+                    .choose(&mut rand::thread_rng())
+            }
+            // Rendezvous hashing: only clients of a removed endpoint move when the endpoints change.
+            LoadBalancerPolicy::ConsistentHash => endpoints
+                .iter()
+                .max_by_key(|(ep, port)| hash(&[&source.to_string(), ep, &port.to_string()])),
+        };
+        pick.cloned()
+    }
+
+    pub fn active_connections(&self) -> ActiveConnections {
+        self.active.clone()
+    }
+}
+
+/// ActiveConnections tracks the number of open connections to each workload, by uid, used by the
+/// LeastActive policy.
+#[derive(Default, Debug, Clone)]
+pub struct ActiveConnections(Arc<Mutex<HashMap<String, usize>>>);
+
+impl ActiveConnections {
+    /// track records an active connection to the workload `uid` until the returned guard is dropped.
+    pub fn track(&self, uid: String) -> ActiveConnectionGuard {
+        *self.0.lock().unwrap().entry(uid.clone()).or_default() += 1;
+        ActiveConnectionGuard {
+            connections: self.clone(),
+            uid,
+        }
+    }
+}
+
+pub struct ActiveConnectionGuard {
+    connections: ActiveConnections,
+    uid: String,
+}
+
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        let mut active = self.connections.0.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.uid) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.uid);
+            }
+        }
+    }
+}
+
+/// closest returns the endpoints nearest to `source`, preferring those on the same node, then the
+/// same subzone, zone and region. When no endpoint shares any locality with the source, all are
+/// returned, so selection fails over outward rather than failing.
+pub fn closest(
+    source: &Workload,
+    endpoints: &HashSet<(String, u16)>,
+    workloads: &HashMap<String, Workload>,
+) -> HashSet<(String, u16)> {
+    let proximity = |ep: &String| {
+        workloads
+            .get(ep)
+            .map(|wl| proximity(source, wl))
+            .unwrap_or_default()
+    };
+    let Some(best) = endpoints.iter().map(|(ep, _)| proximity(ep)).max() else {
+        return HashSet::new();
+    };
+    endpoints
+        .iter()
+        .filter(|(ep, _)| proximity(ep) == best)
+        .cloned()
+        .collect()
+}
+
+/// proximity scores how close `b` is to `a`; higher is closer. Workloads on another network are
+/// never closer than those on the same network.
+fn proximity(a: &Workload, b: &Workload) -> u8 {
+    if a.network != b.network {
+        return 0;
+    }
+    if !a.node.is_empty() && a.node == b.node {
+        return 4;
+    }
+    let (a, b) = (&a.locality, &b.locality);
+    if a.region.is_empty() || a.region != b.region {
+        0
+    } else if a.zone.is_empty() || a.zone != b.zone {
+        1
+    } else if a.subzone.is_empty() || a.subzone != b.subzone {
+        2
+    } else {
+        3
+    }
+}
+
+/// hash is a 64-bit FNV-1a hash. Unlike the std hasher, its output is stable across processes, so
+/// ztunnel instances agree on the endpoint for a given client.
+fn hash(parts: &[&str]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for b in part.bytes().chain(std::iter::once(0)) {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers;
+    use crate::workload::Locality;
+
+    use super::*;
+
+    fn uid(i: u8) -> String {
+        format!("10.0.0.{i}")
+    }
+
+    fn endpoints(n: u8) -> HashSet<(String, u16)> {
+        (1..=n).map(|i| (uid(i), 8080)).collect()
+    }
+
+    fn lb(policy: LoadBalancerPolicy) -> LoadBalancer {
+        LoadBalancer {
+            default_policy: policy,
+            ..Default::default()
+        }
+    }
+
+    const VIP: &str = "127.10.0.1:80";
+    const SOURCE: &str = "127.0.0.1";
+
+    #[test]
+    fn round_robin() {
+        let mut lb = lb(LoadBalancerPolicy::RoundRobin);
+        let eps = endpoints(3);
+        let picks: Vec<_> = (0..6)
+            .map(|_| lb.pick(VIP.parse().unwrap(), SOURCE.parse().unwrap(), &eps))
+            .map(|p| p.unwrap().0.to_string())
+            .collect();
+        assert_eq!(
+            picks,
+            vec!["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.1", "10.0.0.2", "10.0.0.3"]
+        );
+    }
+
+    #[test]
+    fn least_active() {
+        let mut lb = lb(LoadBalancerPolicy::LeastActive);
+        let eps = endpoints(2);
+        let active = lb.active_connections();
+        let _first = active.track(uid(1));
+        for _ in 0..10 {
+            let pick = lb.pick(VIP.parse().unwrap(), SOURCE.parse().unwrap(), &eps);
+            assert_eq!(pick.unwrap().0.to_string(), "10.0.0.2");
+        }
+        let second = active.track(uid(2));
+        let third = active.track(uid(2));
+        let pick = lb.pick(VIP.parse().unwrap(), SOURCE.parse().unwrap(), &eps);
+        assert_eq!(pick.unwrap().0.to_string(), "10.0.0.1");
+        drop(second);
+        drop(third);
+        assert!(active.0.lock().unwrap().get(&uid(2)).is_none());
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let mut lb = lb(LoadBalancerPolicy::ConsistentHash);
+        let mut eps = endpoints(5);
+        let vip = VIP.parse().unwrap();
+        let sources: Vec<IpAddr> = (1..=50).map(|i| IpAddr::from([192, 168, 0, i])).collect();
+        let before: Vec<_> = sources
+            .iter()
+            .map(|s| lb.pick(vip, *s, &eps).unwrap())
+            .collect();
+        // Picks are stable
+        for (s, want) in sources.iter().zip(&before) {
+            assert_eq!(lb.pick(vip, *s, &eps).as_ref(), Some(want));
+        }
+        // Removing an endpoint only moves the sources that were using it
+        let removed = (uid(3), 8080);
+        eps.remove(&removed);
+        for (s, prev) in sources.iter().zip(&before) {
+            let now = lb.pick(vip, *s, &eps).unwrap();
+            if *prev != removed {
+                assert_eq!(now, *prev);
+            }
+        }
+    }
+
+    #[test]
+    fn service_policy() {
+        let mut lb = LoadBalancer {
+            default_policy: LoadBalancerPolicy::Random,
+            service_policies: HashMap::from([(
+                "127.10.0.1".parse().unwrap(),
+                LoadBalancerPolicy::RoundRobin,
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            lb.policy(&VIP.parse().unwrap()),
+            LoadBalancerPolicy::RoundRobin
+        );
+        assert_eq!(
+            lb.policy(&"127.10.0.2:80".parse().unwrap()),
+            LoadBalancerPolicy::Random
+        );
+        let eps = endpoints(2);
+        let first = lb.pick(VIP.parse().unwrap(), SOURCE.parse().unwrap(), &eps);
+        let second = lb.pick(VIP.parse().unwrap(), SOURCE.parse().unwrap(), &eps);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn closest_locality() {
+        let workload = |node: &str, region: &str, zone: &str, subzone: &str| Workload {
+            node: node.to_string(),
+            locality: Locality {
+                region: region.to_string(),
+                zone: zone.to_string(),
+                subzone: subzone.to_string(),
+            },
+            ..test_helpers::test_default_workload()
+        };
+        let source = workload("node-a", "r1", "z1", "s1");
+        let mut workloads = HashMap::from([
+            (uid(1), workload("node-a", "r1", "z1", "s1")),
+            (uid(2), workload("node-b", "r1", "z1", "s1")),
+            (uid(3), workload("node-c", "r1", "z1", "s2")),
+            (uid(4), workload("node-d", "r1", "z2", "s1")),
+            (uid(5), workload("node-e", "r2", "z1", "s1")),
+        ]);
+        let mut eps = endpoints(5);
+        // Each time the closest endpoint goes away, we should fail over to the next closest
+        for want in 1..=5u8 {
+            let got = closest(&source, &eps, &workloads);
+            assert_eq!(got, HashSet::from([(uid(want), 8080)]));
+            eps.remove(&(uid(want), 8080));
+            workloads.remove(&uid(want));
+        }
+        assert!(closest(&source, &eps, &workloads).is_empty());
+    }
+
+    #[test]
+    fn closest_without_locality() {
+        let source = test_helpers::test_default_workload();
+        let workloads = (1..=3)
+            .map(|i| (uid(i), test_helpers::test_default_workload()))
+            .collect();
+        let eps = endpoints(3);
+        assert_eq!(closest(&source, &eps, &workloads), eps);
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            "round_robin".parse::<LoadBalancerPolicy>(),
+            Ok(LoadBalancerPolicy::RoundRobin)
+        );
+        assert_eq!(
+            "CONSISTENT_HASH".parse::<LoadBalancerPolicy>(),
+            Ok(LoadBalancerPolicy::ConsistentHash)
+        );
+        assert!("fastest".parse::<LoadBalancerPolicy>().is_err());
+    }
+}
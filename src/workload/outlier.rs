@@ -0,0 +1,271 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::config;
+
+/// OutlierDetector passively tracks the outcome of connections to endpoints, and ejects endpoints
+/// that fail repeatedly from load balancing for an exponentially increasing period.
+#[derive(serde::Serialize, Default, Debug)]
+pub struct OutlierDetector {
+    /// Number of consecutive failures before an endpoint is ejected. Zero disables ejection.
+    consecutive_failures: u32,
+    base_ejection_time: Duration,
+    max_ejection_time: Duration,
+    /// The maximum percentage of a service's endpoints that may be ejected at once.
+    max_ejection_percent: u8,
+
+    /// The state of each endpoint with recent failures, by workload uid.
+    endpoints: HashMap<String, EndpointState>,
+}
+
+#[derive(Default, Debug)]
+struct EndpointState {
+    consecutive_failures: u32,
+    /// The number of times the endpoint has been ejected without a successful connection since.
+    ejections: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl EndpointState {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.map(|t| t > now).unwrap_or_default()
+    }
+}
+
+impl serde::Serialize for EndpointState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(serde::Serialize)]
+        struct Dump {
+            consecutive_failures: u32,
+            ejections: u32,
+            ejected: bool,
+            ejection_remaining: Option<Duration>,
+        }
+        let now = Instant::now();
+        Dump {
+            consecutive_failures: self.consecutive_failures,
+            ejections: self.ejections,
+            ejected: self.is_ejected(now),
+            ejection_remaining: self
+                .ejected_until
+                .and_then(|t| t.checked_duration_since(now)),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl OutlierDetector {
+    pub fn new(cfg: &config::Config) -> OutlierDetector {
+        OutlierDetector {
+            consecutive_failures: cfg.outlier_consecutive_failures,
+            base_ejection_time: cfg.outlier_base_ejection_time,
+            max_ejection_time: cfg.outlier_max_ejection_time,
+            max_ejection_percent: cfg.outlier_max_ejection_percent,
+            endpoints: Default::default(),
+        }
+    }
+
+    /// record_success records a successful connection to the workload `uid`.
+    pub fn record_success(&mut self, uid: String) {
+        if let Some(state) = self.endpoints.get(&uid) {
+            if !state.is_ejected(Instant::now()) {
+                // The endpoint has recovered, so forget its history
+                self.endpoints.remove(&uid);
+            }
+        }
+    }
+
+    /// record_failure records a failed connection to the workload `uid`, ejecting it once it has
+    /// failed too many times in a row.
+    pub fn record_failure(&mut self, uid: String) {
+        if self.consecutive_failures == 0 {
+            return;
+        }
+        let state = self.endpoints.entry(uid.clone()).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures < self.consecutive_failures {
+            return;
+        }
+        let ejection = self
+            .base_ejection_time
+            .saturating_mul(2u32.saturating_pow(state.ejections))
+            .min(self.max_ejection_time);
+        info!(endpoint=%uid, ?ejection, failures=state.consecutive_failures, "ejecting endpoint");
+        state.consecutive_failures = 0;
+        state.ejections = state.ejections.saturating_add(1);
+        state.ejected_until = Some(Instant::now() + ejection);
+    }
+
+    pub fn remove(&mut self, uid: &str) {
+        self.endpoints.remove(uid);
+    }
+
+    /// filter_ejected returns `endpoints` without those that are currently ejected, or None if no
+    /// endpoints need to be removed. At most max_ejection_percent of the endpoints are removed,
+    /// rounded down, preferring those with the longest ejection remaining. At least one endpoint is
+    /// always kept.
+    pub fn filter_ejected(
+        &self,
+        endpoints: &HashSet<(String, u16)>,
+    ) -> Option<HashSet<(String, u16)>> {
+        if self.endpoints.is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut ejected: Vec<_> = endpoints
+            .iter()
+            .filter_map(|ep| {
+                let state = self.endpoints.get(&ep.0)?;
+                state.is_ejected(now).then_some((state.ejected_until, ep))
+            })
+            .collect();
+        if ejected.is_empty() {
+            return None;
+        }
+        let allowed =
+            (endpoints.len() * self.max_ejection_percent as usize / 100).min(endpoints.len() - 1);
+        if allowed == 0 {
+            return None;
+        }
+        // Endpoints beyond the cap are kept, starting with those whose ejection ends soonest
+        ejected.sort_by_key(|(until, _)| std::cmp::Reverse(*until));
+        ejected.truncate(allowed);
+        let ejected: HashSet<_> = ejected.into_iter().map(|(_, ep)| ep).collect();
+        Some(
+            endpoints
+                .iter()
+                .filter(|ep| !ejected.contains(ep))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn detector() -> OutlierDetector {
+        OutlierDetector {
+            consecutive_failures: 3,
+            base_ejection_time: Duration::from_secs(30),
+            max_ejection_time: Duration::from_secs(100),
+            max_ejection_percent: 50,
+            endpoints: Default::default(),
+        }
+    }
+
+    fn endpoints(n: u8) -> HashSet<(String, u16)> {
+        (1..=n).map(|i| (ip(i), 8080)).collect()
+    }
+
+    fn ip(i: u8) -> String {
+        format!("10.0.0.{i}")
+    }
+
+    fn remaining(od: &OutlierDetector, ip: String) -> Duration {
+        od.endpoints[&ip]
+            .ejected_until
+            .unwrap()
+            .saturating_duration_since(Instant::now())
+    }
+
+    #[test]
+    fn eject_after_consecutive_failures() {
+        let mut od = detector();
+        let eps = endpoints(4);
+        od.record_failure(ip(1));
+        od.record_failure(ip(1));
+        // A success resets the count
+        od.record_success(ip(1));
+        od.record_failure(ip(1));
+        od.record_failure(ip(1));
+        assert_eq!(od.filter_ejected(&eps), None);
+        od.record_failure(ip(1));
+        let healthy = od.filter_ejected(&eps).unwrap();
+        assert_eq!(healthy.len(), 3);
+        assert!(!healthy.contains(&(ip(1), 8080)));
+    }
+
+    #[test]
+    fn exponential_ejection() {
+        let mut od = detector();
+        let eject = |od: &mut OutlierDetector| {
+            for _ in 0..3 {
+                od.record_failure(ip(1));
+            }
+            remaining(od, ip(1))
+        };
+        assert!(eject(&mut od) > Duration::from_secs(29));
+        assert!(eject(&mut od) > Duration::from_secs(59));
+        // Capped at the max ejection time
+        assert!(eject(&mut od) <= Duration::from_secs(100));
+        assert!(eject(&mut od) > Duration::from_secs(99));
+        assert_eq!(od.endpoints[&ip(1)].ejections, 4);
+    }
+
+    #[test_case(50, 4, 2; "half")]
+    #[test_case(50, 2, 1; "half of two")]
+    #[test_case(50, 1, 1; "rounded down")]
+    #[test_case(10, 5, 5; "fewer endpoints than the percentage allows")]
+    #[test_case(10, 10, 9; "one of ten")]
+    #[test_case(0, 4, 4; "zero percent")]
+    #[test_case(100, 3, 1; "never all")]
+    fn max_ejection_percent(percent: u8, n: u8, expected: usize) {
+        let mut od = OutlierDetector {
+            max_ejection_percent: percent,
+            ..detector()
+        };
+        for i in 1..=n {
+            for _ in 0..3 {
+                od.record_failure(ip(i));
+            }
+        }
+        let eps = endpoints(n);
+        let healthy = od.filter_ejected(&eps).unwrap_or_else(|| eps.clone());
+        assert_eq!(healthy.len(), expected);
+    }
+
+    #[test]
+    fn keeps_healthy_endpoints() {
+        let mut od = detector();
+        for _ in 0..3 {
+            od.record_failure(ip(1));
+        }
+        let two: HashSet<_> = [(ip(1), 8080), (ip(5), 8080)].into_iter().collect();
+        assert_eq!(
+            od.filter_ejected(&two).unwrap(),
+            HashSet::from([(ip(5), 8080)])
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let mut od = OutlierDetector {
+            consecutive_failures: 0,
+            ..detector()
+        };
+        for _ in 0..10 {
+            od.record_failure(ip(1));
+        }
+        assert_eq!(od.filter_ejected(&endpoints(2)), None);
+    }
+}
@@ -30,8 +30,8 @@ use tracing::{debug, error, info, info_span, warn, Instrument};
 use crate::config::RootCert;
 use crate::metrics::xds::*;
 use crate::metrics::{IncrementRecorder, Metrics};
-use crate::xds::istio::security::Authorization;
-use crate::xds::istio::workload::Workload;
+use crate::xds::istio::security::{Authorization, PeerAuthentication};
+use crate::xds::istio::workload::{Service, Workload};
 use crate::xds::service::discovery::v3::aggregated_discovery_service_client::AggregatedDiscoveryServiceClient;
 use crate::xds::service::discovery::v3::Resource as ProtoResource;
 use crate::xds::service::discovery::v3::*;
@@ -121,7 +121,9 @@ pub struct Config {
     proxy_metadata: HashMap<String, String>,
 
     workload_handler: Box<dyn Handler<Workload>>,
+    service_handler: Box<dyn Handler<Service>>,
     authorization_handler: Box<dyn Handler<Authorization>>,
+    peer_authentication_handler: Box<dyn Handler<PeerAuthentication>>,
     initial_watches: Vec<String>,
     on_demand: bool,
 }
@@ -133,7 +135,9 @@ impl Config {
             root_cert: config.xds_root_cert.clone(),
             auth: config.auth,
             workload_handler: Box::new(NopHandler {}),
+            service_handler: Box::new(NopHandler {}),
             authorization_handler: Box::new(NopHandler {}),
+            peer_authentication_handler: Box::new(NopHandler {}),
             initial_watches: Vec::new(),
             on_demand: config.xds_on_demand,
             proxy_metadata: config.proxy_metadata,
@@ -145,11 +149,24 @@ impl Config {
         self
     }
 
+    pub fn with_service_handler(mut self, f: impl Handler<Service>) -> Config {
+        self.service_handler = Box::new(f);
+        self
+    }
+
     pub fn with_authorization_handler(mut self, f: impl Handler<Authorization>) -> Config {
         self.authorization_handler = Box::new(f);
         self
     }
 
+    pub fn with_peer_authentication_handler(
+        mut self,
+        f: impl Handler<PeerAuthentication>,
+    ) -> Config {
+        self.peer_authentication_handler = Box::new(f);
+        self
+    }
+
     pub fn watch(mut self, type_url: String) -> Config {
         self.initial_watches.push(type_url);
         self
@@ -469,7 +486,7 @@ impl AdsClient {
         send: &mpsc::Sender<DeltaDiscoveryRequest>,
     ) -> Result<XdsSignal, Error> {
         let Some(response) = stream_event else {
-            return Ok( XdsSignal::None);
+            return Ok(XdsSignal::None);
         };
         let type_url = response.type_url.clone();
         let nonce = response.nonce.clone();
@@ -484,10 +501,17 @@ impl AdsClient {
             xds::WORKLOAD_TYPE => {
                 self.decode_and_handle::<Workload, _>(|a| &a.config.workload_handler, response)
             }
+            xds::SERVICE_TYPE => {
+                self.decode_and_handle::<Service, _>(|a| &a.config.service_handler, response)
+            }
             xds::AUTHORIZATION_TYPE => self.decode_and_handle::<Authorization, _>(
                 |a| &a.config.authorization_handler,
                 response,
             ),
+            xds::PEER_AUTHENTICATION_TYPE => self.decode_and_handle::<PeerAuthentication, _>(
+                |a| &a.config.peer_authentication_handler,
+                response,
+            ),
             _ => {
                 error!("unknown type");
                 Ok(())
@@ -706,7 +730,7 @@ mod tests {
             name: "1.1.1.1".to_string(),
             namespace: "default".to_string(),
             network: "".to_string(),
-            address: ip.octets().to_vec().into(),
+            addresses: vec![ip.octets().to_vec().into()],
             protocol: 0,
             trust_domain: "local".to_string(),
             service_account: "default".to_string(),
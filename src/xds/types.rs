@@ -39,4 +39,6 @@ pub mod istio {
 }
 
 pub const WORKLOAD_TYPE: &str = "type.googleapis.com/istio.workload.Workload";
+pub const SERVICE_TYPE: &str = "type.googleapis.com/istio.workload.Service";
 pub const AUTHORIZATION_TYPE: &str = "type.googleapis.com/istio.security.Authorization";
+pub const PEER_AUTHENTICATION_TYPE: &str = "type.googleapis.com/istio.security.PeerAuthentication";
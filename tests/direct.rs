@@ -12,25 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::time::Duration;
 
 use hyper::{Body, Client, Method, Request};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
 use tokio::time;
 use tokio::time::timeout;
 
 use ztunnel::identity::mock::new_secret_manager;
+use ztunnel::identity::Identity;
 
 use ztunnel::test_helpers::app as testapp;
 use ztunnel::test_helpers::app::TestApp;
 use ztunnel::test_helpers::assert_eventually;
 
 use ztunnel::config;
+use ztunnel::rbac::{
+    Authorization, MtlsMode, PeerAuthentication, RbacAction, RbacMatch, RbacScope,
+};
 use ztunnel::test_helpers::*;
+use ztunnel::workload::lb::LoadBalancerPolicy;
+use ztunnel::workload::{LocalConfig, LocalWorkload, Protocol, ProxyProtocol, Workload};
 
 #[tokio::test]
 async fn test_shutdown_lifecycle() {
@@ -96,6 +104,7 @@ async fn test_shutdown_drain() {
     let echo_addr = echo.address();
     tokio::spawn(echo.run());
     let shutdown = app.shutdown.trigger().clone();
+    let connections = app.connections.clone();
     let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel();
     tokio::spawn(async move {
         app.wait_termination().await.unwrap();
@@ -107,6 +116,7 @@ async fn test_shutdown_drain() {
     let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
     let mut stream = ta.socks5_connect(dst).await;
     read_write_stream(&mut stream).await;
+    assert_eq!(connections.active(), 2);
     // Since we are connected, the app shouldn't shutdown
     shutdown.shutdown_now().await;
     assert!(shutdown_rx.try_recv().is_err());
@@ -120,6 +130,7 @@ async fn test_shutdown_drain() {
         .await
         .expect("app should shutdown")
         .unwrap();
+    assert_eq!(connections.active(), 0);
 }
 
 #[tokio::test]
@@ -138,6 +149,7 @@ async fn test_shutdown_forced_drain() {
     let echo_addr = echo.address();
     tokio::spawn(echo.run());
     let shutdown = app.shutdown.trigger().clone();
+    let connections = app.connections.clone();
     let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel();
     tokio::spawn(async move {
         app.wait_termination().await.unwrap();
@@ -148,8 +160,7 @@ async fn test_shutdown_forced_drain() {
     assert!(shutdown_rx.try_recv().is_err());
     let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
     let mut stream = ta.socks5_connect(dst).await;
-    const BODY: &[u8] = "hello world".as_bytes();
-    stream.write_all(BODY).await.unwrap();
+    read_write_stream(&mut stream).await;
 
     // Since we are connected, the app shouldn't shutdown... but it will hit the max time and forcefully exit
     shutdown.shutdown_now().await;
@@ -158,6 +169,8 @@ async fn test_shutdown_forced_drain() {
         .await
         .expect("app should shutdown")
         .unwrap();
+    // Both the outbound and inbound sides of the connection were force closed
+    assert_eq!(connections.active(), 2);
 }
 
 #[tokio::test]
@@ -223,6 +236,658 @@ async fn test_vip_request_local() {
     run_request_test(&format!("{TEST_VIP}:80"), "local").await;
 }
 
+#[tokio::test]
+async fn test_socks5_hostname() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    testapp::with_app(test_config_with_port(echo_addr.port()), |app| async move {
+        // Service hostnames resolve to the VIP
+        let mut stream = app
+            .socks5_connect_hostname(TEST_SERVICE_HOST, 80)
+            .await
+            .unwrap();
+        read_write_stream(&mut stream).await;
+        // Workloads resolve by name and namespace
+        let mut stream = app
+            .socks5_connect_hostname("local-hbone.default", echo_addr.port())
+            .await
+            .unwrap();
+        read_write_stream(&mut stream).await;
+        // Without DNS fallback, unknown hosts are unreachable
+        assert!(app
+            .socks5_connect_hostname("unknown.example.com", 80)
+            .await
+            .is_err());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_socks5_auth() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let workload = |ip: &str, name: &str, service_account: &str| LocalWorkload {
+        workload: Workload {
+            workload_ips: vec![ip.parse().unwrap()],
+            name: name.to_string(),
+            workload_name: name.to_string(),
+            namespace: "default".to_string(),
+            service_account: service_account.to_string(),
+            ..test_default_workload()
+        },
+        vips: Default::default(),
+    };
+    let lc = LocalConfig {
+        workloads: vec![
+            workload(TEST_WORKLOAD_SOURCE, "source", "default"),
+            workload(TEST_WORKLOAD_TCP, "echo", "default"),
+            workload("127.0.0.5", "tool", "tool"),
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![],
+    };
+    let credentials = r#"
+- username: by-workload
+  password: secret
+  workload: 127.0.0.5
+- username: by-identity
+  password: secret
+  identity: spiffe://cluster.local/ns/default/sa/tool
+"#;
+    let cfg = config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        socks5_credentials: Some(config::ConfigSource::Static(credentials.into())),
+        socks5_allow_unauthenticated: false,
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_TCP.parse().unwrap());
+        for username in ["by-workload", "by-identity"] {
+            let mut stream = app
+                .socks5_connect_with_credentials(dst, username, "secret")
+                .await
+                .unwrap();
+            read_write_stream(&mut stream).await;
+        }
+        assert!(app
+            .socks5_connect_with_credentials(dst, "by-workload", "wrong")
+            .await
+            .is_err());
+        // Unauthenticated access is disabled
+        let stream = TcpStream::connect(app.proxy_addresses.socks5)
+            .await
+            .unwrap();
+        assert!(testapp::socks5_connect(stream, dst).await.is_err());
+
+        // Both credentials connect as the tool workload, rather than the client's address
+        let metrics = app.metrics().await.unwrap();
+        let opened = metrics.query_sum(
+            "istio_tcp_connections_opened_total",
+            &HashMap::from([
+                ("reporter".to_string(), "source".to_string()),
+                ("source_workload".to_string(), "tool".to_string()),
+            ]),
+        );
+        assert_eq!(opened, 2, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
+/// udp_echo starts a server on `ip` which echoes every datagram back to its sender.
+async fn udp_echo(ip: &str) -> SocketAddr {
+    let echo = UdpSocket::bind((ip.parse::<IpAddr>().unwrap(), 0))
+        .await
+        .unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        while let Ok((n, from)) = echo.recv_from(&mut buf).await {
+            echo.send_to(&buf[..n], from).await.unwrap();
+        }
+    });
+    echo_addr
+}
+
+#[tokio::test]
+async fn test_socks5_udp() {
+    let echo_addr = udp_echo(TEST_WORKLOAD_TCP).await;
+    testapp::with_app(test_config(), |app| async move {
+        let udp = app.socks5_udp_associate().await.unwrap();
+        // Datagrams to destinations outside the mesh are dropped
+        let unknown = helpers::with_ip(echo_addr, "127.0.0.9".parse().unwrap());
+        udp.send_to(unknown, b"dropped").await.unwrap();
+        for payload in [&b"hello"[..], &b"world"[..]] {
+            udp.send_to(echo_addr, payload).await.unwrap();
+            let (from, reply) = timeout(Duration::from_secs(5), udp.recv_from())
+                .await
+                .expect("timed out waiting for reply")
+                .unwrap();
+            assert_eq!(from, echo_addr);
+            assert_eq!(reply, payload);
+        }
+
+        // Closing the control connection ends the association, and its flow
+        drop(udp);
+        let labels = HashMap::from([
+            ("reporter".to_string(), "source".to_string()),
+            ("request_protocol".to_string(), "udp".to_string()),
+        ]);
+        assert_eventually(
+            Duration::from_secs(2),
+            || async {
+                app.metrics()
+                    .await
+                    .unwrap()
+                    .query_sum("istio_tcp_connections_closed_total", &labels)
+            },
+            1,
+        )
+        .await;
+        let metrics = app.metrics().await.unwrap();
+        let opened = metrics.query_sum("istio_tcp_connections_opened_total", &labels);
+        assert_eq!(opened, 1, "metrics: {}", metrics.dump());
+        let sent = metrics.query_sum("istio_tcp_sent_bytes_total", &labels);
+        assert_eq!(sent, 10, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_socks5_udp_hbone() {
+    let echo_addr = udp_echo(TEST_WORKLOAD_HBONE).await;
+    testapp::with_app(test_config(), |app| async move {
+        let udp = app.socks5_udp_associate().await.unwrap();
+        for payload in [&b"hello"[..], &b"world"[..]] {
+            udp.send_to(echo_addr, payload).await.unwrap();
+            let (from, reply) = timeout(Duration::from_secs(5), udp.recv_from())
+                .await
+                .expect("timed out waiting for reply")
+                .unwrap();
+            assert_eq!(from, echo_addr);
+            assert_eq!(reply, payload);
+        }
+
+        // The flow is tunneled over HBONE, so the destination's node reports it too. Closing the
+        // association closes the tunnel.
+        drop(udp);
+        for reporter in ["source", "destination"] {
+            let labels = HashMap::from([
+                ("reporter".to_string(), reporter.to_string()),
+                ("request_protocol".to_string(), "udp".to_string()),
+                (
+                    "connection_security_policy".to_string(),
+                    "mutual_tls".to_string(),
+                ),
+            ]);
+            assert_eventually(
+                Duration::from_secs(2),
+                || async {
+                    app.metrics()
+                        .await
+                        .unwrap()
+                        .query_sum("istio_tcp_connections_closed_total", &labels)
+                },
+                1,
+            )
+            .await;
+            let metrics = app.metrics().await.unwrap();
+            let opened = metrics.query_sum("istio_tcp_connections_opened_total", &labels);
+            assert_eq!(opened, 1, "metrics: {}", metrics.dump());
+            let sent = metrics.query_sum("istio_tcp_sent_bytes_total", &labels);
+            assert_eq!(sent, 10, "metrics: {}", metrics.dump());
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_socks5_udp_hbone_waypoint() {
+    // A bare socket on the workload, which no datagram should reach in plaintext
+    let plaintext = UdpSocket::bind((TEST_WORKLOAD_HBONE, 0)).await.unwrap();
+    let dst = plaintext.local_addr().unwrap();
+    let workload = |ip: &str, name: &str, waypoint_addresses: Vec<IpAddr>| LocalWorkload {
+        workload: Workload {
+            workload_ips: vec![ip.parse().unwrap()],
+            protocol: Protocol::HBONE,
+            name: name.to_string(),
+            namespace: "default".to_string(),
+            waypoint_addresses,
+            ..test_default_workload()
+        },
+        vips: Default::default(),
+    };
+    let lc = LocalConfig {
+        workloads: vec![
+            workload(TEST_WORKLOAD_SOURCE, "source", vec![]),
+            workload(
+                TEST_WORKLOAD_HBONE,
+                "hbone",
+                vec!["127.0.0.5".parse().unwrap()],
+            ),
+            workload("127.0.0.5", "waypoint", vec![]),
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![],
+    };
+    let cfg = config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| {
+        let plaintext = &plaintext;
+        async move {
+            // UDP is not tunneled through waypoints, so the datagram is dropped rather than sent
+            // around the waypoint in plaintext
+            let udp = app.socks5_udp_associate().await.unwrap();
+            udp.send_to(dst, b"hello").await.unwrap();
+            let mut buf = [0u8; 16];
+            assert!(
+                timeout(Duration::from_millis(500), plaintext.recv_from(&mut buf))
+                    .await
+                    .is_err(),
+                "datagram sent in plaintext"
+            );
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_proxy_protocol() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let cfg = config::Config {
+        proxy_protocol_trusted_cidrs: vec!["127.0.0.1/32".parse().unwrap()],
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        let outbound = helpers::with_ip(app.proxy_addresses.outbound, "127.0.0.1".parse().unwrap());
+        // The header replaces both addresses; the peer itself is not a known workload
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        let headers = [
+            format!(
+                "PROXY TCP4 {TEST_WORKLOAD_SOURCE} {} 1234 {}\r\n",
+                dst.ip(),
+                dst.port()
+            )
+            .into_bytes(),
+            [
+                &b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c"[..],
+                &[127, 0, 0, 2, 127, 0, 0, 3, 0x04, 0xd2],
+                &dst.port().to_be_bytes(),
+            ]
+            .concat(),
+        ];
+        for header in headers {
+            let mut stream = TcpStream::connect(outbound).await.unwrap();
+            stream.write_all(&header).await.unwrap();
+            read_write_stream(&mut stream).await;
+        }
+
+        // Trusted peers must send a header
+        let mut stream = TcpStream::connect(outbound).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut buf = [0u8; 1];
+        assert!(matches!(stream.read(&mut buf).await, Ok(0) | Err(_)));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_proxy_protocol_to_workload() {
+    let listener = TcpListener::bind((TEST_WORKLOAD_HBONE, 0)).await.unwrap();
+    let dst = listener.local_addr().unwrap();
+    let (header_tx, header_rx) = tokio::sync::watch::channel(Vec::new());
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        // The signature, version and command, family, then the length of the rest
+        let mut header = vec![0u8; 16];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+        header.resize(16 + len, 0);
+        stream.read_exact(&mut header[16..]).await.unwrap();
+        header_tx.send(header).unwrap();
+        let (mut r, mut w) = stream.split();
+        tokio::io::copy(&mut r, &mut w).await.unwrap();
+    });
+    let lc = LocalConfig {
+        workloads: vec![
+            LocalWorkload {
+                workload: Workload {
+                    workload_ips: vec![TEST_WORKLOAD_SOURCE.parse().unwrap()],
+                    namespace: "default".to_string(),
+                    service_account: "client".to_string(),
+                    ..test_default_workload()
+                },
+                vips: Default::default(),
+            },
+            LocalWorkload {
+                workload: Workload {
+                    workload_ips: vec![TEST_WORKLOAD_HBONE.parse().unwrap()],
+                    protocol: Protocol::HBONE,
+                    proxy_protocol: Some(ProxyProtocol {
+                        ports: vec![dst.port()],
+                    }),
+                    ..test_default_workload()
+                },
+                vips: Default::default(),
+            },
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![],
+    };
+    let cfg = config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| {
+        let mut header_rx = header_rx.clone();
+        async move {
+            let mut stream = app.socks5_connect(dst).await;
+            read_write_stream(&mut stream).await;
+
+            header_rx.changed().await.unwrap();
+            let header = header_rx.borrow().clone();
+            assert_eq!(&header[..12], b"\r\n\r\n\0\r\nQUIT\n");
+            // Version 2 PROXY, TCP over IPv4
+            assert_eq!(header[12..14], [0x21, 0x11]);
+            // The source port is not known
+            let addresses = [
+                &[127, 0, 0, 2, 127, 0, 0, 3, 0, 0][..],
+                &dst.port().to_be_bytes(),
+            ]
+            .concat();
+            assert_eq!(header[16..28], addresses);
+            let tlvs = String::from_utf8_lossy(&header[28..]);
+            assert!(
+                tlvs.contains("spiffe://cluster.local/ns/default/sa/client"),
+                "{tlvs}"
+            );
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_http_connect() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_port = echo.address().port();
+    tokio::spawn(echo.run());
+    // Nothing listens on this port once the listener is dropped
+    let closed_port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let workload = |ip: &str, name: &str, namespace: &str, protocol: Protocol| LocalWorkload {
+        workload: Workload {
+            workload_ips: vec![ip.parse().unwrap()],
+            protocol,
+            name: name.to_string(),
+            namespace: namespace.to_string(),
+            service_account: "default".to_string(),
+            ..test_default_workload()
+        },
+        vips: Default::default(),
+    };
+    let lc = LocalConfig {
+        workloads: vec![
+            workload(TEST_WORKLOAD_SOURCE, "source", "default", Protocol::TCP),
+            workload(TEST_WORKLOAD_TCP, "echo", "default", Protocol::TCP),
+            workload(
+                TEST_WORKLOAD_HBONE,
+                "protected",
+                "protected",
+                Protocol::HBONE,
+            ),
+        ],
+        services: vec![],
+        policies: vec![Authorization {
+            name: "deny-echo".to_string(),
+            namespace: "protected".to_string(),
+            scope: RbacScope::Namespace,
+            action: RbacAction::Deny,
+            groups: vec![vec![vec![RbacMatch {
+                destination_ports: vec![echo_port],
+                ..Default::default()
+            }]]],
+        }],
+        peer_authentications: vec![],
+    };
+    let cfg = config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        http_connect_addr: Some("127.0.0.1:0".parse().unwrap()),
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        for target in [
+            format!("{TEST_WORKLOAD_TCP}:{echo_port}"),
+            format!("echo.default:{echo_port}"),
+        ] {
+            let (status, mut stream) = app.http_connect(&target).await.unwrap();
+            assert_eq!(status, 200, "{target}");
+            read_write_stream(&mut stream).await;
+        }
+        for (target, expected) in [
+            // Destinations outside the mesh
+            ("127.0.0.9:80".to_string(), 404),
+            ("unknown.default:80".to_string(), 404),
+            // Rejected by the destination's authorization policy
+            (format!("{TEST_WORKLOAD_HBONE}:{echo_port}"), 403),
+            // Connecting to the destination fails
+            (format!("{TEST_WORKLOAD_TCP}:{closed_port}"), 502),
+        ] {
+            let (status, _) = app.http_connect(&target).await.unwrap();
+            assert_eq!(status, expected, "{target}");
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_peer_authentication() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let workload = |ip: &str, uid: &str, namespace: &str, policies: Vec<String>| LocalWorkload {
+        workload: Workload {
+            uid: uid.to_string(),
+            workload_ips: vec![ip.parse().unwrap()],
+            protocol: Protocol::HBONE,
+            name: uid.to_string(),
+            namespace: namespace.to_string(),
+            peer_authentication_policies: policies,
+            ..test_default_workload()
+        },
+        vips: Default::default(),
+    };
+    let policy =
+        |name: &str, namespace: &str, scope, mode, ports: &[(u16, MtlsMode)]| PeerAuthentication {
+            name: name.to_string(),
+            namespace: namespace.to_string(),
+            scope,
+            mode,
+            port_level_mtls: ports.iter().copied().collect(),
+        };
+    let lc = LocalConfig {
+        workloads: vec![
+            LocalWorkload {
+                workload: Workload {
+                    workload_ips: vec![TEST_WORKLOAD_SOURCE.parse().unwrap()],
+                    namespace: "default".to_string(),
+                    ..test_default_workload()
+                },
+                vips: Default::default(),
+            },
+            workload(TEST_WORKLOAD_HBONE, "strict", "default", vec![]),
+            workload(
+                TEST_WORKLOAD_TCP,
+                "exempt",
+                "default",
+                vec!["default/exempt".to_string()],
+            ),
+            workload("127.0.0.5", "disabled", "plaintext", vec![]),
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![
+            policy(
+                "mesh",
+                "istio-system",
+                RbacScope::Global,
+                Some(MtlsMode::Strict),
+                &[],
+            ),
+            policy(
+                "exempt",
+                "default",
+                RbacScope::WorkloadSelector,
+                None,
+                &[(echo_addr.port(), MtlsMode::Permissive)],
+            ),
+            policy(
+                "plaintext",
+                "plaintext",
+                RbacScope::Namespace,
+                Some(MtlsMode::Disable),
+                &[],
+            ),
+        ],
+    };
+    let cfg = config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        proxy_protocol_trusted_cidrs: vec!["127.0.0.1/32".parse().unwrap()],
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        // Plaintext connections name their destination with a PROXY header, as the address they
+        // were redirected from is not known
+        let inbound_plaintext = app.proxy_addresses.inbound_plaintext;
+        let plaintext_connect = |ip: &str| {
+            let header = format!(
+                "PROXY TCP4 {TEST_WORKLOAD_SOURCE} {ip} 1234 {}\r\n",
+                echo_addr.port()
+            );
+            async move {
+                let mut stream = TcpStream::connect(inbound_plaintext).await.unwrap();
+                stream.write_all(header.as_bytes()).await.unwrap();
+                stream
+            }
+        };
+        let mut stream = plaintext_connect(TEST_WORKLOAD_TCP).await;
+        read_write_stream(&mut stream).await;
+        let mut stream = plaintext_connect(TEST_WORKLOAD_HBONE).await;
+        let mut buf = [0u8; 1];
+        assert!(matches!(stream.read(&mut buf).await, Ok(0) | Err(_)));
+
+        // Peers of workloads that disable mTLS send them plaintext
+        let dst = helpers::with_ip(echo_addr, "127.0.0.5".parse().unwrap());
+        let mut stream = app.socks5_connect(dst).await;
+        read_write_stream(&mut stream).await;
+
+        let metrics = app.metrics().await.unwrap();
+        let rejected = HashMap::from([(
+            "destination_workload_namespace".to_string(),
+            "default".to_string(),
+        )]);
+        assert_eq!(
+            metrics.query_sum("istio_tcp_plaintext_connections_rejected_total", &rejected),
+            1,
+            "metrics: {}",
+            metrics.dump()
+        );
+        let mutual_tls = HashMap::from([(
+            "connection_security_policy".to_string(),
+            "mutual_tls".to_string(),
+        )]);
+        assert_eq!(
+            metrics.query_sum("istio_tcp_connections_opened_total", &mutual_tls),
+            0,
+            "metrics: {}",
+            metrics.dump()
+        );
+
+        let dump = app.admin_request("config_dump").await.unwrap();
+        let dump: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(dump.into_body()).await.unwrap())
+                .unwrap();
+        let modes = &dump["mtls_modes"];
+        assert_eq!(modes["strict"]["mode"], "Strict", "{modes}");
+        assert_eq!(modes["exempt"]["mode"], "Strict", "{modes}");
+        assert_eq!(
+            modes["exempt"]["ports"][echo_addr.port().to_string()],
+            "Permissive",
+            "{modes}"
+        );
+        assert_eq!(modes["disabled"]["mode"], "Disable", "{modes}");
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_connections_dump() {
+    testapp::with_app(test_config(), |app| async move {
+        let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+        let echo_addr = echo.address();
+        tokio::spawn(echo.run());
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        let mut stream = app.socks5_connect(dst).await;
+        read_write_stream(&mut stream).await;
+
+        let app = &app;
+        let dump = |path: &'static str| async move {
+            let res = app.admin_request(path).await.unwrap();
+            let status = res.status();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            (status, body)
+        };
+        let (status, body) = dump("connections").await;
+        assert_eq!(status, hyper::StatusCode::OK);
+        let conns: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let conns = conns.as_array().unwrap();
+        assert_eq!(conns.len(), 2, "{conns:?}");
+        let outbound = &conns[0];
+        assert_eq!(outbound["reporter"], "source", "{outbound}");
+        assert_eq!(outbound["requestType"], "Direct", "{outbound}");
+        assert_eq!(outbound["protocol"], "HBONE", "{outbound}");
+        assert_eq!(outbound["destination"]["name"], "local-hbone", "{outbound}");
+        let inbound = &conns[1];
+        assert_eq!(inbound["reporter"], "destination", "{inbound}");
+        assert_eq!(inbound["destination"]["name"], "local-hbone", "{inbound}");
+        assert_eq!(
+            inbound["source"]["identity"], "spiffe://cluster.local/ns/default/sa/default",
+            "{inbound}"
+        );
+
+        let (_, body) = dump("connections?workload=local-hbone").await;
+        let conns: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(conns.as_array().unwrap().len(), 2, "{conns}");
+        let (_, body) = dump("connections?namespace=other").await;
+        let conns: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(conns, serde_json::json!([]));
+        let (status, _) = dump("connections?pod=local-hbone").await;
+        assert_eq!(status, hyper::StatusCode::BAD_REQUEST);
+    })
+    .await;
+}
+
 #[tokio::test]
 async fn test_stats_exist() {
     testapp::with_app(test_config(), |app| async move {
@@ -298,6 +963,37 @@ async fn test_tcp_connections_metrics() {
     .await;
 }
 
+#[tokio::test]
+async fn test_vip_service_metrics() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    testapp::with_app(test_config_with_port(echo_addr.port()), |app| async move {
+        let dst = SocketAddr::from((TEST_VIP.parse::<IpAddr>().unwrap(), 80));
+        let mut stream = app.socks5_connect(dst).await;
+        read_write_stream(&mut stream).await;
+
+        let metrics = app.metrics().await.unwrap();
+        let opened = metrics.query_sum(
+            "istio_tcp_connections_opened_total",
+            &HashMap::from([
+                ("reporter".to_string(), "source".to_string()),
+                (
+                    "destination_service".to_string(),
+                    TEST_SERVICE_HOST.to_string(),
+                ),
+                ("destination_service_name".to_string(), "echo".to_string()),
+                (
+                    "destination_service_namespace".to_string(),
+                    "default".to_string(),
+                ),
+            ]),
+        );
+        assert_eq!(opened, 1, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
 #[tokio::test]
 async fn test_tcp_bytes_metrics() {
     let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
@@ -337,7 +1033,472 @@ async fn test_tcp_bytes_metrics() {
     .await;
 }
 
-async fn read_write_stream(stream: &mut TcpStream) -> usize {
+#[tokio::test]
+async fn test_hbone_connection_reuse() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    testapp::with_app(test_config(), |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        // Whether opened concurrently or after others have closed, tunnels should share one connection
+        let mut first = app.socks5_connect(dst).await;
+        read_write_stream(&mut first).await;
+        let mut second = app.socks5_connect(dst).await;
+        read_write_stream(&mut second).await;
+        drop(first);
+        drop(second);
+        let mut third = app.socks5_connect(dst).await;
+        read_write_stream(&mut third).await;
+
+        let metrics = app.metrics().await.unwrap();
+        let checkouts = |result: &str| {
+            metrics.query_sum(
+                "istio_hbone_pool_checkouts_total",
+                &HashMap::from([("result".to_string(), result.to_string())]),
+            )
+        };
+        assert_eq!(checkouts("Miss"), 1, "metrics: {}", metrics.dump());
+        assert_eq!(checkouts("Hit"), 2, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_hbone_network_gateway() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let workload = |ip: &str, name: &str, network: &str, vips| LocalWorkload {
+        workload: Workload {
+            workload_ips: vec![ip.parse().unwrap()],
+            protocol: Protocol::HBONE,
+            name: name.to_string(),
+            namespace: "default".to_string(),
+            network: network.to_string(),
+            ..test_default_workload()
+        },
+        vips,
+    };
+    // Workloads on other networks are only reachable through services
+    let vips = HashMap::from([(
+        TEST_VIP.to_string(),
+        HashMap::from([(80u16, echo_addr.port())]),
+    )]);
+    let lc = LocalConfig {
+        workloads: vec![
+            workload(TEST_WORKLOAD_SOURCE, "source", "", Default::default()),
+            workload(TEST_WORKLOAD_HBONE, "remote", "remote", vips),
+            // As the ports differ, the destination's node is played by the source's ztunnel,
+            // which must then know the workload as if it were on its network
+            workload(TEST_WORKLOAD_HBONE, "local", "", Default::default()),
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![],
+    };
+    // The gateway is another ztunnel, which forwards the outer tunnel to the HBONE port of the
+    // destination's node
+    testapp::with_app(test_config(), |gateway| {
+        let lc = lc.clone();
+        async move {
+            let gateway_addr = SocketAddr::new(
+                TEST_WORKLOAD_HBONE.parse().unwrap(),
+                gateway.proxy_addresses.inbound.port(),
+            );
+            let cfg = config::Config {
+                local_xds_config: Some(config::ConfigSource::Static(
+                    serde_yaml::to_string(&lc).unwrap().into(),
+                )),
+                network_gateways: HashMap::from([("remote".to_string(), gateway_addr)]),
+                // The gateway presents the identity of the workload it was reached on
+                network_gateway_identity: Identity::from_str(
+                    "spiffe://cluster.local/ns/default/sa/default",
+                )
+                .unwrap(),
+                ..test_config()
+            };
+            testapp::with_app(cfg, |app| async move {
+                let dst = SocketAddr::new(TEST_VIP.parse().unwrap(), 80);
+                let mut stream = app.socks5_connect(dst).await;
+                read_write_stream(&mut stream).await;
+            })
+            .await;
+
+            let metrics = gateway.metrics().await.unwrap();
+            let labels = HashMap::from([("reporter".to_string(), "destination".to_string())]);
+            let opened = metrics.query_sum("istio_tcp_connections_opened_total", &labels);
+            assert_eq!(opened, 1, "metrics: {}", metrics.dump());
+        }
+    })
+    .await;
+}
+
+#[cfg(feature = "quic")]
+#[tokio::test]
+async fn test_hbone_h3() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let cfg = config::Config {
+        inbound_h3_addr: Some("0.0.0.0:0".parse().unwrap()),
+        // HTTP/3 is not used for connections from the source workload's address
+        enable_original_source: Some(false),
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        // The first tunnel is over HTTP/2, whose response advertises HTTP/3 for the rest
+        for _ in 0..3 {
+            let mut stream = app.socks5_connect(dst).await;
+            read_write_stream(&mut stream).await;
+        }
+
+        let metrics = app.metrics().await.unwrap();
+        let checkouts = |result: &str| {
+            metrics.query_sum(
+                "istio_hbone_pool_checkouts_total",
+                &HashMap::from([("result".to_string(), result.to_string())]),
+            )
+        };
+        assert_eq!(checkouts("Miss"), 1, "metrics: {}", metrics.dump());
+        assert_eq!(checkouts("Hit"), 0, "metrics: {}", metrics.dump());
+        let labels = HashMap::from([
+            ("reporter".to_string(), "destination".to_string()),
+            (
+                "connection_security_policy".to_string(),
+                "mutual_tls".to_string(),
+            ),
+        ]);
+        let opened = metrics.query_sum("istio_tcp_connections_opened_total", &labels);
+        assert_eq!(opened, 3, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_vip_connect_retry() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    // Reserve a port, then close it so connections to it are refused
+    let closed_port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let endpoint = |ip: &str, name: &str, port: u16| LocalWorkload {
+        workload: Workload {
+            workload_ips: vec![ip.parse().unwrap()],
+            name: name.to_string(),
+            workload_name: name.to_string(),
+            namespace: "default".to_string(),
+            ..test_default_workload()
+        },
+        vips: HashMap::from([(TEST_VIP.to_string(), HashMap::from([(80u16, port)]))]),
+    };
+    let lc = LocalConfig {
+        workloads: vec![
+            LocalWorkload {
+                vips: Default::default(),
+                ..endpoint(TEST_WORKLOAD_SOURCE, "source", 0)
+            },
+            endpoint(TEST_WORKLOAD_TCP, "healthy", echo_addr.port()),
+            endpoint("127.0.0.5", "unreachable", closed_port),
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![],
+    };
+    let cfg = config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        // Alternate endpoints, so we are sure to hit the unreachable one
+        lb_policy: LoadBalancerPolicy::RoundRobin,
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        let dst = SocketAddr::from((TEST_VIP.parse::<IpAddr>().unwrap(), 80));
+        for _ in 0..4 {
+            let mut stream = app.socks5_connect(dst).await;
+            read_write_stream(&mut stream).await;
+        }
+        // Every connection succeeded, so any that picked the unreachable endpoint were retried
+        let metrics = app.metrics().await.unwrap();
+        let retries = metrics.query_sum(
+            "istio_tcp_connect_retries_total",
+            &HashMap::from([("reason".to_string(), "connect_error".to_string())]),
+        );
+        assert!(retries > 0, "metrics: {}", metrics.dump());
+        // Retried attempts are not reported as connections of their own
+        for (workload, expected) in [("healthy", 4), ("unreachable", 0)] {
+            let labels = HashMap::from([
+                ("reporter".to_string(), "source".to_string()),
+                ("destination_workload".to_string(), workload.to_string()),
+            ]);
+            let opened = metrics.query_sum("istio_tcp_connections_opened_total", &labels);
+            assert_eq!(opened, expected, "metrics: {}", metrics.dump());
+        }
+    })
+    .await;
+}
+
+/// run_connection_limit_test checks that a connection to `target` is closed by the proxy once it
+/// exceeds the limits in `cfg`, and reported with `response_flags`.
+async fn run_connection_limit_test(target: &str, cfg: config::Config, response_flags: &str) {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    testapp::with_app(cfg, |app| async move {
+        let dst = helpers::with_ip(echo_addr, target.parse().unwrap());
+        let mut stream = app.socks5_connect(dst).await;
+        let size = read_write_stream(&mut stream).await as u64;
+        // Leave the connection alone until the proxy closes it
+        let mut buf = [0; 1];
+        let read = timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("connection should be closed");
+        assert!(matches!(read, Ok(0) | Err(_)), "unexpected read {read:?}");
+
+        let metrics = app.metrics().await.unwrap();
+        let closed = metrics.query_sum(
+            "istio_tcp_connections_closed_total",
+            &HashMap::from([("response_flags".to_string(), response_flags.to_string())]),
+        );
+        assert!(closed > 0, "metrics: {}", metrics.dump());
+        // The bytes copied before the connection was closed are still reported. The destination
+        // may close first, so wait for our side to finish closing the connection.
+        let labels = HashMap::from([("reporter".to_string(), "source".to_string())]);
+        assert_eventually(
+            Duration::from_secs(2),
+            || async {
+                app.metrics()
+                    .await
+                    .unwrap()
+                    .query_sum("istio_tcp_connections_closed_total", &labels)
+            },
+            1,
+        )
+        .await;
+        let metrics = app.metrics().await.unwrap();
+        let sent = metrics.query_sum("istio_tcp_sent_bytes_total", &labels);
+        assert_eq!(sent, size, "metrics: {}", metrics.dump());
+        let received = metrics.query_sum("istio_tcp_received_bytes_total", &labels);
+        assert_eq!(received, size, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_tcp_idle_timeout() {
+    let cfg = config::Config {
+        connection_idle_timeout: Some(Duration::from_secs(1)),
+        ..test_config()
+    };
+    run_connection_limit_test(TEST_WORKLOAD_TCP, cfg, "SI").await;
+}
+
+#[tokio::test]
+async fn test_hbone_idle_timeout() {
+    let cfg = config::Config {
+        connection_idle_timeout: Some(Duration::from_secs(1)),
+        ..test_config()
+    };
+    run_connection_limit_test(TEST_WORKLOAD_HBONE, cfg, "SI").await;
+}
+
+#[tokio::test]
+async fn test_max_connection_duration() {
+    let cfg = config::Config {
+        connection_idle_timeout: None,
+        max_connection_duration: Some(Duration::from_secs(1)),
+        ..test_config()
+    };
+    run_connection_limit_test(TEST_WORKLOAD_HBONE, cfg, "DT").await;
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_max_connections() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let cfg = config::Config {
+        circuit_breaker_max_connections: 1,
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_TCP.parse().unwrap());
+        let mut first = app.socks5_connect(dst).await;
+        read_write_stream(&mut first).await;
+
+        // The destination is at capacity, so the proxy closes the connection without connecting
+        let mut second = app.socks5_connect(dst).await;
+        let mut buf = [0; 1];
+        let read = timeout(Duration::from_secs(5), second.read(&mut buf))
+            .await
+            .expect("connection should be rejected");
+        assert!(matches!(read, Ok(0) | Err(_)), "unexpected read {read:?}");
+        // The first connection is unaffected
+        read_write_stream(&mut first).await;
+
+        let metrics = app.metrics().await.unwrap();
+        let rejected = metrics.query_sum(
+            "istio_tcp_connections_closed_total",
+            &HashMap::from([("response_flags".to_string(), "UO".to_string())]),
+        );
+        assert_eq!(rejected, 1, "metrics: {}", metrics.dump());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_connection_rate_limit() {
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    let cfg = config::Config {
+        connection_rate_limits: "default: {connectionsPerSecond: 0, burst: 1}"
+            .parse()
+            .unwrap(),
+        ..test_config()
+    };
+    testapp::with_app(cfg, |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_TCP.parse().unwrap());
+        let mut first = app.socks5_connect(dst).await;
+        read_write_stream(&mut first).await;
+
+        // The source has used its only token, so the proxy closes the connection
+        let mut second = app.socks5_connect(dst).await;
+        let mut buf = [0; 1];
+        let read = timeout(Duration::from_secs(5), second.read(&mut buf))
+            .await
+            .expect("connection should be rejected");
+        assert!(matches!(read, Ok(0) | Err(_)), "unexpected read {read:?}");
+
+        let metrics = app.metrics().await.unwrap();
+        let rejected = metrics.query_sum(
+            "istio_tcp_connections_closed_total",
+            &HashMap::from([("response_flags".to_string(), "RL".to_string())]),
+        );
+        assert_eq!(rejected, 1, "metrics: {}", metrics.dump());
+
+        // Removing the limit at runtime lets the source connect again
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "http://localhost:{}/ratelimits",
+                app.admin_address.port()
+            ))
+            .body(Body::from("{}"))
+            .unwrap();
+        let resp = Client::new().request(req).await.unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        let mut third = app.socks5_connect(dst).await;
+        read_write_stream(&mut third).await;
+    })
+    .await;
+}
+
+/// native_hbone_config defines a native HBONE workload at 127.0.0.5, which is served by an
+/// `HboneTestServer` on its own HBONE port rather than by the proxy.
+fn native_hbone_config() -> config::Config {
+    let lc = LocalConfig {
+        workloads: vec![
+            LocalWorkload {
+                workload: Workload {
+                    workload_ips: vec![TEST_WORKLOAD_SOURCE.parse().unwrap()],
+                    name: "source".to_string(),
+                    namespace: "default".to_string(),
+                    ..test_default_workload()
+                },
+                vips: Default::default(),
+            },
+            LocalWorkload {
+                workload: Workload {
+                    workload_ips: vec!["127.0.0.5".parse().unwrap()],
+                    protocol: Protocol::HBONE,
+                    native_hbone: true,
+                    name: "native".to_string(),
+                    namespace: "default".to_string(),
+                    ..test_default_workload()
+                },
+                vips: Default::default(),
+            },
+        ],
+        services: vec![],
+        policies: vec![],
+        peer_authentications: vec![],
+    };
+    config::Config {
+        local_xds_config: Some(config::ConfigSource::Static(
+            serde_yaml::to_string(&lc).unwrap().into(),
+        )),
+        ..test_config()
+    }
+}
+
+/// read_hbone_server_prefix asserts the stream reached the `HboneTestServer`, which writes its name first.
+async fn read_hbone_server_prefix<S: AsyncRead + Unpin>(stream: &mut S) {
+    let mut buf = [0; 9];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"waypoint\n", &buf);
+}
+
+// Both directions share the HboneTestServer, which always binds port 15008, so they run in one test.
+#[tokio::test]
+async fn test_native_hbone() {
+    let server = tcp::HboneTestServer::new(tcp::Mode::ReadWrite).await;
+    tokio::spawn(server.run());
+    testapp::with_app(native_hbone_config(), |app| async move {
+        let native_ip: IpAddr = "127.0.0.5".parse().unwrap();
+
+        // Outbound: the CONNECT is sent straight to the workload's own HBONE port
+        let mut stream = app.socks5_connect(SocketAddr::from((native_ip, 80))).await;
+        read_hbone_server_prefix(&mut stream).await;
+        read_write_stream(&mut stream).await;
+
+        // Inbound: HBONE captured for the workload is passed through, so mTLS terminates at the
+        // workload rather than at the proxy
+        let id = Identity::Spiffe {
+            trust_domain: "cluster.local".to_string(),
+            namespace: "default".to_string(),
+            service_account: "default".to_string(),
+        };
+        let cert = new_secret_manager(Duration::from_secs(10))
+            .fetch_certificate(&id)
+            .await
+            .unwrap();
+        let mut connector = cert.connector(Some(&id)).unwrap().configure().unwrap();
+        connector.set_verify_hostname(false);
+        connector.set_use_server_name_indication(false);
+        let tcp = TcpStream::connect(helpers::with_ip(app.proxy_addresses.inbound, native_ip))
+            .await
+            .unwrap();
+        let tls = tokio_boring::connect(connector, "", tcp).await.unwrap();
+        let (mut request_sender, connection) = hyper::client::conn::Builder::new()
+            .http2_only(true)
+            .handshake(tls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let req = Request::builder()
+            .uri(SocketAddr::from((native_ip, 80)).to_string())
+            .method(Method::CONNECT)
+            .version(hyper::Version::HTTP_2)
+            .body(Body::empty())
+            .unwrap();
+        let resp = request_sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        let mut upgraded = hyper::upgrade::on(resp).await.unwrap();
+        read_hbone_server_prefix(&mut upgraded).await;
+        read_write_stream(&mut upgraded).await;
+    })
+    .await;
+}
+
+async fn read_write_stream<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> usize {
     const BODY: &[u8] = b"hello world";
     stream.write_all(BODY).await.unwrap();
     let mut buf: [u8; BODY.len()] = [0; BODY.len()];
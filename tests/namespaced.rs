@@ -18,7 +18,7 @@ use std::time::Duration;
 
 use hyper::{Body, Method};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UdpSocket};
 use tokio::time::timeout;
 use tracing::{error, info};
 
@@ -127,6 +127,50 @@ async fn test_tcp_request() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_udp_request() -> anyhow::Result<()> {
+    let mut manager = setup_netns_test!();
+    run_udp_server(manager.workload_builder("server", REMOTE_NODE).register()?)?;
+    manager.deploy_ztunnel(REMOTE_NODE)?;
+    let client = manager
+        .workload_builder("client", DEFAULT_NODE)
+        .register()?;
+    let local = manager.deploy_ztunnel(DEFAULT_NODE)?;
+
+    let srv = resolve_target(manager.resolver(), "server");
+    client
+        .run(move || async move {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            // Once the flow has expired, the next datagram opens a new one
+            for _ in 0..2 {
+                info!("Running udp client to {srv}");
+                socket.send_to(b"hello world", srv).await?;
+                let mut buf = [0; 64];
+                let (n, from) = timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+                    .await
+                    .unwrap()?;
+                assert_eq!(from, srv);
+                assert_eq!(&buf[..n], b"hello world");
+                // Longer than the idle timeout of the ztunnel
+                tokio::time::sleep(Duration::from_secs(3)).await;
+            }
+            Ok(())
+        })?
+        .join()
+        .unwrap()?;
+    let labels = HashMap::from([
+        ("reporter".to_string(), "source".to_string()),
+        ("request_protocol".to_string(), "udp".to_string()),
+    ]);
+    let metrics = [
+        (CONNECTIONS_OPENED, 2),
+        (CONNECTIONS_CLOSED, 2),
+        (BYTES_SENT, REQ_SIZE * 2),
+    ];
+    verify_metrics(local, &metrics, &labels).await;
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_tcp_local_request() -> anyhow::Result<()> {
     let mut manager = setup_netns_test!();
@@ -495,6 +539,21 @@ fn run_tcp_server(server: Namespace) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// run_udp_server deploys a simple server in the provided namespace, which echoes datagrams
+fn run_udp_server(server: Namespace) -> anyhow::Result<()> {
+    server.run_ready(|ready| async move {
+        let socket = UdpSocket::bind(("0.0.0.0", SERVER_PORT)).await?;
+        info!("Running udp echo server");
+        ready.set_ready();
+        let mut buf = [0; 64];
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await?;
+            socket.send_to(&buf[..n], from).await?;
+        }
+    })?;
+    Ok(())
+}
+
 /// run_hbone_server deploys a simple echo server, deployed over HBONE, in the provided namespace
 fn run_hbone_server(server: Namespace) -> anyhow::Result<()> {
     server.run_ready(|ready| async move {